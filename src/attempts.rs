@@ -0,0 +1,396 @@
+use crate::process::find_scope_files;
+use crate::types::{AttemptKind, AttemptRecord, DiffStat};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Content of every file in a file's scope, keyed by path relative to the working
+/// directory (None if the file doesn't exist)
+pub type ScopeContents = BTreeMap<PathBuf, Option<Vec<u8>>>;
+
+/// Directory holding a task's per-attempt diffs and transcripts (next to its state file)
+pub fn artifacts_dir(state_path: &Path) -> PathBuf {
+    state_path.with_extension("")
+}
+
+/// Read the current content of the file's scope
+pub fn capture_scope(file_path: &Path, allowlist_pattern: &str, working_dir: &Path) -> ScopeContents {
+    find_scope_files(file_path, allowlist_pattern, working_dir)
+        .into_iter()
+        .map(|path| {
+            let content = fs::read(working_dir.join(&path)).ok();
+            (path, content)
+        })
+        .collect()
+}
+
+/// Re-read the scope after an agent run, including files that existed before but may be gone
+pub fn recapture_scope(
+    before: &ScopeContents,
+    file_path: &Path,
+    allowlist_pattern: &str,
+    working_dir: &Path,
+) -> ScopeContents {
+    let mut after = capture_scope(file_path, allowlist_pattern, working_dir);
+    for path in before.keys() {
+        after
+            .entry(path.clone())
+            .or_insert_with(|| fs::read(working_dir.join(path)).ok());
+    }
+    after
+}
+
+/// Paths whose content differs between two captures of the same scope
+pub fn changed_paths(before: &ScopeContents, after: &ScopeContents) -> Vec<PathBuf> {
+    after
+        .iter()
+        .filter(|(path, new)| before.get(*path).and_then(|old| old.as_ref()) != new.as_ref())
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Build a unified diff (and its diffstat) between two captures of the same scope
+pub fn diff_scope(before: &ScopeContents, after: &ScopeContents) -> (String, DiffStat) {
+    let mut diff = String::new();
+    let mut stat = DiffStat::default();
+
+    for (path, new) in after {
+        let old = before.get(path).cloned().flatten();
+        if old.as_ref() == new.as_ref() {
+            continue;
+        }
+        stat.files_changed += 1;
+
+        let name = path.display().to_string();
+        let (old_label, new_label) = match (&old, new) {
+            (None, _) => ("/dev/null".to_string(), format!("b/{}", name)),
+            (_, None) => (format!("a/{}", name), "/dev/null".to_string()),
+            _ => (format!("a/{}", name), format!("b/{}", name)),
+        };
+
+        let old_text = old.as_deref().map(std::str::from_utf8);
+        let new_text = new.as_deref().map(std::str::from_utf8);
+        match (old_text, new_text) {
+            (Some(Err(_)), _) | (_, Some(Err(_))) => {
+                diff.push_str(&format!("Binary files {} and {} differ\n", old_label, new_label));
+            }
+            (old_text, new_text) => {
+                let old_text = old_text.and_then(|r| r.ok()).unwrap_or("");
+                let new_text = new_text.and_then(|r| r.ok()).unwrap_or("");
+                let text_diff = TextDiff::from_lines(old_text, new_text);
+                for change in text_diff.iter_all_changes() {
+                    match change.tag() {
+                        ChangeTag::Insert => stat.insertions += 1,
+                        ChangeTag::Delete => stat.deletions += 1,
+                        ChangeTag::Equal => {}
+                    }
+                }
+                diff.push_str(
+                    &text_diff
+                        .unified_diff()
+                        .header(&old_label, &new_label)
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    (diff, stat)
+}
+
+/// Write an attempt's diff and transcript under the artifacts directory and
+/// return the record to store in the file's state
+pub fn write_attempt(
+    artifacts_dir: &Path,
+    file_path: &Path,
+    kind: AttemptKind,
+    number: u32,
+    diff: &str,
+    diffstat: DiffStat,
+    transcript: &str,
+) -> Result<AttemptRecord> {
+    let rel_dir = PathBuf::from("attempts").join(file_key(file_path));
+    let dir = artifacts_dir.join(&rel_dir);
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create attempts directory: {}", dir.display()))?;
+
+    let base = format!("{:02}-{}", number, kind.as_str());
+    let diff_file = rel_dir.join(format!("{}.diff", base));
+    let transcript_file = rel_dir.join(format!("{}.transcript.txt", base));
+
+    fs::write(artifacts_dir.join(&diff_file), diff)
+        .with_context(|| format!("Failed to write diff: {}", diff_file.display()))?;
+    fs::write(artifacts_dir.join(&transcript_file), transcript)
+        .with_context(|| format!("Failed to write transcript: {}", transcript_file.display()))?;
+
+    Ok(AttemptRecord {
+        number,
+        kind,
+        finished_at: Utc::now(),
+        diffstat,
+        changed_files: Vec::new(),
+        diff_file: Some(diff_file),
+        transcript_file: Some(transcript_file),
+    })
+}
+
+/// Keep the scope as it was before the file's first agent run, so a fresh start can
+/// revert to it. Does nothing if a snapshot was already saved.
+pub fn save_original_scope(artifacts_dir: &Path, file_path: &Path, scope: &ScopeContents) -> Result<()> {
+    let dir = artifacts_dir.join("attempts").join(file_key(file_path));
+    if dir.join("original.json").exists() {
+        return Ok(());
+    }
+    save_scope(&dir, "original", scope)
+}
+
+/// Read the snapshot taken before the file's first agent run, if one was saved
+pub fn load_original_scope(artifacts_dir: &Path, file_path: &Path) -> Result<Option<ScopeContents>> {
+    load_scope(&artifacts_dir.join("attempts").join(file_key(file_path)), "original")
+}
+
+/// Put the file's scope back to the snapshot taken before its first agent run, removing
+/// files created since. Returns the restored paths, or None if there is no snapshot.
+pub fn restore_original_scope(
+    artifacts_dir: &Path,
+    file_path: &Path,
+    allowlist_pattern: &str,
+    working_dir: &Path,
+) -> Result<Option<Vec<PathBuf>>> {
+    let Some(original) = load_original_scope(artifacts_dir, file_path)? else {
+        return Ok(None);
+    };
+    restore_scope(&original, file_path, allowlist_pattern, working_dir).map(Some)
+}
+
+/// Save the file's scope before an agent run, replacing the last run's snapshot
+pub fn save_inflight_scope(artifacts_dir: &Path, file_path: &Path, scope: &ScopeContents) -> Result<()> {
+    let dir = artifacts_dir.join("attempts").join(file_key(file_path));
+    clear_inflight_scope(artifacts_dir, file_path)?;
+    save_scope(&dir, "inflight", scope)
+}
+
+/// Drop the snapshot of a finished agent run
+pub fn clear_inflight_scope(artifacts_dir: &Path, file_path: &Path) -> Result<()> {
+    let dir = artifacts_dir.join("attempts").join(file_key(file_path));
+    let manifest_path = dir.join("inflight.json");
+    if manifest_path.exists() {
+        fs::remove_file(&manifest_path)
+            .with_context(|| format!("Failed to remove snapshot manifest: {}", manifest_path.display()))?;
+    }
+    let files_dir = dir.join("inflight");
+    if files_dir.exists() {
+        fs::remove_dir_all(&files_dir)
+            .with_context(|| format!("Failed to remove snapshot: {}", files_dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Undo the partial edits of an agent run that never finished (the runner crashed or was
+/// killed). Returns the restored paths, or None if no run was in flight.
+pub fn restore_inflight_scope(
+    artifacts_dir: &Path,
+    file_path: &Path,
+    allowlist_pattern: &str,
+    working_dir: &Path,
+) -> Result<Option<Vec<PathBuf>>> {
+    let dir = artifacts_dir.join("attempts").join(file_key(file_path));
+    let Some(before) = load_scope(&dir, "inflight")? else {
+        return Ok(None);
+    };
+    restore_scope(&before, file_path, allowlist_pattern, working_dir).map(Some)
+}
+
+/// Write a scope snapshot as `<name>/<path>` files plus a `<name>.json` manifest of which
+/// files existed
+fn save_scope(dir: &Path, name: &str, scope: &ScopeContents) -> Result<()> {
+    let files_dir = dir.join(name);
+    let mut manifest = BTreeMap::new();
+    for (path, content) in scope {
+        if let Some(content) = content {
+            let dest = files_dir.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).with_context(|| {
+                    format!("Failed to create snapshot directory: {}", parent.display())
+                })?;
+            }
+            fs::write(&dest, content)
+                .with_context(|| format!("Failed to write snapshot: {}", dest.display()))?;
+        }
+        manifest.insert(path.clone(), content.is_some());
+    }
+
+    // Written last so a partial snapshot is never treated as complete
+    let manifest_path = dir.join(format!("{}.json", name));
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create attempts directory: {}", dir.display()))?;
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write snapshot manifest: {}", manifest_path.display()))
+}
+
+fn load_scope(dir: &Path, name: &str) -> Result<Option<ScopeContents>> {
+    let manifest_path = dir.join(format!("{}.json", name));
+    if !manifest_path.exists() {
+        return Ok(None);
+    }
+    let manifest: BTreeMap<PathBuf, bool> = serde_json::from_str(
+        &fs::read_to_string(&manifest_path)
+            .with_context(|| format!("Failed to read snapshot manifest: {}", manifest_path.display()))?,
+    )
+    .with_context(|| format!("Failed to parse snapshot manifest: {}", manifest_path.display()))?;
+
+    let mut scope = ScopeContents::new();
+    for (path, existed) in &manifest {
+        let content = if *existed {
+            let src = dir.join(name).join(path);
+            Some(fs::read(&src).with_context(|| format!("Failed to read snapshot: {}", src.display()))?)
+        } else {
+            None
+        };
+        scope.insert(path.clone(), content);
+    }
+    Ok(Some(scope))
+}
+
+/// Write a snapshot back over the scope, removing files it didn't have
+fn restore_scope(
+    snapshot: &ScopeContents,
+    file_path: &Path,
+    allowlist_pattern: &str,
+    working_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let current = recapture_scope(snapshot, file_path, allowlist_pattern, working_dir);
+    let mut restored = Vec::new();
+    for (path, content) in &current {
+        let wanted = snapshot.get(path).cloned().flatten();
+        if &wanted == content {
+            continue;
+        }
+        let dest = working_dir.join(path);
+        match wanted {
+            Some(wanted) => fs::write(&dest, wanted)
+                .with_context(|| format!("Failed to restore file: {}", dest.display()))?,
+            None => fs::remove_file(&dest)
+                .with_context(|| format!("Failed to remove file: {}", dest.display()))?,
+        }
+        restored.push(path.clone());
+    }
+    Ok(restored)
+}
+
+/// Flatten a file path into a single directory name (`src/a/b.ts` -> `src__a__b.ts`)
+pub fn file_key(file_path: &Path) -> String {
+    file_path
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_scope_counts_changes() {
+        let mut before = ScopeContents::new();
+        before.insert(PathBuf::from("src/a.ts"), Some(b"one\ntwo\nthree\n".to_vec()));
+        before.insert(PathBuf::from("src/a.test.ts"), None);
+        before.insert(PathBuf::from("src/untouched.ts"), Some(b"same\n".to_vec()));
+
+        let mut after = before.clone();
+        after.insert(PathBuf::from("src/a.ts"), Some(b"one\n2\nthree\n".to_vec()));
+        after.insert(PathBuf::from("src/a.test.ts"), Some(b"test\nmore\n".to_vec()));
+
+        let (diff, stat) = diff_scope(&before, &after);
+        assert_eq!(
+            stat,
+            DiffStat {
+                files_changed: 2,
+                insertions: 3,
+                deletions: 1,
+            }
+        );
+        assert!(diff.contains("--- /dev/null\n+++ b/src/a.test.ts"));
+        assert!(diff.contains("-two\n+2\n"));
+        assert!(!diff.contains("untouched"));
+    }
+
+    #[test]
+    fn test_total_diffstat_counts_each_file_once() {
+        let mut before = ScopeContents::new();
+        before.insert(PathBuf::from("src/a.ts"), Some(b"one\n".to_vec()));
+        before.insert(PathBuf::from("src/a.test.ts"), None);
+        let mut first = before.clone();
+        first.insert(PathBuf::from("src/a.ts"), Some(b"two\n".to_vec()));
+        let mut second = first.clone();
+        second.insert(PathBuf::from("src/a.ts"), Some(b"three\n".to_vec()));
+        second.insert(PathBuf::from("src/a.test.ts"), Some(b"test\n".to_vec()));
+
+        let mut file = crate::types::FileState::new(serde_json::Value::Null);
+        for (number, (from, to)) in [(&before, &first), (&first, &second)].into_iter().enumerate() {
+            let (_, diffstat) = diff_scope(from, to);
+            file.attempt_log.push(AttemptRecord {
+                number: number as u32 + 1,
+                kind: AttemptKind::Prompt,
+                finished_at: Utc::now(),
+                diffstat,
+                changed_files: changed_paths(from, to),
+                diff_file: None,
+                transcript_file: None,
+            });
+        }
+
+        assert_eq!(
+            file.total_diffstat(),
+            DiffStat {
+                files_changed: 2,
+                insertions: 3,
+                deletions: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_restore_original_scope() {
+        let root = std::env::temp_dir().join(format!("clr-restore-{}", std::process::id()));
+        let working_dir = root.join("work");
+        let artifacts_dir = root.join("artifacts");
+        fs::create_dir_all(working_dir.join("src")).unwrap();
+        fs::write(working_dir.join("src/a.ts"), "original\n").unwrap();
+
+        let file = Path::new("src/a.ts");
+        let scope = capture_scope(file, "{file_stem}*", &working_dir);
+        save_original_scope(&artifacts_dir, file, &scope).unwrap();
+
+        fs::write(working_dir.join("src/a.ts"), "edited\n").unwrap();
+        fs::write(working_dir.join("src/a.test.ts"), "new\n").unwrap();
+        // A later save doesn't replace the first snapshot
+        let edited = capture_scope(file, "{file_stem}*", &working_dir);
+        save_original_scope(&artifacts_dir, file, &edited).unwrap();
+
+        let restored = restore_original_scope(&artifacts_dir, file, "{file_stem}*", &working_dir)
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs::read_to_string(working_dir.join("src/a.ts")).unwrap(), "original\n");
+        assert!(!working_dir.join("src/a.test.ts").exists());
+        assert!(restore_original_scope(&artifacts_dir, Path::new("src/b.ts"), "{file_stem}*", &working_dir)
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_key() {
+        assert_eq!(file_key(Path::new("src/utils/parser.ts")), "src__utils__parser.ts");
+        assert_eq!(file_key(Path::new("./parser.ts")), "parser.ts");
+    }
+}
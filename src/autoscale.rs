@@ -0,0 +1,290 @@
+use crate::control::ControlHandle;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sysinfo::System;
+use tracing::{debug, info};
+
+/// Verify runs used to establish the baseline latency
+const BASELINE_SAMPLES: usize = 5;
+/// Recent verify runs compared against the baseline
+const RECENT_SAMPLES: usize = 10;
+
+/// Thresholds for the autoscaler. Above a `high` value the pools shrink;
+/// below every `low` value (and with no timeouts) they grow.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub memory_high: f64,
+    pub memory_low: f64,
+    /// 1-minute load average divided by CPU count
+    pub load_high: f64,
+    pub load_low: f64,
+    /// Recent verify latency divided by the baseline
+    pub latency_high: f64,
+    pub latency_low: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        // Stays below the memory monitor's 85% pause threshold
+        Self {
+            memory_high: 80.0,
+            memory_low: 65.0,
+            load_high: 1.5,
+            load_low: 0.8,
+            latency_high: 2.0,
+            latency_low: 1.3,
+        }
+    }
+}
+
+/// Resource and failure signals sampled on each tick
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Signals {
+    pub memory_percent: f64,
+    pub load_per_cpu: f64,
+    /// None until enough verify runs have finished to compare against the baseline
+    pub latency_ratio: Option<f64>,
+    /// Verify runs that timed out since the last tick
+    pub verify_timeouts: usize,
+}
+
+/// Active worker counts for both pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Levels {
+    pub prompt: usize,
+    pub verify: usize,
+}
+
+/// Decide the next pool sizes. Steps by one worker per tick in either direction,
+/// staying within `1..=max`.
+pub fn decide(signals: &Signals, current: Levels, max: Levels, thresholds: &Thresholds) -> Levels {
+    let memory_pressure = signals.memory_percent > thresholds.memory_high;
+    let overloaded = signals.load_per_cpu > thresholds.load_high;
+    let verify_slow = signals.verify_timeouts > 0
+        || signals
+            .latency_ratio
+            .is_some_and(|r| r > thresholds.latency_high);
+
+    if memory_pressure || overloaded || verify_slow {
+        // Verification is what's struggling on slow/timeouts; everything shrinks on resource pressure
+        let shrink_prompt = memory_pressure || overloaded;
+        return Levels {
+            prompt: if shrink_prompt {
+                current.prompt.saturating_sub(1).max(1)
+            } else {
+                current.prompt
+            },
+            verify: current.verify.saturating_sub(1).max(1),
+        };
+    }
+
+    let healthy = signals.memory_percent < thresholds.memory_low
+        && signals.load_per_cpu < thresholds.load_low
+        && signals
+            .latency_ratio
+            .is_none_or(|r| r < thresholds.latency_low);
+
+    if healthy {
+        Levels {
+            prompt: (current.prompt + 1).min(max.prompt),
+            verify: (current.verify + 1).min(max.verify),
+        }
+    } else {
+        current
+    }
+}
+
+/// Verify latencies and timeouts reported by verify workers
+#[derive(Clone, Default)]
+pub struct VerifyStats {
+    inner: Arc<Mutex<VerifyStatsInner>>,
+}
+
+#[derive(Default)]
+struct VerifyStatsInner {
+    baseline: Vec<Duration>,
+    recent: VecDeque<Duration>,
+    timeouts: usize,
+}
+
+impl VerifyStats {
+    /// Record a finished verify run
+    pub fn record(&self, elapsed: Duration, timed_out: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if timed_out {
+            inner.timeouts += 1;
+            return;
+        }
+        if inner.baseline.len() < BASELINE_SAMPLES {
+            inner.baseline.push(elapsed);
+        }
+        inner.recent.push_back(elapsed);
+        if inner.recent.len() > RECENT_SAMPLES {
+            inner.recent.pop_front();
+        }
+    }
+
+    /// Recent average latency relative to the baseline, and timeouts since the last call
+    fn take_signals(&self) -> (Option<f64>, usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let timeouts = std::mem::take(&mut inner.timeouts);
+        if inner.baseline.len() < BASELINE_SAMPLES {
+            return (None, timeouts);
+        }
+        let average = |samples: &mut dyn Iterator<Item = &Duration>, n: usize| {
+            samples.map(Duration::as_secs_f64).sum::<f64>() / n as f64
+        };
+        let baseline = average(&mut inner.baseline.iter(), inner.baseline.len());
+        let recent = average(&mut inner.recent.iter(), inner.recent.len());
+        let ratio = if baseline > 0.0 { recent / baseline } else { 1.0 };
+        (Some(ratio), timeouts)
+    }
+}
+
+/// Periodically sample signals and resize the pools through the control gates, up to
+/// `max` workers each (bounded by the gates' ceilings)
+pub fn spawn_autoscaler(
+    control: ControlHandle,
+    stats: VerifyStats,
+    max: Levels,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let thresholds = Thresholds::default();
+    let max = Levels {
+        prompt: max.prompt.min(control.prompt_gate.ceiling()),
+        verify: max.verify.min(control.verify_gate.ceiling()),
+    };
+
+    tokio::spawn(async move {
+        let mut sys = System::new();
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1) as f64;
+
+        loop {
+            tokio::time::sleep(interval).await;
+
+            sys.refresh_memory();
+            let (latency_ratio, verify_timeouts) = stats.take_signals();
+            let signals = Signals {
+                memory_percent: sys.used_memory() as f64 / sys.total_memory().max(1) as f64
+                    * 100.0,
+                load_per_cpu: System::load_average().one / cpus,
+                latency_ratio,
+                verify_timeouts,
+            };
+
+            let current = Levels {
+                prompt: control.prompt_gate.limit(),
+                verify: control.verify_gate.limit(),
+            };
+            let next = decide(&signals, current, max, &thresholds);
+
+            if next != current {
+                control.prompt_gate.set_limit(next.prompt);
+                control.verify_gate.set_limit(next.verify);
+                info!(
+                    prompt_concurrency = next.prompt,
+                    verify_concurrency = next.verify,
+                    memory_percent = format!("{:.1}", signals.memory_percent),
+                    load_per_cpu = format!("{:.2}", signals.load_per_cpu),
+                    verify_latency_ratio = ?signals.latency_ratio.map(|r| format!("{:.2}", r)),
+                    verify_timeouts = signals.verify_timeouts,
+                    "Adjusted concurrency"
+                );
+            } else {
+                debug!(
+                    prompt_concurrency = current.prompt,
+                    verify_concurrency = current.verify,
+                    memory_percent = format!("{:.1}", signals.memory_percent),
+                    load_per_cpu = format!("{:.2}", signals.load_per_cpu),
+                    "Concurrency unchanged"
+                );
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: Levels = Levels {
+        prompt: 8,
+        verify: 8,
+    };
+
+    fn healthy() -> Signals {
+        Signals {
+            memory_percent: 40.0,
+            load_per_cpu: 0.3,
+            latency_ratio: Some(1.0),
+            verify_timeouts: 0,
+        }
+    }
+
+    #[test]
+    fn test_decide_grows_when_healthy_up_to_max() {
+        let t = Thresholds::default();
+        let current = Levels { prompt: 2, verify: 8 };
+        assert_eq!(decide(&healthy(), current, MAX, &t), Levels { prompt: 3, verify: 8 });
+
+        // Warming up: no latency baseline yet still counts as healthy
+        let signals = Signals {
+            latency_ratio: None,
+            ..healthy()
+        };
+        assert_eq!(decide(&signals, current, MAX, &t), Levels { prompt: 3, verify: 8 });
+    }
+
+    #[test]
+    fn test_decide_shrinks_on_pressure() {
+        let t = Thresholds::default();
+        let current = Levels { prompt: 4, verify: 1 };
+
+        let memory = Signals {
+            memory_percent: 90.0,
+            ..healthy()
+        };
+        assert_eq!(decide(&memory, current, MAX, &t), Levels { prompt: 3, verify: 1 });
+
+        // Timeouts only shrink the verify pool
+        let current = Levels { prompt: 4, verify: 4 };
+        let timeouts = Signals {
+            verify_timeouts: 2,
+            ..healthy()
+        };
+        assert_eq!(decide(&timeouts, current, MAX, &t), Levels { prompt: 4, verify: 3 });
+    }
+
+    #[test]
+    fn test_decide_holds_between_thresholds() {
+        let t = Thresholds::default();
+        let current = Levels { prompt: 4, verify: 4 };
+        let signals = Signals {
+            memory_percent: 72.0,
+            ..healthy()
+        };
+        assert_eq!(decide(&signals, current, MAX, &t), current);
+    }
+
+    #[test]
+    fn test_verify_stats_latency_ratio() {
+        let stats = VerifyStats::default();
+        for _ in 0..BASELINE_SAMPLES {
+            stats.record(Duration::from_secs(2), false);
+        }
+        stats.record(Duration::from_secs(1), true);
+        let (ratio, timeouts) = stats.take_signals();
+        assert_eq!(ratio, Some(1.0));
+        assert_eq!(timeouts, 1);
+
+        for _ in 0..RECENT_SAMPLES {
+            stats.record(Duration::from_secs(6), false);
+        }
+        let (ratio, timeouts) = stats.take_signals();
+        assert_eq!(ratio, Some(3.0));
+        assert_eq!(timeouts, 0);
+    }
+}
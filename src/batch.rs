@@ -0,0 +1,129 @@
+use crate::config::BatchConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How files are grouped into batches for a single prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchBy {
+    /// Files in the same directory, up to the batch size
+    #[default]
+    Dir,
+    /// Consecutive files until the batch size or byte budget is reached
+    Size,
+}
+
+/// Group files (already in queue order) into prompt batches.
+/// Batches keep the queue order of their first file; a file larger than the byte
+/// budget gets a batch of its own.
+pub fn group_batches(
+    paths: Vec<PathBuf>,
+    config: &BatchConfig,
+    file_size: impl Fn(&Path) -> u64,
+) -> Vec<Vec<PathBuf>> {
+    let max_files = config.size.max(1);
+    if max_files == 1 {
+        return paths.into_iter().map(|p| vec![p]).collect();
+    }
+
+    match config.by {
+        BatchBy::Dir => {
+            let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+            for path in paths {
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                match groups.iter_mut().find(|(d, _)| *d == dir) {
+                    Some((_, files)) => files.push(path),
+                    None => groups.push((dir, vec![path])),
+                }
+            }
+            groups
+                .into_iter()
+                .flat_map(|(_, files)| {
+                    files
+                        .chunks(max_files)
+                        .map(<[PathBuf]>::to_vec)
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        }
+        BatchBy::Size => {
+            let mut batches = Vec::new();
+            let mut current: Vec<PathBuf> = Vec::new();
+            let mut bytes = 0;
+            for path in paths {
+                let size = file_size(&path);
+                if !current.is_empty()
+                    && (current.len() >= max_files || bytes + size > config.max_bytes)
+                {
+                    batches.push(std::mem::take(&mut current));
+                    bytes = 0;
+                }
+                bytes += size;
+                current.push(path);
+            }
+            if !current.is_empty() {
+                batches.push(current);
+            }
+            batches
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_group_by_dir() {
+        let config = BatchConfig {
+            size: 2,
+            by: BatchBy::Dir,
+            ..Default::default()
+        };
+        let batches = group_batches(
+            paths(&["a/1.ts", "b/1.ts", "a/2.ts", "a/3.ts", "b/2.ts"]),
+            &config,
+            |_| 0,
+        );
+        assert_eq!(
+            batches,
+            vec![
+                paths(&["a/1.ts", "a/2.ts"]),
+                paths(&["a/3.ts"]),
+                paths(&["b/1.ts", "b/2.ts"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_by_size_budget() {
+        let config = BatchConfig {
+            size: 3,
+            by: BatchBy::Size,
+            max_bytes: 100,
+        };
+        let size = |p: &Path| match p.to_str().unwrap() {
+            "big.ts" => 500,
+            _ => 40,
+        };
+        let batches = group_batches(
+            paths(&["a.ts", "b.ts", "c.ts", "big.ts", "d.ts", "e.ts", "f.ts", "g.ts"]),
+            &config,
+            size,
+        );
+        assert_eq!(
+            batches,
+            vec![
+                paths(&["a.ts", "b.ts"]),
+                paths(&["c.ts"]),
+                paths(&["big.ts"]),
+                paths(&["d.ts", "e.ts"]),
+                paths(&["f.ts", "g.ts"]),
+            ]
+        );
+    }
+}
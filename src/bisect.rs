@@ -0,0 +1,101 @@
+use std::future::Future;
+
+/// Narrow a failing batch down to the items that fail on their own.
+///
+/// `failure` is the output of the failed check over all of `items`. Each failing set is
+/// split in half and both halves are checked; halves that pass are cleared. When both
+/// halves pass, the failure only shows up in combination, so every item in the set is
+/// reported with the set's output.
+///
+/// Returns the failing items with the output that implicated them, and the passing items.
+pub async fn bisect<T, O, F, Fut>(items: Vec<T>, failure: O, mut check: F) -> (Vec<(T, O)>, Vec<T>)
+where
+    T: Clone,
+    O: Clone,
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<(), O>>,
+{
+    let mut failing = Vec::new();
+    let mut passing = Vec::new();
+    let mut suspects = vec![(items, failure)];
+
+    while let Some((mut items, failure)) = suspects.pop() {
+        if items.len() <= 1 {
+            failing.extend(items.into_iter().map(|item| (item, failure.clone())));
+            continue;
+        }
+
+        let right = items.split_off(items.len() / 2);
+        let left = items;
+        let left_result = check(left.clone()).await;
+        let right_result = check(right.clone()).await;
+
+        match (left_result, right_result) {
+            (Ok(()), Ok(())) => {
+                failing.extend(left.into_iter().map(|item| (item, failure.clone())));
+                failing.extend(right.into_iter().map(|item| (item, failure.clone())));
+            }
+            (left_result, right_result) => {
+                // Checked right after left, so push right first to keep input order
+                for (half, result) in [(right, right_result), (left, left_result)] {
+                    match result {
+                        Ok(()) => passing.extend(half),
+                        Err(output) => suspects.push((half, output)),
+                    }
+                }
+            }
+        }
+    }
+
+    (failing, passing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bisect_finds_culprits() {
+        let mut checks = 0;
+        let (failing, mut passing) = bisect(
+            (1..=8).collect(),
+            "batch".to_string(),
+            |items: Vec<u32>| {
+                checks += 1;
+                async move {
+                    let bad: Vec<_> = items.iter().filter(|i| **i == 3 || **i == 6).collect();
+                    if bad.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(format!("{:?}", bad))
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(
+            failing,
+            vec![(3, "[3]".to_string()), (6, "[6]".to_string())]
+        );
+        passing.sort();
+        assert_eq!(passing, vec![1, 2, 4, 5, 7, 8]);
+        assert_eq!(checks, 10);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_combination_failure_implicates_whole_set() {
+        // Fails only when 1 and 4 are checked together
+        let (failing, passing) = bisect(vec![1, 2, 3, 4], "both", |items: Vec<u32>| async move {
+            if items.contains(&1) && items.contains(&4) {
+                Err("both")
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+
+        assert_eq!(failing.into_iter().map(|(i, _)| i).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert!(passing.is_empty());
+    }
+}
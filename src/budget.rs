@@ -0,0 +1,178 @@
+use crate::config::{BudgetConfig, BudgetLimits, BudgetPolicy};
+use crate::types::UsageTotals;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Describe the first limit `usage` has reached, if any
+pub fn exceeded(limits: &BudgetLimits, usage: &UsageTotals, wall_secs: f64) -> Option<String> {
+    if let Some(max) = limits.max_cost_usd {
+        if usage.cost_usd >= max {
+            return Some(format!("cost ${:.2} reached limit ${:.2}", usage.cost_usd, max));
+        }
+    }
+    if let Some(max) = limits.max_tokens {
+        if usage.tokens() >= max {
+            return Some(format!("{} tokens reached limit {}", usage.tokens(), max));
+        }
+    }
+    if let Some(max) = limits.max_wall_secs {
+        if wall_secs >= max as f64 {
+            return Some(format!("{:.0}s wall time reached limit {}s", wall_secs, max));
+        }
+    }
+    if let Some(max) = limits.max_agent_calls {
+        if usage.agent_calls >= max {
+            return Some(format!("{} agent calls reached limit {}", usage.agent_calls, max));
+        }
+    }
+    None
+}
+
+/// Cheaply cloneable handle tracking whether the task budget has run out
+#[derive(Clone)]
+pub struct BudgetHandle {
+    config: Arc<BudgetConfig>,
+    exhausted: Arc<AtomicBool>,
+    abort_notify: Arc<Notify>,
+    reason: Arc<Mutex<Option<String>>>,
+    started: Instant,
+    /// Task wall time from previous runs
+    previous_wall_secs: f64,
+}
+
+impl BudgetHandle {
+    pub fn new(config: BudgetConfig, previous_wall_secs: f64) -> Self {
+        Self {
+            config: Arc::new(config),
+            exhausted: Arc::new(AtomicBool::new(false)),
+            abort_notify: Arc::new(Notify::new()),
+            reason: Arc::new(Mutex::new(None)),
+            started: Instant::now(),
+            previous_wall_secs,
+        }
+    }
+
+    /// Seconds since this run started
+    pub fn run_secs(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// Check if the task budget has run out (no new prompts or fixups should start)
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::SeqCst)
+    }
+
+    /// Check if in-flight work should stop now (budget exhausted under the abort policy)
+    pub fn is_aborting(&self) -> bool {
+        self.is_exhausted() && self.config.policy == BudgetPolicy::Abort
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    /// Check the task's usage against its budget, tripping the handle if it's used up.
+    /// Returns true if the budget is exhausted.
+    pub fn check_task(&self, usage: &UsageTotals) -> bool {
+        if self.is_exhausted() {
+            return true;
+        }
+        let wall_secs = self.previous_wall_secs + self.run_secs();
+        let Some(reason) = exceeded(&self.config.task, usage, wall_secs) else {
+            return false;
+        };
+
+        warn!(reason = %reason, policy = ?self.config.policy, "Task budget exhausted, no new work will start");
+        *self.reason.lock().unwrap() = Some(reason);
+        self.exhausted.store(true, Ordering::SeqCst);
+        if self.config.policy == BudgetPolicy::Abort {
+            self.abort_notify.notify_waiters();
+        }
+        true
+    }
+
+    /// Check a file's usage against the per-file budget
+    pub fn check_file(&self, usage: &UsageTotals) -> Option<String> {
+        exceeded(&self.config.file, usage, usage.wall_secs)
+    }
+
+    /// Resolves once the budget is exhausted under the abort policy; never resolves otherwise
+    pub async fn aborted(&self) {
+        if self.config.policy != BudgetPolicy::Abort {
+            return std::future::pending().await;
+        }
+        loop {
+            let notified = self.abort_notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_exhausted() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeded_reports_first_limit_hit() {
+        let limits = BudgetLimits {
+            max_cost_usd: Some(5.0),
+            max_tokens: Some(1000),
+            max_wall_secs: None,
+            max_agent_calls: Some(10),
+        };
+        let mut usage = UsageTotals {
+            cost_usd: 1.0,
+            input_tokens: 400,
+            output_tokens: 100,
+            agent_calls: 3,
+            wall_secs: 0.0,
+        };
+        assert_eq!(exceeded(&limits, &usage, 1e6), None);
+
+        usage.output_tokens = 600;
+        assert_eq!(
+            exceeded(&limits, &usage, 0.0).as_deref(),
+            Some("1000 tokens reached limit 1000")
+        );
+        assert_eq!(exceeded(&BudgetLimits::default(), &usage, 1e6), None);
+    }
+
+    #[tokio::test]
+    async fn test_abort_policy_wakes_waiters() {
+        let config = BudgetConfig {
+            task: BudgetLimits {
+                max_agent_calls: Some(2),
+                ..Default::default()
+            },
+            policy: BudgetPolicy::Abort,
+            ..Default::default()
+        };
+        let handle = BudgetHandle::new(config, 0.0);
+        let waiter = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.aborted().await }
+        });
+
+        let mut usage = UsageTotals {
+            agent_calls: 1,
+            ..Default::default()
+        };
+        assert!(!handle.check_task(&usage));
+        usage.agent_calls = 2;
+        assert!(handle.check_task(&usage));
+        assert!(handle.reason().unwrap().contains("agent calls"));
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+//! Portable task bundles: a task's state, transcripts, diffs and failure logs in one
+//! tar.gz with paths relative to the task, for moving a run to another machine or
+//! archiving it
+
+use crate::attempts::artifacts_dir;
+use crate::snapshot::snapshot_path;
+use crate::state::State;
+use crate::task_list::TaskList;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Format of the bundle layout
+const BUNDLE_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const STATE: &str = "state.json";
+const SNAPSHOT: &str = "snapshot.json";
+const ARTIFACTS: &str = "artifacts";
+
+/// Description of a bundled task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub bundle_version: u32,
+    /// Task ID on the machine it was packed on
+    pub task_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub completed: bool,
+    /// Working directory the task ran in, for reference (unpacking picks a new one)
+    pub working_dir: PathBuf,
+    pub packed_at: DateTime<Utc>,
+}
+
+/// Bundle a task into a tar.gz at `output`. Returns the manifest written.
+pub fn pack(tasks_dir: &Path, task_id: &str, output: &Path) -> Result<Manifest> {
+    let task_list = TaskList::load_or_create(tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+    let state_path = tasks_dir.join(&entry.state_file);
+    // Loading replays the journal, so the bundle holds a single compacted state file
+    let mut state = State::load(&state_path)
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+    let input_file = relative_to(&state.config.input_file, &entry.working_dir);
+    state.config.input_file = input_file;
+    // Leases and process IDs belong to runners on this machine
+    for file in state.files.values_mut().chain(state.archived.values_mut()) {
+        file.lease = None;
+        file.process = None;
+    }
+
+    let manifest = Manifest {
+        bundle_version: BUNDLE_VERSION,
+        task_id: task_id.to_string(),
+        description: entry.description.clone(),
+        completed: entry.completed,
+        working_dir: entry.working_dir.clone(),
+        packed_at: Utc::now(),
+    };
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create bundle: {}", output.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    append_bytes(
+        &mut archive,
+        MANIFEST,
+        &serde_json::to_vec_pretty(&manifest).context("Failed to serialize manifest")?,
+    )?;
+    append_bytes(
+        &mut archive,
+        STATE,
+        &serde_json::to_vec_pretty(&state).context("Failed to serialize state")?,
+    )?;
+
+    let snapshot = snapshot_path(&state_path);
+    if snapshot.exists() {
+        archive
+            .append_path_with_name(&snapshot, SNAPSHOT)
+            .with_context(|| format!("Failed to add snapshot: {}", snapshot.display()))?;
+    }
+    // Transcripts, diffs and the task's failure logs
+    let artifacts = artifacts_dir(&state_path);
+    if artifacts.is_dir() {
+        archive
+            .append_dir_all(ARTIFACTS, &artifacts)
+            .with_context(|| format!("Failed to add artifacts: {}", artifacts.display()))?;
+    }
+
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .with_context(|| format!("Failed to write bundle: {}", output.display()))?;
+    Ok(manifest)
+}
+
+/// Unpack a bundle as a new task in `tasks_dir` running in `working_dir`. Returns the
+/// new task's ID and the manifest.
+pub fn unpack(bundle: &Path, tasks_dir: &Path, working_dir: &Path) -> Result<(String, Manifest)> {
+    let mut manifest = None;
+    let mut state = None;
+    let mut snapshot = None;
+    let mut artifacts = Vec::new();
+
+    let file = File::open(bundle)
+        .with_context(|| format!("Failed to open bundle: {}", bundle.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Failed to read bundle: {}", bundle.display()))?;
+    for entry in entries {
+        let mut entry =
+            entry.with_context(|| format!("Failed to read bundle: {}", bundle.display()))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().context("Bad path in bundle")?.into_owned();
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .with_context(|| format!("Failed to read {} from bundle", name.display()))?;
+
+        if name == Path::new(MANIFEST) {
+            manifest = Some(content);
+        } else if name == Path::new(STATE) {
+            state = Some(content);
+        } else if name == Path::new(SNAPSHOT) {
+            snapshot = Some(content);
+        } else if let Some(path) = bundled_path(&name, ARTIFACTS) {
+            artifacts.push((path, content));
+        }
+    }
+
+    let manifest: Manifest = serde_json::from_slice(
+        &manifest.ok_or_else(|| anyhow::anyhow!("Not a task bundle (no {})", MANIFEST))?,
+    )
+    .context("Failed to parse bundle manifest")?;
+    if manifest.bundle_version > BUNDLE_VERSION {
+        anyhow::bail!(
+            "Bundle format {} is newer than this build supports ({}); upgrade claude-loop-runner",
+            manifest.bundle_version,
+            BUNDLE_VERSION
+        );
+    }
+    let state = state.ok_or_else(|| anyhow::anyhow!("Bundle has no {}", STATE))?;
+
+    let mut task_list = TaskList::load_or_create(tasks_dir)?;
+    let task_id = task_list.create_task(working_dir.to_path_buf(), manifest.description.clone());
+    if manifest.completed {
+        task_list.mark_completed(&task_id);
+    }
+    let state_path = tasks_dir.join(&task_list.get_task(&task_id).unwrap().state_file);
+    if let Some(parent) = state_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    // Written as-is and loaded back, so older state formats go through the usual migration
+    fs::write(&state_path, &state)
+        .with_context(|| format!("Failed to write state file: {}", state_path.display()))?;
+    let mut loaded = State::load(&state_path)
+        .with_context(|| format!("Failed to load state from bundle: {}", bundle.display()))?;
+    // Packing made the input file relative to the old working directory
+    if loaded.config.input_file.is_relative() {
+        loaded.config.input_file = working_dir.join(&loaded.config.input_file);
+    }
+    loaded
+        .compact(&state_path)
+        .context("Failed to save unpacked state")?;
+
+    if let Some(snapshot) = snapshot {
+        let path = snapshot_path(&state_path);
+        fs::write(&path, snapshot)
+            .with_context(|| format!("Failed to write snapshot: {}", path.display()))?;
+    }
+    let artifacts_root = artifacts_dir(&state_path);
+    for (path, content) in artifacts {
+        write_file(&artifacts_root.join(path), &content)?;
+    }
+
+    task_list.save(tasks_dir)?;
+    Ok((task_id, manifest))
+}
+
+/// `path` relative to `base` if it lies inside it, otherwise unchanged
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    path.strip_prefix(base)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Path of a bundle entry under `dir`, if it is there and can't escape it
+fn bundled_path(name: &Path, dir: &str) -> Option<PathBuf> {
+    let path = name.strip_prefix(dir).ok()?;
+    let safe = path.components().all(|c| matches!(c, Component::Normal(_)));
+    (safe && path.components().next().is_some()).then(|| path.to_path_buf())
+}
+
+fn append_bytes<W: Write>(archive: &mut tar::Builder<W>, name: &str, content: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    archive
+        .append_data(&mut header, name, content)
+        .with_context(|| format!("Failed to add {} to bundle", name))
+}
+
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config::Config;
+    use crate::lease::Lease;
+    use crate::pools::verify::failure_log_path;
+    use crate::types::{FileState, FileStatus};
+    use clap::Parser;
+
+    #[test]
+    fn test_pack_and_unpack_onto_new_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_dir = dir.path().join("old");
+        let tasks_dir = old_dir.join("claude-loop-tasks");
+        let input = old_dir.join("in.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut config = Config::from_cli(&cli).unwrap();
+        config.input_file = input.clone();
+
+        let mut task_list = TaskList::load_or_create(&tasks_dir).unwrap();
+        let task_id = task_list.create_task(old_dir.clone(), Some("do it".to_string()));
+        task_list.save(&tasks_dir).unwrap();
+        let state_path = tasks_dir.join(&task_list.get_task(&task_id).unwrap().state_file);
+        let mut state = State::new(config);
+        let a = PathBuf::from("src/a.ts");
+        let mut file = FileState::new(serde_json::json!({"n": 1}));
+        file.lease = Some(Lease::new("host:1", 60));
+        state.files.insert(a.clone(), file);
+        state.save(&state_path).unwrap();
+        state.update_status(&a, FileStatus::Failed, None);
+        state.save(&state_path).unwrap();
+        let transcript =
+            artifacts_dir(&state_path).join("attempts/src__a.ts/01-prompt.transcript.txt");
+        write_file(&transcript, b"agent output").unwrap();
+        write_file(
+            &failure_log_path(&artifacts_dir(&state_path), &a),
+            b"tsc failed\n",
+        )
+        .unwrap();
+
+        let bundle = dir.path().join("task.tar.gz");
+        pack(&tasks_dir, &task_id, &bundle).unwrap();
+
+        // Unpacked next to an existing task, onto a different checkout
+        let new_dir = dir.path().join("new");
+        let new_tasks = new_dir.join("claude-loop-tasks");
+        let mut existing = TaskList::load_or_create(&new_tasks).unwrap();
+        existing.create_task(new_dir.clone(), None);
+        existing.save(&new_tasks).unwrap();
+
+        let (new_id, manifest) = unpack(&bundle, &new_tasks, &new_dir).unwrap();
+        assert_eq!(new_id, "task_1");
+        assert_eq!(manifest.task_id, task_id);
+        assert_eq!(manifest.working_dir, old_dir);
+
+        let task_list = TaskList::load_or_create(&new_tasks).unwrap();
+        let entry = task_list.get_task(&new_id).unwrap();
+        assert_eq!(entry.working_dir, new_dir);
+        assert_eq!(entry.description.as_deref(), Some("do it"));
+        let new_state_path = new_tasks.join(&entry.state_file);
+        let unpacked = State::load(&new_state_path).unwrap();
+        // The journaled status change was compacted into the bundle
+        assert_eq!(unpacked.files[&a].status, FileStatus::Failed);
+        assert!(unpacked.files[&a].lease.is_none());
+        assert_eq!(unpacked.config.input_file, new_dir.join("in.json"));
+        let transcript =
+            artifacts_dir(&new_state_path).join("attempts/src__a.ts/01-prompt.transcript.txt");
+        assert_eq!(fs::read(transcript).unwrap(), b"agent output");
+        assert_eq!(
+            fs::read_to_string(failure_log_path(&artifacts_dir(&new_state_path), &a)).unwrap(),
+            "tsc failed\n"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Fingerprints of completed files, and reuse of earlier results in incremental runs
+
+use crate::config::Config;
+use crate::process::find_related_files;
+use crate::snapshot::{hash_bytes, hash_file};
+use crate::state::State;
+use crate::task_list::TaskList;
+use crate::types::{FileStatus, Fingerprint};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Version string of the installed agent CLI ("unknown" if it can't be run)
+pub async fn agent_version() -> String {
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new("claude")
+            .arg("--version")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await;
+    match output {
+        Ok(Ok(output)) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => {
+            warn!("Failed to get claude --version, fingerprints will record it as unknown");
+            "unknown".to_string()
+        }
+    }
+}
+
+/// Fingerprint of a file as it is now, or None if it can't be read
+pub fn fingerprint(
+    path: &Path,
+    original_data: &serde_json::Value,
+    config: &Config,
+    working_dir: &Path,
+    agent: &str,
+) -> Option<Fingerprint> {
+    let source = hash_file(&working_dir.join(path)).ok()?;
+
+    let mut related = find_related_files(path, working_dir);
+    related.sort();
+    let related: Vec<_> = related
+        .iter()
+        .map(|p| (p, hash_file(&working_dir.join(p)).unwrap_or_default()))
+        .collect();
+
+    let prompt = serde_json::json!({
+        "prompt": config.prompt,
+        "fixup_prompt": config.fixup_prompt,
+        "verification_cmd": config.verification_cmd,
+        "review_rubric": config.review_rubric,
+        "allowlist_pattern": config.allowlist_pattern,
+        "pipeline": config.pipeline,
+        "original_data": original_data,
+    });
+
+    Some(Fingerprint {
+        source,
+        related: hash_bytes(
+            serde_json::to_string(&related)
+                .unwrap_or_default()
+                .as_bytes(),
+        ),
+        prompt: hash_bytes(prompt.to_string().as_bytes()),
+        agent: agent.to_string(),
+    })
+}
+
+/// Result of an earlier completed run of a file
+#[derive(Debug, Clone)]
+pub struct CachedResult {
+    pub task_id: String,
+    pub result_data: Option<serde_json::Value>,
+    pub result_data_raw: Option<bool>,
+}
+
+/// Completed files across every task in the tasks directory, by path and fingerprint
+#[derive(Debug, Default)]
+pub struct ResultCache {
+    entries: HashMap<(PathBuf, Fingerprint), CachedResult>,
+}
+
+impl ResultCache {
+    /// Index the completed files of every task whose state can be read
+    pub fn load(tasks_dir: &Path) -> Self {
+        let mut cache = ResultCache::default();
+        let task_list = match TaskList::load_or_create(tasks_dir) {
+            Ok(list) => list,
+            Err(e) => {
+                warn!(error = %e, "Failed to load task list, incremental run will process every file");
+                return cache;
+            }
+        };
+        for (task_id, entry) in &task_list.tasks {
+            let state = match State::load_read_only(&tasks_dir.join(&entry.state_file)) {
+                Ok(state) => state,
+                Err(e) => {
+                    debug!(task_id = %task_id, error = %e, "Skipping task for result cache");
+                    continue;
+                }
+            };
+            for (path, file) in state.files {
+                let Some(fingerprint) = file.fingerprint else {
+                    continue;
+                };
+                if file.status != FileStatus::Completed {
+                    continue;
+                }
+                cache.entries.insert(
+                    (path, fingerprint),
+                    CachedResult {
+                        task_id: task_id.clone(),
+                        result_data: file.result_data,
+                        result_data_raw: file.result_data_raw,
+                    },
+                );
+            }
+        }
+        cache
+    }
+
+    pub fn get(&self, path: &Path, fingerprint: &Fingerprint) -> Option<&CachedResult> {
+        self.entries.get(&(path.to_path_buf(), fingerprint.clone()))
+    }
+}
+
+/// Mark pending files, and files a resumed run left awaiting verification, that match a
+/// completed run in any task as completed, copying that run's result. Returns the files reused.
+pub fn reuse_results(
+    state: &mut State,
+    cache: &ResultCache,
+    config: &Config,
+    working_dir: &Path,
+    agent: &str,
+) -> Vec<PathBuf> {
+    let mut reused = Vec::new();
+    for (path, file) in state.files.iter_mut() {
+        if !matches!(file.status, FileStatus::Pending | FileStatus::AwaitingVerification) {
+            continue;
+        }
+        let Some(fingerprint) = fingerprint(path, &file.original_data, config, working_dir, agent)
+        else {
+            continue;
+        };
+        let Some(cached) = cache.get(path, &fingerprint) else {
+            continue;
+        };
+        info!(
+            file = %path.display(),
+            task_id = %cached.task_id,
+            "Unchanged since an earlier run, reusing its result"
+        );
+        file.result_data = cached.result_data.clone();
+        file.result_data_raw = cached.result_data_raw;
+        file.cached_from = Some(cached.task_id.clone());
+        file.fingerprint = Some(fingerprint);
+        file.set_status(FileStatus::Completed, None);
+        reused.push(path.clone());
+    }
+    reused.sort();
+    for path in &reused {
+        state.mark_changed(path);
+    }
+    reused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::types::FileState;
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn test_reuses_results_of_unchanged_files_from_other_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let working_dir = dir.path().join("work");
+        let tasks_dir = dir.path().join("tasks");
+        fs::create_dir_all(&working_dir).unwrap();
+        fs::write(working_dir.join("a.ts"), "a").unwrap();
+        fs::write(working_dir.join("a.test.ts"), "test a").unwrap();
+        fs::write(working_dir.join("b.ts"), "b").unwrap();
+
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let config = Config::from_cli(&cli).unwrap();
+        let mut task_list = TaskList::load_or_create(&tasks_dir).unwrap();
+        let earlier_id = task_list.create_task(working_dir.clone(), None);
+        task_list.save(&tasks_dir).unwrap();
+
+        let mut earlier = State::new(config.clone());
+        for name in ["a.ts", "b.ts"] {
+            let path = PathBuf::from(name);
+            let mut file = FileState::new(serde_json::Value::Null);
+            file.status = FileStatus::Completed;
+            file.result_data = Some(serde_json::json!({"done": name}));
+            file.fingerprint =
+                fingerprint(&path, &file.original_data, &config, &working_dir, "1.0");
+            earlier.files.insert(path, file);
+        }
+        let earlier_path = tasks_dir.join(&task_list.get_task(&earlier_id).unwrap().state_file);
+        earlier.save(&earlier_path).unwrap();
+
+        // b.ts changed since, and a different agent version doesn't match
+        fs::write(working_dir.join("b.ts"), "b changed").unwrap();
+        let cache = ResultCache::load(&tasks_dir);
+        let mut state = State::new(config.clone());
+        for name in ["a.ts", "b.ts"] {
+            state
+                .files
+                .insert(PathBuf::from(name), FileState::new(serde_json::Value::Null));
+        }
+        assert!(reuse_results(&mut state.clone(), &cache, &config, &working_dir, "2.0").is_empty());
+
+        let reused = reuse_results(&mut state, &cache, &config, &working_dir, "1.0");
+        assert_eq!(reused, vec![PathBuf::from("a.ts")]);
+        let a = &state.files[Path::new("a.ts")];
+        assert_eq!(a.status, FileStatus::Completed);
+        assert_eq!(a.result_data, Some(serde_json::json!({"done": "a.ts"})));
+        assert_eq!(a.cached_from.as_deref(), Some(earlier_id.as_str()));
+        assert_eq!(state.files[Path::new("b.ts")].status, FileStatus::Pending);
+
+        // Related test files and the prompt are part of the fingerprint
+        let before = fingerprint(
+            Path::new("a.ts"),
+            &serde_json::Value::Null,
+            &config,
+            &working_dir,
+            "1.0",
+        );
+        fs::write(working_dir.join("a.test.ts"), "test a changed").unwrap();
+        let after = fingerprint(
+            Path::new("a.ts"),
+            &serde_json::Value::Null,
+            &config,
+            &working_dir,
+            "1.0",
+        );
+        assert_ne!(
+            before.as_ref().unwrap().related,
+            after.as_ref().unwrap().related
+        );
+        let mut reworded = config.clone();
+        reworded.prompt = "do it differently".to_string();
+        let reworded = fingerprint(
+            Path::new("a.ts"),
+            &serde_json::Value::Null,
+            &reworded,
+            &working_dir,
+            "1.0",
+        );
+        assert_ne!(after.unwrap().prompt, reworded.unwrap().prompt);
+    }
+}
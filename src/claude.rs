@@ -1,9 +1,10 @@
-use crate::process::expand_pattern;
-use crate::types::ProcessOutput;
+use crate::process::{expand_pattern, wait_for_output};
+use crate::types::{AgentUsage, FileTask, ProcessOutput};
 use anyhow::{Context, Result};
 use std::path::Path;
 use std::process::Stdio;
-use tokio::process::Command;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
 
 /// Instruction appended to prompts to get structured result output
 pub const RESULT_INSTRUCTION: &str = r#"
@@ -41,6 +42,54 @@ Original data: {original_data}
     )
 }
 
+/// Instruction appended to batch prompts to get one result per file
+pub const BATCH_RESULT_INSTRUCTION: &str = r#"
+
+When you have finished all files, output your result data as a JSON object keyed by file path, on a single line starting with "RESULT:"
+Example: RESULT: {"src/a.ts": {"coverage": 78.5}, "src/b.ts": "done"}
+"#;
+
+/// Build a prompt covering several files, with a combined allowlist
+pub fn build_batch_prompt(
+    base_prompt: &str,
+    tasks: &[FileTask],
+    allowlist_pattern: &str,
+) -> String {
+    let allowlist = tasks
+        .iter()
+        .map(|t| expand_pattern(allowlist_pattern, &t.path))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let files = tasks
+        .iter()
+        .map(|t| {
+            format!(
+                "File: {}\nOriginal data: {}",
+                t.path.display(),
+                serde_json::to_string(&t.original_data).unwrap_or_else(|_| "null".to_string())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    format!(
+        "{base_prompt}
+
+Apply this to each of the following {count} files.
+
+IMPORTANT: You may ONLY read and modify files matching these patterns: {allowlist}
+Do not edit any other files.
+
+{files}
+{result_instruction}",
+        base_prompt = base_prompt,
+        count = tasks.len(),
+        allowlist = allowlist,
+        files = files,
+        result_instruction = BATCH_RESULT_INSTRUCTION,
+    )
+}
+
 /// Build fixup prompt with error context
 pub fn build_fixup_prompt(
     fixup_prompt: &str,
@@ -73,22 +122,172 @@ Please fix the issues and try again.
     )
 }
 
-/// Run the Claude CLI with the given prompt
-pub async fn run_claude(prompt: &str, working_dir: &Path) -> Result<ProcessOutput> {
-    let output = Command::new("claude")
+/// Instruction appended to review prompts to get a structured verdict
+pub const REVIEW_INSTRUCTION: &str = r#"
+
+Do not modify any files. When you have finished reviewing, output your verdict as JSON on a single line starting with "VERDICT:"
+Example: VERDICT: {"approved": false, "reasons": ["test_parse only checks that the mock returns its input"]}
+If the changes meet every point of the rubric, output: VERDICT: {"approved": true}
+"#;
+
+/// Build a prompt asking a reviewer to judge a file's changes against a rubric
+pub fn build_review_prompt(rubric: &str, file_path: &Path, diff: &str) -> String {
+    format!(
+        "Review the following changes made for {file} against this rubric:
+
+{rubric}
+
+```diff
+{diff}```
+{review_instruction}",
+        rubric = rubric,
+        file = file_path.display(),
+        diff = diff,
+        review_instruction = REVIEW_INSTRUCTION,
+    )
+}
+
+/// Start the Claude CLI with the given prompt.
+/// Output is requested as JSON to get cost and token usage.
+/// The process is killed if the returned child is dropped.
+pub fn spawn_claude(prompt: &str, working_dir: &Path) -> Result<Child> {
+    Command::new("claude")
         .arg("-p")
         .arg(prompt)
         .arg("--dangerously-skip-permissions") // Non-interactive mode
+        .arg("--output-format")
+        .arg("json")
         .current_dir(working_dir)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .output()
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to execute claude CLI")
+}
+
+/// Wait for a Claude run started with `spawn_claude`; `stdout` holds the final response
+/// text. Falls back to the raw output if it isn't the expected JSON.
+/// Returns None if the run didn't finish within `timeout`.
+pub async fn wait_claude(child: Child, timeout: Option<Duration>) -> Result<Option<ProcessOutput>> {
+    let started = Instant::now();
+    let Some(output) = wait_for_output(child, timeout)
         .await
-        .context("Failed to execute claude CLI")?;
+        .context("Failed to execute claude CLI")?
+    else {
+        return Ok(None);
+    };
+
+    let raw_stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let (stdout, agent_usage, is_error) = match parse_json_output(&raw_stdout) {
+        Some((text, usage, is_error)) => (text, usage, is_error),
+        None => (
+            raw_stdout,
+            AgentUsage {
+                duration_secs: started.elapsed().as_secs_f64(),
+                ..Default::default()
+            },
+            false,
+        ),
+    };
 
-    Ok(ProcessOutput {
-        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+    Ok(Some(ProcessOutput {
+        stdout,
         stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         exit_code: output.status.code().unwrap_or(-1),
-    })
+        agent_usage: Some(agent_usage),
+        is_error,
+    }))
+}
+
+/// Why a finished Claude run failed: a non-zero exit or an error reported in its output
+pub fn agent_failure(output: &ProcessOutput) -> Option<String> {
+    if output.is_error {
+        Some(format!("Claude reported an error: {}", output.stdout.trim()))
+    } else if output.exit_code != 0 {
+        Some(format!(
+            "Claude exited with code {}: {}",
+            output.exit_code,
+            output.stderr.trim()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Parse `--output-format json` output into the response text, its usage and whether
+/// Claude reported the run as an error
+fn parse_json_output(stdout: &str) -> Option<(String, AgentUsage, bool)> {
+    let value: serde_json::Value = serde_json::from_str(stdout.trim()).ok()?;
+    let text = value.get("result")?.as_str()?.to_string();
+
+    let number = |v: &serde_json::Value, key: &str| v.get(key).and_then(|n| n.as_u64()).unwrap_or(0);
+    let usage = value.get("usage").cloned().unwrap_or_default();
+
+    Some((
+        text,
+        AgentUsage {
+            cost_usd: value
+                .get("total_cost_usd")
+                .or_else(|| value.get("cost_usd"))
+                .and_then(|c| c.as_f64())
+                .unwrap_or(0.0),
+            input_tokens: number(&usage, "input_tokens")
+                + number(&usage, "cache_creation_input_tokens")
+                + number(&usage, "cache_read_input_tokens"),
+            output_tokens: number(&usage, "output_tokens"),
+            duration_secs: number(&value, "duration_ms") as f64 / 1000.0,
+        },
+        value.get("is_error").and_then(|e| e.as_bool()).unwrap_or(false),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json_output() {
+        let stdout = r#"{"type":"result","subtype":"success","result":"Done.\nRESULT: {\"ok\":true}","total_cost_usd":0.25,"duration_ms":1500,"usage":{"input_tokens":100,"cache_read_input_tokens":900,"output_tokens":50}}"#;
+        let (text, usage, is_error) = parse_json_output(stdout).unwrap();
+        assert!(!is_error);
+        assert!(text.ends_with(r#"RESULT: {"ok":true}"#));
+        assert_eq!(
+            usage,
+            AgentUsage {
+                cost_usd: 0.25,
+                input_tokens: 1000,
+                output_tokens: 50,
+                duration_secs: 1.5,
+            }
+        );
+
+        assert!(parse_json_output("plain text\nRESULT: \"done\"").is_none());
+    }
+
+    #[test]
+    fn test_agent_failure() {
+        let stdout = r#"{"type":"result","subtype":"success","is_error":true,"result":"API Error: 529 Overloaded"}"#;
+        let (text, _, is_error) = parse_json_output(stdout).unwrap();
+        let mut output = ProcessOutput {
+            stdout: text,
+            stderr: String::new(),
+            exit_code: 0,
+            agent_usage: None,
+            is_error,
+        };
+        assert_eq!(
+            agent_failure(&output).as_deref(),
+            Some("Claude reported an error: API Error: 529 Overloaded")
+        );
+
+        output.is_error = false;
+        assert!(agent_failure(&output).is_none());
+
+        output.exit_code = 1;
+        output.stderr = "connection reset\n".to_string();
+        assert_eq!(
+            agent_failure(&output).as_deref(),
+            Some("Claude exited with code 1: connection reset")
+        );
+    }
 }
@@ -1,4 +1,12 @@
-use clap::Parser;
+use crate::batch::BatchBy;
+use crate::config::{
+    BudgetPolicy, DirtyFilePolicy, OrphanPolicy, PartialEditPolicy, RemovedFilePolicy,
+};
+use crate::control::ControlCommand;
+use crate::ordering::OrderStrategy;
+use crate::types::FileStatus;
+use crate::verify_groups::VerifyGroup;
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone)]
@@ -13,6 +21,9 @@ disconnections. Use --resume to continue from where you left off if interrupted.
 )]
 #[command(version)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Input JSON file mapping filepaths to metadata
     #[arg(short, long)]
     pub input: Option<PathBuf>,
@@ -29,6 +40,15 @@ pub struct Cli {
     #[arg(short, long)]
     pub verify: Option<String>,
 
+    /// Rubric for an agent review of each file's diff after verification passes;
+    /// rejections are fixed up like verification failures
+    #[arg(long, value_name = "RUBRIC")]
+    pub review: Option<String>,
+
+    /// Run files through the stages defined in this JSON file instead of prompt -> verify -> fixup
+    #[arg(long, value_name = "FILE")]
+    pub pipeline: Option<PathBuf>,
+
     /// Number of workers for prompt pool
     #[arg(short, long, default_value = "5")]
     pub concurrency: usize,
@@ -37,26 +57,132 @@ pub struct Cli {
     #[arg(long)]
     pub verify_concurrency: Option<usize>,
 
-    /// Maximum number of files to process
+    /// Adjust pool sizes automatically from memory, load average and verify latency,
+    /// starting from --concurrency/--verify-concurrency
+    #[arg(long)]
+    pub auto_concurrency: bool,
+
+    /// Most workers --auto-concurrency may grow either pool to
+    /// (defaults to --concurrency, or the pool's starting size if larger)
+    #[arg(long, value_name = "N")]
+    pub max_concurrency: Option<usize>,
+
+    /// Kill a verification command after this many seconds and count it as a failure
+    #[arg(long, value_name = "SECS")]
+    pub verify_timeout: Option<u64>,
+
+    /// Limit how many verifications in a named group run at once, e.g. `tsc=1` or
+    /// `integration=2:tests/integration/*` (repeatable). Files join a group by pattern
+    /// or with `verify_group` in their input data.
+    #[arg(long = "verify-group", value_name = "NAME=LIMIT[:PATTERNS]")]
+    pub verify_groups: Vec<VerifyGroup>,
+
+    /// Verify up to N files with one command run; failing batches are bisected to find the culprits
+    #[arg(long, value_name = "N")]
+    pub verify_batch_size: Option<usize>,
+
+    /// How long to wait for a verify batch to fill before running it [default: 30]
+    #[arg(long, value_name = "SECS")]
+    pub verify_batch_wait: Option<u64>,
+
+    /// Maximum number of files to process (the first N under --order)
     #[arg(short, long)]
     pub max_files: Option<usize>,
 
+    /// Order in which files are processed [default: input]
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    pub order: Option<OrderStrategy>,
+
+    /// Infer dependencies from relative imports, in addition to `depends_on` in the input metadata
+    #[arg(long)]
+    pub infer_deps: bool,
+
+    /// Skip files whose content, related files, prompt and agent version match a completed
+    /// run in any task in the tasks directory, reusing that run's result
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Send up to N files to Claude in a single prompt (verification stays per file)
+    #[arg(long, value_name = "N")]
+    pub batch_size: Option<usize>,
+
+    /// How to group files into batches [default: dir]
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    pub batch_by: Option<BatchBy>,
+
+    /// Byte budget per batch with --batch-by size [default: 16384]
+    #[arg(long, value_name = "BYTES")]
+    pub batch_max_bytes: Option<u64>,
+
     /// File allowlist pattern for Claude ({file}, {file_stem}, {file_dir} substituted)
     #[arg(short, long, default_value = "{file_stem}*")]
     pub allowlist: String,
 
     /// Tasks directory for state files and task list
-    #[arg(short = 'd', long, default_value = "./claude-loop-tasks")]
+    #[arg(short = 'd', long, default_value = "./claude-loop-tasks", global = true)]
     pub tasks_dir: PathBuf,
 
     /// Resume a specific task by ID, or all incomplete tasks if not specified
     #[arg(long)]
     pub resume: Option<Option<String>>,
 
-    /// Maximum number of fixup retry attempts
+    /// Seconds a runner's claim on a file lasts without a heartbeat before other runners
+    /// resuming the same task may take the file over
+    #[arg(long, value_name = "SECS")]
+    pub lease_ttl: Option<u64>,
+
+    /// On resume, what to do with processes from a crashed run still working on files
+    /// [default: ask]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub orphans: Option<OrphanPolicy>,
+
+    /// On resume, what to do with edits left by agent runs that never finished
+    /// [default: keep]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub partial_edits: Option<PartialEditPolicy>,
+
+    /// On resume, sync the task with its input file first: add new files, drop files no
+    /// longer listed and pick up changed metadata
+    #[arg(long)]
+    pub resync: bool,
+
+    /// With --resync, what to do with files no longer in the input [default: archive]
+    #[arg(long, value_enum, value_name = "POLICY", requires = "resync")]
+    pub removed: Option<RemovedFilePolicy>,
+
+    /// With --resync, send files whose metadata changed back to Pending
+    #[arg(long, requires = "resync")]
+    pub reset_changed: bool,
+
+    /// Maximum number of fixup retry attempts (verification failures per file)
     #[arg(long, default_value = "3")]
     pub max_retries: u32,
 
+    /// Retry a Claude run this many times if it fails to launch, exits non-zero or reports an error [default: 0]
+    #[arg(long, value_name = "N")]
+    pub agent_retries: Option<u32>,
+
+    /// Kill a Claude run after this many seconds
+    #[arg(long, value_name = "SECS")]
+    pub agent_timeout: Option<u64>,
+
+    /// Retry a Claude run this many times if it times out [default: 0]
+    #[arg(long, value_name = "N")]
+    pub agent_timeout_retries: Option<u32>,
+
+    /// Wait this long before the first retry, doubling on each retry after [default: 0]
+    #[arg(long, value_name = "SECS")]
+    pub retry_backoff: Option<u64>,
+
+    /// Upper bound on the retry backoff [default: 300]
+    #[arg(long, value_name = "SECS")]
+    pub retry_backoff_max: Option<u64>,
+
+    /// After this many failed fixups in a row, revert the file's scope and rerun the
+    /// original prompt instead of stacking another fixup
+    #[arg(long, value_name = "K")]
+    pub fresh_start_after: Option<u32>,
+
     /// Working directory for the task (defaults to current directory)
     #[arg(short = 'w', long)]
     pub working_dir: Option<PathBuf>,
@@ -81,6 +207,60 @@ pub struct Cli {
     #[arg(long)]
     pub git_commit_message: Option<String>,
 
+    /// Squash the task's per-file commits into one when finalizing (implies --git)
+    #[arg(long)]
+    pub git_squash: bool,
+
+    /// Push the task branch to this remote when finalizing (implies --git)
+    #[arg(long, value_name = "REMOTE")]
+    pub git_push: Option<String>,
+
+    /// Finalize git work when the task completes: squash/push if configured,
+    /// then return to the original branch (implies --git)
+    #[arg(long)]
+    pub git_finalize: bool,
+
+    /// What to do when Claude edits a file that was already dirty before the run
+    /// and falls outside the allowlist [default: report]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub dirty_files: Option<DirtyFilePolicy>,
+
+    /// Task budget: maximum total cost in USD
+    #[arg(long, value_name = "USD")]
+    pub max_cost: Option<f64>,
+
+    /// Task budget: maximum input + output tokens
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<u64>,
+
+    /// Task budget: maximum wall-clock run time across resumes
+    #[arg(long, value_name = "SECS")]
+    pub max_wall_time: Option<u64>,
+
+    /// Task budget: maximum number of Claude invocations
+    #[arg(long, value_name = "N")]
+    pub max_agent_calls: Option<u32>,
+
+    /// Per-file budget: maximum cost in USD (the file fails when exceeded)
+    #[arg(long, value_name = "USD")]
+    pub file_max_cost: Option<f64>,
+
+    /// Per-file budget: maximum input + output tokens
+    #[arg(long, value_name = "N")]
+    pub file_max_tokens: Option<u64>,
+
+    /// Per-file budget: maximum seconds spent in Claude
+    #[arg(long, value_name = "SECS")]
+    pub file_max_wall_time: Option<u64>,
+
+    /// Per-file budget: maximum number of Claude invocations
+    #[arg(long, value_name = "N")]
+    pub file_max_agent_calls: Option<u32>,
+
+    /// When a task budget runs out, let running Claude calls finish or abort them [default: finish]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub budget_policy: Option<BudgetPolicy>,
+
     /// Disable all git features (overrides --git, --git-commit, --git-branch)
     #[arg(long)]
     pub no_git: bool,
@@ -96,6 +276,155 @@ pub struct Cli {
     pub limit: f64,
 }
 
+/// Standalone commands that operate on an existing task instead of running one
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Wrap up a task on the git side: squash, push, and return to the original branch
+    Finalize {
+        /// Task ID to finalize
+        task_id: String,
+
+        /// Squash the task's per-file commits into a single commit
+        #[arg(long)]
+        squash: bool,
+
+        /// Push the task branch to this remote
+        #[arg(long, value_name = "REMOTE")]
+        push: Option<String>,
+    },
+
+    /// Show task progress, or per-file attempts and diffs for one task
+    Status {
+        /// Task ID to inspect (lists all tasks if omitted)
+        task_id: Option<String>,
+
+        /// Show the attempt history for a single file
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// Print the recorded diff of each attempt (with --file)
+        #[arg(long)]
+        diffs: bool,
+    },
+
+    /// Summarize where a task's time went: per-phase averages, slowest files, throughput
+    Report {
+        /// Task ID to report on
+        task_id: String,
+
+        /// Number of slowest files to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+
+    /// Sync a task with its input file: add new files, archive or remove files no longer
+    /// listed, and pick up changed metadata
+    Sync {
+        /// Task ID to sync
+        task_id: String,
+
+        /// Input file to sync with, kept as the task's input file [default: the task's]
+        #[arg(long)]
+        input: Option<PathBuf>,
+
+        /// What to do with files no longer in the input [default: archive]
+        #[arg(long, value_enum, value_name = "POLICY")]
+        removed: Option<RemovedFilePolicy>,
+
+        /// Send files whose metadata changed back to Pending
+        #[arg(long)]
+        reset_changed: bool,
+
+        /// Show the differences without changing the task
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Start a new task from an existing task's config, with overrides, for some or all of
+    /// its files (the original task is left as it is)
+    Clone(Box<CloneArgs>),
+
+    /// Bundle a task's state, transcripts, diffs and failure logs into a tar.gz
+    Pack {
+        /// Task ID to pack
+        task_id: String,
+
+        /// Bundle to write [default: <TASK_ID>.tar.gz]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Add a task from a bundle made by `pack`, rebased onto a new working directory
+    Unpack {
+        /// Bundle to unpack
+        bundle: PathBuf,
+
+        /// Working directory for the unpacked task [default: current directory]
+        #[arg(short = 'w', long)]
+        working_dir: Option<PathBuf>,
+    },
+
+    /// Control a running task: pause/resume, resize pools, skip or requeue files
+    Ctl {
+        /// Task ID of the running task
+        task_id: String,
+
+        #[command(subcommand)]
+        action: ControlCommand,
+    },
+}
+
+/// Arguments of the `clone` command
+#[derive(Args, Debug, Clone)]
+pub struct CloneArgs {
+    /// Task ID to clone
+    pub task_id: String,
+
+    /// Only files with this status (repeatable)
+    #[arg(long, value_enum)]
+    pub status: Vec<FileStatus>,
+
+    /// Only files matching this glob (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub glob: Vec<glob::Pattern>,
+
+    /// Only files whose last error contains this text
+    #[arg(long, value_name = "TEXT")]
+    pub error: Option<String>,
+
+    /// Prompt for the new task
+    #[arg(long)]
+    pub prompt: Option<String>,
+
+    /// Fixup prompt for the new task
+    #[arg(long)]
+    pub fixup: Option<String>,
+
+    /// Verification command for the new task
+    #[arg(long)]
+    pub verify: Option<String>,
+
+    /// Review rubric for the new task
+    #[arg(long, value_name = "RUBRIC")]
+    pub review: Option<String>,
+
+    /// Allowlist pattern for the new task
+    #[arg(long)]
+    pub allowlist: Option<String>,
+
+    /// Prompt concurrency for the new task
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Maximum fixup retries for the new task
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Description for the new task [default: the prompt, if overridden]
+    #[arg(long)]
+    pub description: Option<String>,
+}
+
 impl Cli {
     /// Check if any git feature is requested (and not overridden by --no-git)
     #[allow(dead_code)]
@@ -103,6 +432,16 @@ impl Cli {
         !self.no_git && (self.git || self.git_branch || self.git_commit)
     }
 
+    /// Check if any git flag was passed that implies --git
+    pub fn git_implied(&self) -> bool {
+        self.git
+            || self.git_branch
+            || self.git_commit
+            || self.git_squash
+            || self.git_push.is_some()
+            || self.git_finalize
+    }
+
     /// Check if we're in resume mode
     pub fn is_resume(&self) -> bool {
         self.resume.is_some()
@@ -115,12 +454,15 @@ impl Cli {
 
     /// Validate that required arguments are present when not resuming
     pub fn validate(&self) -> anyhow::Result<()> {
-        if !self.is_resume() {
+        if self.resync && !self.is_resume() {
+            anyhow::bail!("--resync only applies with --resume");
+        }
+        if self.command.is_none() && !self.is_resume() {
             if self.input.is_none() {
                 anyhow::bail!("--input is required when not using --resume");
             }
-            if self.prompt.is_none() {
-                anyhow::bail!("--prompt is required when not using --resume");
+            if self.prompt.is_none() && self.pipeline.is_none() {
+                anyhow::bail!("--prompt is required when not using --resume or --pipeline");
             }
         }
         Ok(())
@@ -0,0 +1,79 @@
+use crate::cli::{Cli, CloneArgs};
+use crate::state::{FileSelector, State};
+use crate::task_list::{describe_prompt, TaskList};
+use anyhow::{Context, Result};
+
+/// Run the `clone` command: create a new task from an existing one's config
+pub fn run(cli: &Cli, args: &CloneArgs) -> Result<()> {
+    let task_id = args.task_id.as_str();
+    let mut task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?
+        .clone();
+    let state = State::load(&cli.tasks_dir.join(&entry.state_file))
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+
+    if args.concurrency == Some(0) {
+        anyhow::bail!("--concurrency must be at least 1");
+    }
+    let mut config = state.config.clone();
+    if let Some(ref prompt) = args.prompt {
+        config.prompt = prompt.clone();
+    }
+    if let Some(ref fixup) = args.fixup {
+        config.fixup_prompt = Some(fixup.clone());
+    }
+    if let Some(ref verify) = args.verify {
+        config.verification_cmd = Some(verify.clone());
+    }
+    if let Some(ref review) = args.review {
+        config.review_rubric = Some(review.clone());
+    }
+    if let Some(ref allowlist) = args.allowlist {
+        config.allowlist_pattern = allowlist.clone();
+    }
+    if let Some(concurrency) = args.concurrency {
+        config.concurrency = concurrency;
+    }
+    if let Some(max_retries) = args.max_retries {
+        config.max_retries = max_retries;
+    }
+    config.check()?;
+
+    let selector = FileSelector {
+        statuses: args.status.clone(),
+        globs: args.glob.clone(),
+        error: args.error.clone(),
+    };
+    let mut cloned = state.fork(config, &selector);
+    if cloned.files.is_empty() {
+        anyhow::bail!("No files in {} match the selection", task_id);
+    }
+
+    let description = args
+        .description
+        .clone()
+        .or_else(|| args.prompt.as_deref().map(describe_prompt))
+        .or(entry.description);
+    let new_id = task_list
+        .create_clone(task_id, description)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+    let state_path = cli
+        .tasks_dir
+        .join(&task_list.get_task(&new_id).unwrap().state_file);
+    cloned
+        .save(&state_path)
+        .context("Failed to save state for cloned task")?;
+    task_list.save(&cli.tasks_dir)?;
+
+    println!(
+        "Created {} from {} with {} of its {} files",
+        new_id,
+        task_id,
+        cloned.files.len(),
+        state.files.len()
+    );
+    println!("To run it, use: claude-loop-runner --resume {}", new_id);
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use crate::cli::Cli;
+use crate::control::{self, ControlCommand};
+use crate::task_list::TaskList;
+use anyhow::Result;
+
+/// Run the `ctl` command: append a command to the running task's control file
+pub fn run(cli: &Cli, task_id: &str, command: &ControlCommand) -> Result<()> {
+    let task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+
+    let control_path = control::control_path(&cli.tasks_dir.join(&entry.state_file));
+    if !control_path.exists() {
+        anyhow::bail!(
+            "Task {} is not running (no control file at {})",
+            task_id,
+            control_path.display()
+        );
+    }
+
+    control::send_command(&control_path, command)?;
+    println!("Sent {:?} to {}", command, task_id);
+    Ok(())
+}
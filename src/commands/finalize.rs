@@ -0,0 +1,92 @@
+use crate::cli::Cli;
+use crate::git;
+use crate::state::State;
+use crate::task_list::TaskList;
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Run the standalone `finalize` command for a task
+pub async fn run(cli: &Cli, task_id: &str, squash: bool, push: Option<&str>) -> Result<()> {
+    let task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+
+    let state_path = cli.tasks_dir.join(&entry.state_file);
+    let mut state = State::load(&state_path)
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+
+    if squash {
+        state.config.git.squash_on_finalize = true;
+    }
+    if let Some(remote) = push {
+        state.config.git.push_remote = Some(remote.to_string());
+    }
+
+    let summary = state.get_summary();
+    if summary.pending > 0 || summary.prompt_in_progress > 0 || summary.verify_in_progress > 0 {
+        warn!(
+            task_id = %task_id,
+            pending = summary.pending,
+            "Task still has unfinished files, finalizing anyway"
+        );
+    }
+
+    finalize_task(task_id, &mut state, &entry.working_dir).await?;
+    state
+        .save(&state_path)
+        .context("Failed to save state after finalize")?;
+
+    Ok(())
+}
+
+/// Wrap up a task on the git side.
+///
+/// Squashes the task's commits (if configured), pushes the task branch (if a remote
+/// is configured) and finally checks out the branch we started from.
+pub async fn finalize_task(task_id: &str, state: &mut State, working_dir: &Path) -> Result<()> {
+    let git_state = state.git_state.clone();
+    if !git_state.enabled {
+        anyhow::bail!("Git tracking was not enabled for {}, nothing to finalize", task_id);
+    }
+
+    let work_branch = git_state
+        .task_branch
+        .clone()
+        .or_else(|| git_state.original_branch.clone())
+        .ok_or_else(|| anyhow::anyhow!("No branch recorded for {}", task_id))?;
+
+    let current = git::get_current_branch(working_dir).await?;
+    if current != work_branch {
+        anyhow::bail!(
+            "Expected to be on '{}' to finalize {}, but HEAD is on '{}'",
+            work_branch,
+            task_id,
+            current
+        );
+    }
+
+    if state.config.git.squash_on_finalize {
+        match git_state.base_commit.as_deref() {
+            Some(base) => {
+                let message = state.summary_commit_message(task_id);
+                git::squash_since(working_dir, base, &message).await?;
+            }
+            None => warn!(task_id = %task_id, "No base commit recorded, skipping squash"),
+        }
+    }
+
+    if let Some(ref remote) = state.config.git.push_remote {
+        git::push_branch(working_dir, remote, &work_branch).await?;
+    }
+
+    if let Some(ref original) = git_state.original_branch {
+        if git_state.task_branch.is_some() && *original != work_branch {
+            git::checkout_branch(working_dir, original).await?;
+        }
+    }
+
+    info!(task_id = %task_id, branch = %work_branch, "Task finalized");
+    Ok(())
+}
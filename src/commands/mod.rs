@@ -0,0 +1,49 @@
+pub mod clone;
+pub mod ctl;
+pub mod finalize;
+pub mod pack;
+pub mod report;
+pub mod status;
+pub mod sync;
+pub mod unpack;
+
+use crate::cli::{Cli, Command};
+use anyhow::Result;
+
+/// Dispatch a standalone subcommand
+pub async fn run(command: &Command, cli: &Cli) -> Result<()> {
+    match command {
+        Command::Finalize {
+            task_id,
+            squash,
+            push,
+        } => finalize::run(cli, task_id, *squash, push.as_deref()).await,
+        Command::Status {
+            task_id,
+            file,
+            diffs,
+        } => status::run(cli, task_id.as_deref(), file.as_deref(), *diffs),
+        Command::Report { task_id, top } => report::run(cli, task_id, *top),
+        Command::Sync {
+            task_id,
+            input,
+            removed,
+            reset_changed,
+            dry_run,
+        } => sync::run(
+            cli,
+            task_id,
+            input.as_deref(),
+            removed.unwrap_or_default(),
+            *reset_changed,
+            *dry_run,
+        ),
+        Command::Clone(args) => clone::run(cli, args),
+        Command::Pack { task_id, output } => pack::run(cli, task_id, output.as_deref()),
+        Command::Unpack {
+            bundle,
+            working_dir,
+        } => unpack::run(cli, bundle, working_dir.as_deref()),
+        Command::Ctl { task_id, action } => ctl::run(cli, task_id, action),
+    }
+}
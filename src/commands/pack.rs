@@ -0,0 +1,14 @@
+use crate::bundle;
+use crate::cli::Cli;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Run the `pack` command: bundle a task into a single archive
+pub fn run(cli: &Cli, task_id: &str, output: Option<&Path>) -> Result<()> {
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", task_id)));
+    bundle::pack(&cli.tasks_dir, task_id, &output)?;
+    println!("Packed {} into {}", task_id, output.display());
+    Ok(())
+}
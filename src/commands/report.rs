@@ -0,0 +1,68 @@
+use crate::cli::Cli;
+use crate::state::State;
+use crate::task_list::TaskList;
+use crate::timeline::{format_duration, hourly_throughput, phase_stats, slowest_files};
+use anyhow::{Context, Result};
+
+/// Run the `report` command: time spent per phase, slowest files and throughput
+pub fn run(cli: &Cli, task_id: &str, top: usize) -> Result<()> {
+    let task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+    let state = State::load(&cli.tasks_dir.join(&entry.state_file))
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+
+    let summary = state.get_summary();
+    println!(
+        "{}: {} files, {} completed, {} failed, {} blocked, {} skipped, {} remaining",
+        task_id,
+        summary.total,
+        summary.completed,
+        summary.failed,
+        summary.blocked,
+        summary.skipped,
+        summary.total - summary.completed - summary.failed - summary.blocked - summary.skipped
+    );
+
+    let stats = phase_stats(&state);
+    if stats.is_empty() {
+        println!("No timeline recorded yet");
+        return Ok(());
+    }
+
+    println!();
+    println!("Time per phase:");
+    for (status, stats) in &stats {
+        println!(
+            "   {:<22} {:>4} runs  avg {:>7}  total {:>7}",
+            status.as_str(),
+            stats.count,
+            format_duration(stats.average()),
+            format_duration(stats.total)
+        );
+    }
+
+    println!();
+    println!("Slowest files:");
+    for (path, time) in slowest_files(&state, top) {
+        println!(
+            "   {:>7}  {:<22} {}",
+            format_duration(time),
+            state.files[&path].status.as_str(),
+            path.display()
+        );
+    }
+
+    println!();
+    println!("Throughput:");
+    for (hour, counts) in hourly_throughput(&state) {
+        println!(
+            "   {}  {:>4} completed  {:>4} failed",
+            hour.format("%Y-%m-%d %H:00 UTC"),
+            counts.completed,
+            counts.failed
+        );
+    }
+    Ok(())
+}
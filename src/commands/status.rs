@@ -0,0 +1,221 @@
+use crate::attempts;
+use crate::cli::Cli;
+use crate::state::State;
+use crate::task_list::TaskList;
+use crate::timeline::{busy_time, format_duration, phases};
+use anyhow::{Context, Result};
+use chrono::TimeDelta;
+use std::fs;
+use std::path::Path;
+
+/// Run the `status` command
+pub fn run(cli: &Cli, task_id: Option<&str>, file: Option<&Path>, show_diffs: bool) -> Result<()> {
+    let task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+
+    let Some(task_id) = task_id else {
+        return list_tasks(cli, &task_list);
+    };
+
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?;
+    let state_path = cli.tasks_dir.join(&entry.state_file);
+    let state = State::load(&state_path)
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+
+    match file {
+        Some(file) => show_file(&state, &attempts::artifacts_dir(&state_path), file, show_diffs),
+        None => {
+            show_task(task_id, &state);
+            Ok(())
+        }
+    }
+}
+
+fn list_tasks(cli: &Cli, task_list: &TaskList) -> Result<()> {
+    let mut ids: Vec<_> = task_list.tasks.keys().collect();
+    ids.sort_by_key(|id| {
+        id.strip_prefix("task_")
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(u32::MAX)
+    });
+
+    for id in ids {
+        let entry = &task_list.tasks[id];
+        let counts = match State::load(&cli.tasks_dir.join(&entry.state_file)) {
+            Ok(state) => {
+                let s = state.get_summary();
+                format!(
+                    "{} files, {} completed, {} failed, {} remaining",
+                    s.total,
+                    s.completed,
+                    s.failed,
+                    s.total - s.completed - s.failed
+                )
+            }
+            Err(_) => "state unavailable".to_string(),
+        };
+        println!(
+            "{:<10} {:<10} {}  {}",
+            id,
+            if entry.completed {
+                "completed"
+            } else if entry.paused_for_budget {
+                "paused"
+            } else {
+                "incomplete"
+            },
+            counts,
+            match entry.cloned_from {
+                Some(ref source) => format!(
+                    "{} (cloned from {})",
+                    entry.description.as_deref().unwrap_or(""),
+                    source
+                ),
+                None => entry.description.clone().unwrap_or_default(),
+            }
+        );
+    }
+    Ok(())
+}
+
+fn show_task(task_id: &str, state: &State) {
+    let summary = state.get_summary();
+    println!(
+        "{}: {} files, {} completed, {} failed, {} blocked, {} pending",
+        task_id, summary.total, summary.completed, summary.failed, summary.blocked, summary.pending
+    );
+    if !state.usage.is_empty() {
+        println!(
+            "usage: ${:.4}, {} tokens, {} agent calls, {:.0}s",
+            state.usage.cost_usd,
+            state.usage.tokens(),
+            state.usage.agent_calls,
+            state.usage.wall_secs
+        );
+    }
+    if let Some(ref reason) = state.budget_paused {
+        println!("paused for budget: {}", reason);
+    }
+    if !state.archived.is_empty() {
+        println!("archived: {} files no longer in the input", state.archived.len());
+    }
+    println!();
+
+    let mut paths: Vec<_> = state.files.keys().collect();
+    paths.sort();
+    for path in paths {
+        let file = &state.files[path];
+        let stat = file.total_diffstat();
+        let time = busy_time(&file.timeline);
+        println!(
+            "{:<22} {:>2} runs  {:>3} files  +{:<5} -{:<5} {:>7}  {}",
+            file.status.as_str(),
+            file.attempt_log.len(),
+            stat.files_changed,
+            stat.insertions,
+            stat.deletions,
+            if time > TimeDelta::zero() { format_duration(time) } else { "-".to_string() },
+            path.display()
+        );
+    }
+}
+
+fn show_file(state: &State, artifacts_dir: &Path, file: &Path, show_diffs: bool) -> Result<()> {
+    let file_state = state
+        .files
+        .get(file)
+        .ok_or_else(|| anyhow::anyhow!("File not in task: {}", file.display()))?;
+
+    println!("{} ({})", file.display(), file_state.status.as_str());
+    if let Some(ref error) = file_state.last_error {
+        println!("last error: {}", error.lines().next().unwrap_or_default());
+    }
+    if let Some(ref task_id) = file_state.cached_from {
+        println!("result reused from {} (unchanged since that run)", task_id);
+    }
+    if let Some(ref lease) = file_state.lease {
+        println!(
+            "leased by {} (heartbeat {}, {} {})",
+            lease.owner,
+            lease.heartbeat.format("%H:%M:%S UTC"),
+            if lease.is_expired() { "expired" } else { "expires" },
+            lease.expires_at.format("%H:%M:%S UTC")
+        );
+    }
+    if let Some(ref process) = file_state.process {
+        let pids: Vec<String> = process.children.iter().map(|c| c.pid.to_string()).collect();
+        println!(
+            "running as pid {} on {} (runner pid {})",
+            pids.join(", "),
+            process.host,
+            process.runner.pid
+        );
+    }
+    for stage in &state.config.pipeline {
+        let Some(stage_state) = file_state.stages.get(&stage.name) else {
+            println!("   stage {:<16} pending", stage.name);
+            continue;
+        };
+        println!(
+            "   stage {:<16} {:<8} {} failed runs{}",
+            stage.name,
+            stage_state.status.as_str(),
+            stage_state.attempts,
+            stage_state
+                .last_error
+                .as_deref()
+                .map(|e| format!("  ({})", e))
+                .unwrap_or_default()
+        );
+    }
+
+    if !file_state.timeline.is_empty() {
+        println!();
+        println!("timeline:");
+        let ended = phases(&file_state.timeline);
+        for (i, event) in file_state.timeline.iter().enumerate() {
+            println!(
+                "   {}  {:<22} {:<9} attempt {:<3} {:>7}{}",
+                event.at.format("%Y-%m-%d %H:%M:%S UTC"),
+                event.status.as_str(),
+                event.worker.map(|w| format!("worker {}", w)).unwrap_or_default(),
+                event.attempt,
+                ended.get(i).map(|p| format_duration(p.duration)).unwrap_or_default(),
+                event
+                    .outcome
+                    .as_deref()
+                    .map(|o| format!("  ({})", o))
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    for attempt in &file_state.attempt_log {
+        println!();
+        println!(
+            "#{} {:<6} {}  {} files  +{} -{}",
+            attempt.number,
+            attempt.kind.as_str(),
+            attempt.finished_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            attempt.diffstat.files_changed,
+            attempt.diffstat.insertions,
+            attempt.diffstat.deletions
+        );
+        if let Some(ref transcript) = attempt.transcript_file {
+            println!("   transcript: {}", artifacts_dir.join(transcript).display());
+        }
+        if let Some(ref diff_file) = attempt.diff_file {
+            let diff_path = artifacts_dir.join(diff_file);
+            if show_diffs {
+                let diff = fs::read_to_string(&diff_path)
+                    .with_context(|| format!("Failed to read diff: {}", diff_path.display()))?;
+                println!();
+                print!("{}", diff);
+            } else {
+                println!("   diff: {}", diff_path.display());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use crate::cli::Cli;
+use crate::config::RemovedFilePolicy;
+use crate::control;
+use crate::state::{read_input, State, SyncReport};
+use crate::task_list::TaskList;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Run the `sync` command: bring a stopped task in line with its input file
+pub fn run(
+    cli: &Cli,
+    task_id: &str,
+    input: Option<&Path>,
+    removed: RemovedFilePolicy,
+    reset_changed: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let mut task_list = TaskList::load_or_create(&cli.tasks_dir)?;
+    let entry = task_list
+        .get_task(task_id)
+        .ok_or_else(|| anyhow::anyhow!("Task not found: {}", task_id))?
+        .clone();
+
+    let state_path = cli.tasks_dir.join(&entry.state_file);
+    // A running task would put removed files back on its next save
+    if !dry_run && control::control_path(&state_path).exists() {
+        anyhow::bail!(
+            "Task {} is running; stop it first, or resume it with --resync",
+            task_id
+        );
+    }
+
+    let mut state = State::load(&state_path)
+        .with_context(|| format!("Failed to load state for task: {}", task_id))?;
+    if let Some(input) = input {
+        state.config_mut().input_file = input.to_path_buf();
+    }
+    let input_path = state.config.input_file.clone();
+
+    let report = if dry_run {
+        let input = read_input(&input_path)?;
+        state.clone().apply_input(input, removed, reset_changed)
+    } else {
+        state.sync_input(
+            &state_path,
+            &input_path,
+            removed,
+            reset_changed,
+            &entry.working_dir,
+        )?
+    };
+
+    print_report(&report, removed);
+    println!(
+        "{}{}: {} added, {} removed, {} changed, {} reset to pending",
+        if dry_run { "(dry run) " } else { "" },
+        task_id,
+        report.added.len(),
+        report.removed.len(),
+        report.changed.len(),
+        report.reset.len()
+    );
+
+    if !dry_run && entry.completed && !(report.added.is_empty() && report.reset.is_empty()) {
+        task_list.mark_incomplete(task_id);
+        task_list.save(&cli.tasks_dir)?;
+    }
+    Ok(())
+}
+
+fn print_report(report: &SyncReport, removed: RemovedFilePolicy) {
+    for path in &report.added {
+        println!("added    {}", path.display());
+    }
+    for path in &report.removed {
+        println!(
+            "removed  {}{}",
+            path.display(),
+            if removed == RemovedFilePolicy::Archive {
+                " (archived)"
+            } else {
+                ""
+            }
+        );
+    }
+    for path in &report.changed {
+        println!(
+            "changed  {}{}",
+            path.display(),
+            if report.reset.contains(path) {
+                " (reset to pending)"
+            } else {
+                ""
+            }
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use crate::bundle;
+use crate::cli::Cli;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Run the `unpack` command: add a bundled task to the tasks directory
+pub fn run(cli: &Cli, bundle_path: &Path, working_dir: Option<&Path>) -> Result<()> {
+    let working_dir = match working_dir {
+        Some(dir) => std::path::absolute(dir)
+            .with_context(|| format!("Invalid working directory: {}", dir.display()))?,
+        None => std::env::current_dir().context("Failed to get current directory")?,
+    };
+    let (task_id, manifest) = bundle::unpack(bundle_path, &cli.tasks_dir, &working_dir)?;
+    println!(
+        "Unpacked {} (packed from {} in {}) as {} in {}",
+        bundle_path.display(),
+        manifest.task_id,
+        manifest.working_dir.display(),
+        task_id,
+        working_dir.display()
+    );
+    if !manifest.completed {
+        println!("To run it, use: claude-loop-runner --resume {}", task_id);
+    }
+    Ok(())
+}
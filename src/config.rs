@@ -1,6 +1,65 @@
+use crate::batch::BatchBy;
 use crate::cli::Cli;
+use crate::ordering::OrderStrategy;
+use crate::pipeline::{load_pipeline, StageConfig};
+use crate::verify_groups::VerifyGroup;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
+
+/// What to do when the agent edits a file that was already dirty before the run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DirtyFilePolicy {
+    /// Log the edit as an unauthorized change
+    #[default]
+    Report,
+    /// Log the edit and restore the content captured before the run
+    Revert,
+}
+
+/// What to do on resume with processes a crashed run left working on files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanPolicy {
+    /// Ask on the terminal; leave them running if there is no terminal
+    #[default]
+    Ask,
+    /// Kill them (and their children) before requeueing the files
+    Kill,
+    /// Leave them running and don't requeue their files in this run
+    Leave,
+}
+
+/// What to do on resume with edits an agent run that never finished left behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialEditPolicy {
+    /// Requeue the file with the edits in place
+    #[default]
+    Keep,
+    /// Restore the file's scope to how it was before the run
+    Revert,
+}
+
+/// What a sync with the input file does with files no longer listed in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RemovedFilePolicy {
+    /// Move them to the task's archive, restored if they come back to the input
+    #[default]
+    Archive,
+    /// Drop them from the task
+    Remove,
+}
+
+/// Recovery of files left in progress by a run that crashed or was killed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RecoveryConfig {
+    #[serde(default)]
+    pub orphans: OrphanPolicy,
+    #[serde(default)]
+    pub partial_edits: PartialEditPolicy,
+}
 
 /// Git-related configuration
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -17,6 +76,182 @@ pub struct GitConfig {
     /// Custom commit message template (supports {file}, {file_stem}, {task_id})
     #[serde(skip_serializing_if = "Option::is_none")]
     pub commit_message_template: Option<String>,
+    /// Squash the task's per-file commits into a single commit when finalizing
+    #[serde(default)]
+    pub squash_on_finalize: bool,
+    /// Remote to push the task branch to when finalizing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_remote: Option<String>,
+    /// Finalize automatically when the task completes
+    #[serde(default)]
+    pub auto_finalize: bool,
+    /// How to handle edits to files that were dirty before the run
+    #[serde(default)]
+    pub dirty_file_policy: DirtyFilePolicy,
+}
+
+/// What happens to in-flight work when a task budget runs out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPolicy {
+    /// Let running Claude calls finish, but start no new prompts or fixups
+    #[default]
+    Finish,
+    /// Kill running Claude calls and return their files to the queue
+    Abort,
+}
+
+/// Spending limits (all optional)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetLimits {
+    /// Maximum total cost in USD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cost_usd: Option<f64>,
+    /// Maximum input + output tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// Maximum wall-clock seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_wall_secs: Option<u64>,
+    /// Maximum number of Claude invocations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_agent_calls: Option<u32>,
+}
+
+/// Budgets for the whole task and for each file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BudgetConfig {
+    /// Limits across the task (wall time is total run time across resumes)
+    #[serde(default)]
+    pub task: BudgetLimits,
+    /// Limits per file (wall time is time spent in Claude); a file over budget fails
+    #[serde(default)]
+    pub file: BudgetLimits,
+    /// What to do with in-flight work when the task budget runs out
+    #[serde(default)]
+    pub policy: BudgetPolicy,
+}
+
+/// How failed Claude runs and fixups are retried.
+/// Verification failures are limited separately by `Config::max_retries`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Retries when Claude fails to launch, exits non-zero or reports an error
+    #[serde(default)]
+    pub agent_errors: u32,
+    /// Retries when a Claude run times out
+    #[serde(default)]
+    pub agent_timeouts: u32,
+    /// Seconds before a Claude run is killed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_timeout_secs: Option<u64>,
+    /// Delay before the first retry, doubling on each retry after (0 disables backoff)
+    #[serde(default)]
+    pub backoff_secs: u64,
+    /// Upper bound on the backoff delay
+    #[serde(default = "default_backoff_max_secs")]
+    pub backoff_max_secs: u64,
+    /// Revert the file's scope and rerun the original prompt after this many failed fixups in a row
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fresh_start_after: Option<u32>,
+}
+
+fn default_backoff_max_secs() -> u64 {
+    300
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            agent_errors: 0,
+            agent_timeouts: 0,
+            agent_timeout_secs: None,
+            backoff_secs: 0,
+            backoff_max_secs: default_backoff_max_secs(),
+            fresh_start_after: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry (1-based)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        if self.backoff_secs == 0 || retry == 0 {
+            return Duration::ZERO;
+        }
+        let secs = self
+            .backoff_secs
+            .saturating_mul(1u64 << (retry - 1).min(32))
+            .min(self.backoff_max_secs.max(self.backoff_secs));
+        Duration::from_secs(secs)
+    }
+
+    pub fn agent_timeout(&self) -> Option<Duration> {
+        self.agent_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Apply retry flags over saved values
+    fn merge_with_cli(mut self, cli: &Cli) -> Self {
+        self.agent_errors = cli.agent_retries.unwrap_or(self.agent_errors);
+        self.agent_timeouts = cli.agent_timeout_retries.unwrap_or(self.agent_timeouts);
+        self.agent_timeout_secs = cli.agent_timeout.or(self.agent_timeout_secs);
+        self.backoff_secs = cli.retry_backoff.unwrap_or(self.backoff_secs);
+        self.backoff_max_secs = cli.retry_backoff_max.unwrap_or(self.backoff_max_secs);
+        self.fresh_start_after = cli.fresh_start_after.or(self.fresh_start_after);
+        self
+    }
+}
+
+/// Grouping several files into one prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchConfig {
+    /// Maximum files per prompt (1 disables batching)
+    pub size: usize,
+    /// How files are grouped
+    #[serde(default)]
+    pub by: BatchBy,
+    /// Byte budget per batch when grouping by size
+    pub max_bytes: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            size: 1,
+            by: BatchBy::default(),
+            max_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn enabled(&self) -> bool {
+        self.size > 1
+    }
+}
+
+/// Verifying several files with one command run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyBatchConfig {
+    /// Maximum files per verify run (1 disables batching)
+    pub size: usize,
+    /// Seconds to wait for more files before running a partial batch
+    pub wait_secs: u64,
+}
+
+impl Default for VerifyBatchConfig {
+    fn default() -> Self {
+        Self {
+            size: 1,
+            wait_secs: 30,
+        }
+    }
+}
+
+impl VerifyBatchConfig {
+    pub fn enabled(&self) -> bool {
+        self.size > 1
+    }
 }
 
 /// Configuration for the runner, persisted in state file
@@ -32,6 +267,9 @@ pub struct Config {
     /// Verification command template
     #[serde(skip_serializing_if = "Option::is_none")]
     pub verification_cmd: Option<String>,
+    /// Rubric for reviewing each file's diff once verification passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review_rubric: Option<String>,
     /// File allowlist pattern
     pub allowlist_pattern: String,
     /// Number of workers for prompt pool
@@ -39,52 +277,142 @@ pub struct Config {
     /// Number of workers for verify pool (defaults to concurrency if not set)
     #[serde(default)]
     pub verify_concurrency: Option<usize>,
+    /// Resize pools automatically based on resource and verify signals
+    #[serde(default)]
+    pub auto_concurrency: bool,
+    /// Upper bound for automatic resizing (defaults to concurrency)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+    /// Verification command timeout in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_timeout_secs: Option<u64>,
+    /// Named groups limiting how many verifications run at once
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub verify_groups: Vec<VerifyGroup>,
+    /// Batched verification
+    #[serde(default)]
+    pub verify_batch: VerifyBatchConfig,
     /// Maximum files to process
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_files: Option<usize>,
+    /// Order in which files are queued and selected for --max-files
+    #[serde(default)]
+    pub order: OrderStrategy,
+    /// Infer file dependencies from relative imports
+    #[serde(default)]
+    pub infer_dependencies: bool,
+    /// Skip files unchanged since a completed run in any task, reusing its result
+    #[serde(default)]
+    pub incremental: bool,
     /// Maximum fixup retry attempts
     pub max_retries: u32,
+    /// Agent retries, backoff and fresh starts
+    #[serde(default)]
+    pub retry: RetryPolicy,
     /// Git configuration
     #[serde(default)]
     pub git: GitConfig,
+    /// Prompt batching
+    #[serde(default)]
+    pub batch: BatchConfig,
+    /// Cost, token, time and call budgets
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    /// User-defined stages replacing prompt -> verify -> fixup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<StageConfig>,
+    /// Seconds a runner's lease on a file lasts between heartbeats
+    #[serde(default = "default_lease_ttl_secs")]
+    pub lease_ttl_secs: u64,
+    /// Handling of files a crashed run left in progress
+    #[serde(default)]
+    pub recovery: RecoveryConfig,
+}
+
+fn default_lease_ttl_secs() -> u64 {
+    120
 }
 
 impl Config {
+    /// Whether each file's scope is saved before its first prompt (for fresh starts and reviews)
+    pub fn saves_original_scope(&self) -> bool {
+        self.retry.fresh_start_after.is_some() || self.review_rubric.is_some()
+    }
+
     /// Create a new config from CLI arguments
     pub fn from_cli(cli: &Cli) -> anyhow::Result<Self> {
         let input_file = cli
             .input
             .clone()
             .ok_or_else(|| anyhow::anyhow!("--input is required"))?;
-        let prompt = cli
-            .prompt
-            .clone()
-            .ok_or_else(|| anyhow::anyhow!("--prompt is required"))?;
+        let pipeline = match cli.pipeline {
+            Some(ref path) => load_pipeline(path)?,
+            None => Vec::new(),
+        };
+        // A pipeline's stages carry their own prompts
+        let prompt = match cli.prompt {
+            Some(ref prompt) => prompt.clone(),
+            None if !pipeline.is_empty() => String::new(),
+            None => anyhow::bail!("--prompt is required"),
+        };
 
         let git = GitConfig {
-            enabled: cli.git,
+            enabled: cli.git_implied(),
             auto_branch: cli.git_branch,
             auto_commit: cli.git_commit,
             commit_message_template: cli.git_commit_message.clone(),
+            squash_on_finalize: cli.git_squash,
+            push_remote: cli.git_push.clone(),
+            auto_finalize: cli.git_finalize,
+            dirty_file_policy: cli.dirty_files.unwrap_or_default(),
         };
 
-        Ok(Self {
+        let config = Self {
             input_file,
             prompt,
             fixup_prompt: cli.fixup.clone(),
             verification_cmd: cli.verify.clone(),
+            review_rubric: cli.review.clone(),
             allowlist_pattern: cli.allowlist.clone(),
             concurrency: cli.concurrency,
             verify_concurrency: cli.verify_concurrency,
+            auto_concurrency: cli.auto_concurrency,
+            max_concurrency: cli.max_concurrency,
+            verify_timeout_secs: cli.verify_timeout,
+            verify_groups: cli.verify_groups.clone(),
+            verify_batch: VerifyBatchConfig {
+                size: cli.verify_batch_size.unwrap_or(1),
+                wait_secs: cli
+                    .verify_batch_wait
+                    .unwrap_or(VerifyBatchConfig::default().wait_secs),
+            },
             max_files: cli.max_files,
+            order: cli.order.unwrap_or_default(),
+            infer_dependencies: cli.infer_deps,
+            incremental: cli.incremental,
             max_retries: cli.max_retries,
+            retry: RetryPolicy::default().merge_with_cli(cli),
             git,
-        })
+            batch: BatchConfig {
+                size: cli.batch_size.unwrap_or(1),
+                by: cli.batch_by.unwrap_or_default(),
+                max_bytes: cli.batch_max_bytes.unwrap_or(BatchConfig::default().max_bytes),
+            },
+            budget: BudgetConfig::default().merge_with_cli(cli),
+            pipeline,
+            lease_ttl_secs: cli.lease_ttl.unwrap_or_else(default_lease_ttl_secs),
+            recovery: RecoveryConfig {
+                orphans: cli.orphans.unwrap_or_default(),
+                partial_edits: cli.partial_edits.unwrap_or_default(),
+            },
+        };
+        config.check()?;
+        Ok(config)
     }
 
     /// Merge CLI args over saved config
     /// CLI args win if explicitly provided
-    pub fn merge_with_cli(mut self, cli: &Cli) -> Self {
+    pub fn merge_with_cli(mut self, cli: &Cli) -> anyhow::Result<Self> {
         if let Some(ref input) = cli.input {
             self.input_file = input.clone();
         }
@@ -97,6 +425,9 @@ impl Config {
         if let Some(ref verify) = cli.verify {
             self.verification_cmd = Some(verify.clone());
         }
+        if let Some(ref review) = cli.review {
+            self.review_rubric = Some(review.clone());
+        }
         // Only override allowlist if not default
         if cli.allowlist != "{file_stem}*" {
             self.allowlist_pattern = cli.allowlist.clone();
@@ -109,13 +440,41 @@ impl Config {
         if cli.verify_concurrency.is_some() {
             self.verify_concurrency = cli.verify_concurrency;
         }
+        if cli.auto_concurrency {
+            self.auto_concurrency = true;
+        }
+        if cli.max_concurrency.is_some() {
+            self.max_concurrency = cli.max_concurrency;
+        }
+        if cli.verify_timeout.is_some() {
+            self.verify_timeout_secs = cli.verify_timeout;
+        }
+        if !cli.verify_groups.is_empty() {
+            self.verify_groups = cli.verify_groups.clone();
+        }
+        if let Some(size) = cli.verify_batch_size {
+            self.verify_batch.size = size;
+        }
+        if let Some(wait) = cli.verify_batch_wait {
+            self.verify_batch.wait_secs = wait;
+        }
         if let Some(max_files) = cli.max_files {
             self.max_files = Some(max_files);
         }
+        if let Some(order) = cli.order {
+            self.order = order;
+        }
+        if cli.infer_deps {
+            self.infer_dependencies = true;
+        }
+        if cli.incremental {
+            self.incremental = true;
+        }
         // Only override max_retries if not default
         if cli.max_retries != 3 {
             self.max_retries = cli.max_retries;
         }
+        self.retry = self.retry.merge_with_cli(cli);
         // Override git settings if explicitly enabled
         if cli.git {
             self.git.enabled = true;
@@ -129,6 +488,113 @@ impl Config {
         if let Some(ref msg) = cli.git_commit_message {
             self.git.commit_message_template = Some(msg.clone());
         }
+        if cli.git_squash {
+            self.git.enabled = true;
+            self.git.squash_on_finalize = true;
+        }
+        if let Some(ref remote) = cli.git_push {
+            self.git.enabled = true;
+            self.git.push_remote = Some(remote.clone());
+        }
+        if cli.git_finalize {
+            self.git.enabled = true;
+            self.git.auto_finalize = true;
+        }
+        if let Some(policy) = cli.dirty_files {
+            self.git.dirty_file_policy = policy;
+        }
+        if let Some(size) = cli.batch_size {
+            self.batch.size = size;
+        }
+        if let Some(by) = cli.batch_by {
+            self.batch.by = by;
+        }
+        if let Some(max_bytes) = cli.batch_max_bytes {
+            self.batch.max_bytes = max_bytes;
+        }
+        self.budget = self.budget.merge_with_cli(cli);
+        if let Some(ref path) = cli.pipeline {
+            self.pipeline = load_pipeline(path)?;
+        }
+        if let Some(ttl) = cli.lease_ttl {
+            self.lease_ttl_secs = ttl;
+        }
+        if let Some(orphans) = cli.orphans {
+            self.recovery.orphans = orphans;
+        }
+        if let Some(partial_edits) = cli.partial_edits {
+            self.recovery.partial_edits = partial_edits;
+        }
+        self.check()?;
+        Ok(self)
+    }
+
+    /// Reject combinations of settings where one would be silently ignored
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.review_rubric.is_some() {
+            if !self.pipeline.is_empty() {
+                anyhow::bail!("--review doesn't apply to --pipeline; review in a prompt stage instead");
+            }
+            if self.verification_cmd.is_none() {
+                anyhow::bail!("--review needs --verify: files are reviewed once verification passes");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BudgetConfig {
+    /// Apply budget flags over saved values (used to raise a limit before resuming)
+    fn merge_with_cli(mut self, cli: &Cli) -> Self {
+        let task = &mut self.task;
+        task.max_cost_usd = cli.max_cost.or(task.max_cost_usd);
+        task.max_tokens = cli.max_tokens.or(task.max_tokens);
+        task.max_wall_secs = cli.max_wall_time.or(task.max_wall_secs);
+        task.max_agent_calls = cli.max_agent_calls.or(task.max_agent_calls);
+
+        let file = &mut self.file;
+        file.max_cost_usd = cli.file_max_cost.or(file.max_cost_usd);
+        file.max_tokens = cli.file_max_tokens.or(file.max_tokens);
+        file.max_wall_secs = cli.file_max_wall_time.or(file.max_wall_secs);
+        file.max_agent_calls = cli.file_max_agent_calls.or(file.max_agent_calls);
+
+        if let Some(policy) = cli.budget_policy {
+            self.policy = policy;
+        }
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            backoff_secs: 5,
+            backoff_max_secs: 30,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (1..=5).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+        assert_eq!(policy.backoff(100), Duration::from_secs(30));
+        assert_eq!(RetryPolicy::default().backoff(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_review_requires_verify() {
+        use clap::Parser;
+        let parse = |args: &[&str]| {
+            let base = ["claude-loop-runner", "-i", "in.json", "-p", "do it"];
+            Config::from_cli(&Cli::parse_from(base.iter().chain(args)))
+        };
+        assert!(parse(&["--review", "be strict"]).is_err());
+        assert!(parse(&["--review", "be strict", "-v", "npm test"]).is_ok());
+
+        // Adding a review when resuming a task without verification is rejected too
+        let config = parse(&[]).unwrap();
+        let resume = Cli::parse_from(["claude-loop-runner", "--resume", "--review", "be strict"]);
+        assert!(config.merge_with_cli(&resume).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::state::State;
+use anyhow::{Context, Result};
+use async_channel::Sender;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, error, info, warn};
+
+/// Workers a pool can grow to at runtime when the configured concurrency is lower
+pub const MIN_POOL_CEILING: usize = 32;
+
+/// A command sent to a running task through its control file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, clap::Subcommand)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// Pause all workers after their current file
+    Pause,
+    /// Resume paused workers
+    Resume,
+    /// Change the number of active prompt and/or verify workers
+    Concurrency {
+        /// Active prompt workers
+        #[arg(long)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        prompt: Option<usize>,
+        /// Active verify workers
+        #[arg(long)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        verify: Option<usize>,
+    },
+    /// Skip a file that hasn't started (or is waiting in the queue)
+    Skip {
+        /// File path as it appears in the input
+        file: PathBuf,
+    },
+    /// Reset a finished, failed, blocked or skipped file and queue it again
+    Requeue {
+        /// File path as it appears in the input
+        file: PathBuf,
+    },
+    /// Rewrite the state file now, folding in the journal
+    Save,
+}
+
+/// Path of the control file for a task (next to its state file)
+pub fn control_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("control")
+}
+
+/// Append a command to a task's control file (used by the `ctl` subcommand)
+pub fn send_command(control_path: &Path, command: &ControlCommand) -> Result<()> {
+    let line = serde_json::to_string(command).context("Failed to serialize control command")?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(control_path)
+        .with_context(|| format!("Failed to open control file: {}", control_path.display()))?;
+    writeln!(file, "{}", line)
+        .with_context(|| format!("Failed to write control file: {}", control_path.display()))
+}
+
+/// Limits how many workers of a pool are active; the limit can change while running
+pub struct PoolGate {
+    limit: AtomicUsize,
+    active: AtomicUsize,
+    ceiling: usize,
+    notify: Notify,
+}
+
+/// An active slot in a pool, released on drop
+pub struct GatePermit {
+    gate: Arc<PoolGate>,
+}
+
+impl Drop for GatePermit {
+    fn drop(&mut self) {
+        self.gate.active.fetch_sub(1, Ordering::SeqCst);
+        self.gate.notify.notify_waiters();
+    }
+}
+
+impl PoolGate {
+    /// Create a gate allowing `limit` active workers, resizable up to `ceiling`
+    pub fn new(limit: usize, ceiling: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit: AtomicUsize::new(limit.clamp(1, ceiling)),
+            active: AtomicUsize::new(0),
+            ceiling,
+            notify: Notify::new(),
+        })
+    }
+
+    /// Most workers the pool can grow to
+    pub fn ceiling(&self) -> usize {
+        self.ceiling
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Change the number of active workers (clamped to 1..=ceiling); returns the new limit.
+    /// Lowering the limit takes effect as active workers finish their current file.
+    pub fn set_limit(&self, limit: usize) -> usize {
+        let limit = limit.clamp(1, self.ceiling);
+        self.limit.store(limit, Ordering::SeqCst);
+        self.notify.notify_waiters();
+        limit
+    }
+
+    /// Wait until the limit is above `count`
+    pub async fn wait_for_limit_above(&self, count: usize) {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.limit() > count {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Wait for a free slot
+    pub async fn acquire(self: &Arc<Self>) -> GatePermit {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let active = self.active.load(Ordering::SeqCst);
+            if active < self.limit()
+                && self
+                    .active
+                    .compare_exchange(active, active + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            {
+                return GatePermit {
+                    gate: Arc::clone(self),
+                };
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Cheaply cloneable handle for workers to check the operator's pause state and pool sizes
+#[derive(Clone)]
+pub struct ControlHandle {
+    paused: Arc<AtomicBool>,
+    resume_notify: Arc<Notify>,
+    pub prompt_gate: Arc<PoolGate>,
+    pub verify_gate: Arc<PoolGate>,
+}
+
+impl ControlHandle {
+    /// Gates starting at the given sizes, able to grow to at least `max_concurrency`
+    pub fn new(prompt_concurrency: usize, verify_concurrency: usize, max_concurrency: usize) -> Self {
+        Self {
+            paused: Arc::new(AtomicBool::new(false)),
+            resume_notify: Arc::new(Notify::new()),
+            prompt_gate: PoolGate::new(
+                prompt_concurrency,
+                prompt_concurrency.max(max_concurrency).max(MIN_POOL_CEILING),
+            ),
+            verify_gate: PoolGate::new(
+                verify_concurrency,
+                verify_concurrency.max(max_concurrency).max(MIN_POOL_CEILING),
+            ),
+        }
+    }
+
+    /// Check if workers were paused by the operator
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait until not paused (returns immediately if not paused)
+    pub async fn wait_if_paused(&self) {
+        loop {
+            // Register before checking so a resume in between isn't missed
+            let notified = self.resume_notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if !self.is_paused() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+        if !paused {
+            self.resume_notify.notify_waiters();
+        }
+    }
+}
+
+/// Commands that need the dispatcher (they change which files are queued)
+#[derive(Debug, Clone)]
+pub enum DispatchCommand {
+    Skip(PathBuf),
+    Requeue(PathBuf),
+}
+
+/// Watch the control file and apply commands as they are appended.
+/// The file is truncated at startup so commands from earlier runs aren't replayed.
+pub fn spawn_control_listener(
+    path: PathBuf,
+    handle: ControlHandle,
+    state: Arc<Mutex<State>>,
+    state_path: PathBuf,
+    dispatch_tx: Sender<DispatchCommand>,
+) -> Result<tokio::task::JoinHandle<()>> {
+    // Other runners on the same task may be reading the file already, so it is only
+    // created here; commands written before this runner started are skipped
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to create control file: {}", path.display()))?;
+    let start = file.metadata().map(|m| m.len()).unwrap_or_default();
+    info!(path = %path.display(), "Listening for control commands");
+
+    Ok(tokio::spawn(async move {
+        let mut offset = start;
+        let mut partial = String::new();
+
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let chunk = match read_from(&path, &mut offset).await {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    debug!(error = %e, "Failed to read control file");
+                    continue;
+                }
+            };
+            partial.push_str(&String::from_utf8_lossy(&chunk));
+
+            // Only complete lines are parsed; a trailing partial line waits for the next poll
+            while let Some(newline) = partial.find('\n') {
+                let line: String = partial.drain(..=newline).collect();
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<ControlCommand>(line) {
+                    Ok(command) => {
+                        apply(command, &handle, &state, &state_path, &dispatch_tx).await
+                    }
+                    Err(e) => warn!(line = %line, error = %e, "Ignoring invalid control command"),
+                }
+            }
+        }
+    }))
+}
+
+/// Read what was appended since `offset` and advance it. A file shorter than `offset`
+/// was removed and recreated (by a runner sharing the task exiting), so it's read from the start.
+async fn read_from(path: &Path, offset: &mut u64) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    if file.metadata().await?.len() < *offset {
+        *offset = 0;
+    }
+    file.seek(std::io::SeekFrom::Start(*offset)).await?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).await?;
+    *offset += buf.len() as u64;
+    Ok(buf)
+}
+
+async fn apply(
+    command: ControlCommand,
+    handle: &ControlHandle,
+    state: &Arc<Mutex<State>>,
+    state_path: &Path,
+    dispatch_tx: &Sender<DispatchCommand>,
+) {
+    match command {
+        ControlCommand::Pause => {
+            handle.set_paused(true);
+            info!("Paused by control command, workers will stop after their current file");
+        }
+        ControlCommand::Resume => {
+            handle.set_paused(false);
+            info!("Resumed by control command");
+        }
+        ControlCommand::Concurrency { prompt, verify } => {
+            if let Some(n) = prompt {
+                let limit = handle.prompt_gate.set_limit(n);
+                info!(requested = n, prompt_concurrency = limit, "Prompt concurrency changed");
+            }
+            if let Some(n) = verify {
+                let limit = handle.verify_gate.set_limit(n);
+                info!(requested = n, verify_concurrency = limit, "Verify concurrency changed");
+            }
+        }
+        ControlCommand::Skip { file } => {
+            let _ = dispatch_tx.send(DispatchCommand::Skip(file)).await;
+        }
+        ControlCommand::Requeue { file } => {
+            let _ = dispatch_tx.send(DispatchCommand::Requeue(file)).await;
+        }
+        ControlCommand::Save => {
+            let mut state = state.lock().await;
+            match state.compact(state_path) {
+                Ok(()) => info!("State saved by control command"),
+                Err(e) => error!(error = %e, "Failed to save state"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pool_gate_resize() {
+        let gate = PoolGate::new(1, 4);
+        let first = gate.acquire().await;
+
+        // Second acquire must wait until the limit is raised
+        let waiting = tokio::spawn({
+            let gate = Arc::clone(&gate);
+            async move {
+                let _permit = gate.acquire().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        assert_eq!(gate.set_limit(10), 4);
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(first);
+        assert_eq!(gate.active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_control_command_round_trip() {
+        let command = ControlCommand::Concurrency {
+            prompt: Some(3),
+            verify: None,
+        };
+        let line = serde_json::to_string(&command).unwrap();
+        assert_eq!(line, r#"{"command":"concurrency","prompt":3}"#);
+        assert_eq!(serde_json::from_str::<ControlCommand>(&line).unwrap(), command);
+        assert_eq!(
+            serde_json::from_str::<ControlCommand>(r#"{"command":"skip","file":"src/a.ts"}"#)
+                .unwrap(),
+            ControlCommand::Skip {
+                file: PathBuf::from("src/a.ts")
+            }
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use crate::types::{FileState, FileStatus};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+/// Extensions tried when resolving extensionless JS/TS import specifiers
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs"];
+
+/// Dependencies between files in a task.
+///
+/// Declared with `"depends_on": ["path", ...]` in a file's metadata, and optionally
+/// inferred from relative import statements. Only edges to files in the task are kept.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    deps: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// Whether a file can be dispatched yet
+#[derive(Debug, PartialEq, Eq)]
+pub enum Readiness {
+    /// All dependencies are completed
+    Ready,
+    /// Some dependencies haven't finished yet
+    Waiting,
+    /// A dependency failed, was skipped or is itself blocked
+    Blocked(PathBuf),
+}
+
+impl DependencyGraph {
+    /// Build the graph from the files' metadata, optionally inferring edges from imports
+    pub fn build(
+        files: &HashMap<PathBuf, FileState>,
+        infer_imports: bool,
+        working_dir: &Path,
+    ) -> Self {
+        let mut deps: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+
+        for (path, file_state) in files {
+            let mut file_deps = declared_dependencies(&file_state.original_data);
+
+            if infer_imports {
+                if let Ok(content) = std::fs::read_to_string(working_dir.join(path)) {
+                    file_deps.extend(infer_dependencies(path, &content, |p| files.contains_key(p)));
+                }
+            }
+
+            file_deps.retain(|d| d != path && files.contains_key(d));
+            file_deps.sort();
+            file_deps.dedup();
+            if !file_deps.is_empty() {
+                debug!(file = %path.display(), deps = ?file_deps, "File dependencies");
+                deps.insert(path.clone(), file_deps);
+            }
+        }
+
+        Self { deps }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deps.is_empty()
+    }
+
+    /// Number of dependency edges
+    pub fn edge_count(&self) -> usize {
+        self.deps.values().map(Vec::len).sum()
+    }
+
+    /// Check whether a file's dependencies allow it to run
+    pub fn readiness(&self, path: &Path, files: &HashMap<PathBuf, FileState>) -> Readiness {
+        let mut waiting = false;
+        for dep in self.deps.get(path).into_iter().flatten() {
+            match files.get(dep).map(|f| &f.status) {
+                Some(FileStatus::Completed) | None => {}
+                Some(FileStatus::Failed | FileStatus::Blocked | FileStatus::Skipped) => {
+                    return Readiness::Blocked(dep.clone());
+                }
+                Some(_) => waiting = true,
+            }
+        }
+        if waiting {
+            Readiness::Waiting
+        } else {
+            Readiness::Ready
+        }
+    }
+
+    /// Files that are on a dependency cycle or depend on one (they can never become ready)
+    pub fn cyclic_files(&self) -> HashSet<PathBuf> {
+        let mut remaining: HashMap<&PathBuf, HashSet<&PathBuf>> = self
+            .deps
+            .iter()
+            .map(|(path, deps)| (path, deps.iter().filter(|d| self.deps.contains_key(*d)).collect()))
+            .collect();
+
+        // Repeatedly peel off files whose dependencies have all been peeled
+        loop {
+            let free: Vec<&PathBuf> = remaining
+                .iter()
+                .filter(|(_, deps)| deps.is_empty())
+                .map(|(path, _)| *path)
+                .collect();
+            if free.is_empty() {
+                break;
+            }
+            for path in free {
+                remaining.remove(path);
+                for deps in remaining.values_mut() {
+                    deps.remove(path);
+                }
+            }
+        }
+
+        remaining.into_keys().cloned().collect()
+    }
+}
+
+/// Read `depends_on` from a file's metadata (an array of paths or a single path)
+fn declared_dependencies(original_data: &serde_json::Value) -> Vec<PathBuf> {
+    match original_data.get("depends_on") {
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|s| normalize(Path::new(s)))
+            .collect(),
+        Some(serde_json::Value::String(s)) => vec![normalize(Path::new(s))],
+        _ => Vec::new(),
+    }
+}
+
+/// Infer dependencies from relative imports (JS/TS `import`/`require`, Python `from .x import`).
+/// `exists` decides which resolved candidates are real files in the task.
+pub fn infer_dependencies(
+    path: &Path,
+    content: &str,
+    exists: impl Fn(&Path) -> bool,
+) -> Vec<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let mut found = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if ext == "py" {
+            if let Some(module) = line
+                .strip_prefix("from ")
+                .and_then(|rest| rest.split_whitespace().next())
+                .filter(|m| m.starts_with('.'))
+            {
+                let dots = module.chars().take_while(|c| *c == '.').count();
+                let mut base = dir.to_path_buf();
+                for _ in 1..dots {
+                    base.pop();
+                }
+                let rel = module[dots..].replace('.', "/");
+                let candidates = [
+                    base.join(format!("{}.py", rel)),
+                    base.join(&rel).join("__init__.py"),
+                ];
+                if let Some(hit) = candidates.iter().map(|c| normalize(c)).find(|c| exists(c)) {
+                    found.push(hit);
+                }
+            }
+            continue;
+        }
+
+        for specifier in js_specifiers(line) {
+            if !specifier.starts_with('.') {
+                continue;
+            }
+            let base = normalize(&dir.join(specifier));
+            let mut candidates = vec![base.clone()];
+            for e in JS_EXTENSIONS {
+                candidates.push(PathBuf::from(format!("{}.{}", base.display(), e)));
+            }
+            for e in JS_EXTENSIONS {
+                candidates.push(base.join(format!("index.{}", e)));
+            }
+            if let Some(hit) = candidates.into_iter().find(|c| exists(c)) {
+                found.push(hit);
+            }
+        }
+    }
+
+    found
+}
+
+/// Extract module specifiers from `from '...'`, `import '...'`, `require('...')` and `import('...')`
+fn js_specifiers(line: &str) -> Vec<&str> {
+    let mut specifiers = Vec::new();
+    for marker in ["from ", "import ", "require(", "import("] {
+        let mut rest = line;
+        while let Some(pos) = rest.find(marker) {
+            let after = rest[pos + marker.len()..].trim_start();
+            rest = &rest[pos + marker.len()..];
+            let Some(quote) = after.chars().next().filter(|c| matches!(c, '\'' | '"' | '`')) else {
+                continue;
+            };
+            if let Some(end) = after[1..].find(quote) {
+                specifiers.push(&after[1..1 + end]);
+            }
+        }
+    }
+    specifiers
+}
+
+/// Lexically normalize a relative path (resolve `.` and `..` without touching the filesystem)
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn file(status: FileStatus, data: serde_json::Value) -> FileState {
+        let mut state = FileState::new(data);
+        state.status = status;
+        state
+    }
+
+    #[test]
+    fn test_readiness_follows_dependency_status() {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("leaf.ts"), file(FileStatus::Pending, json!({})));
+        files.insert(
+            PathBuf::from("mid.ts"),
+            file(FileStatus::Pending, json!({"depends_on": ["./leaf.ts"]})),
+        );
+        files.insert(
+            PathBuf::from("top.ts"),
+            file(FileStatus::Pending, json!({"depends_on": "mid.ts"})),
+        );
+        let graph = DependencyGraph::build(&files, false, Path::new("."));
+        assert_eq!(graph.edge_count(), 2);
+
+        assert_eq!(graph.readiness(Path::new("leaf.ts"), &files), Readiness::Ready);
+        assert_eq!(graph.readiness(Path::new("mid.ts"), &files), Readiness::Waiting);
+
+        files.get_mut(Path::new("leaf.ts")).unwrap().status = FileStatus::Completed;
+        assert_eq!(graph.readiness(Path::new("mid.ts"), &files), Readiness::Ready);
+
+        files.get_mut(Path::new("mid.ts")).unwrap().status = FileStatus::Failed;
+        assert_eq!(
+            graph.readiness(Path::new("top.ts"), &files),
+            Readiness::Blocked(PathBuf::from("mid.ts"))
+        );
+    }
+
+    #[test]
+    fn test_cyclic_files() {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("a.ts"), file(FileStatus::Pending, json!({"depends_on": ["b.ts"]})));
+        files.insert(PathBuf::from("b.ts"), file(FileStatus::Pending, json!({"depends_on": ["a.ts"]})));
+        files.insert(PathBuf::from("c.ts"), file(FileStatus::Pending, json!({"depends_on": ["a.ts"]})));
+        files.insert(PathBuf::from("d.ts"), file(FileStatus::Pending, json!({})));
+        let graph = DependencyGraph::build(&files, false, Path::new("."));
+
+        let cyclic = graph.cyclic_files();
+        assert_eq!(cyclic.len(), 3);
+        assert!(!cyclic.contains(Path::new("d.ts")));
+    }
+
+    #[test]
+    fn test_infer_dependencies_from_imports() {
+        let known: HashSet<PathBuf> = [
+            "src/utils/format.ts",
+            "src/api/index.ts",
+            "src/components/Button.tsx",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let content = r#"
+import { format } from '../utils/format';
+import api from "../api";
+import React from 'react';
+const Button = require('./Button');
+"#;
+        let mut deps = infer_dependencies(Path::new("src/components/Card.tsx"), content, |p| {
+            known.contains(p)
+        });
+        deps.sort();
+        assert_eq!(
+            deps,
+            vec![
+                PathBuf::from("src/api/index.ts"),
+                PathBuf::from("src/components/Button.tsx"),
+                PathBuf::from("src/utils/format.ts"),
+            ]
+        );
+
+        let py = "from .models import User\nfrom ..core import db\nimport os\n";
+        let known_py: HashSet<PathBuf> = ["app/api/models.py", "app/core/__init__.py"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let mut deps = infer_dependencies(Path::new("app/api/views.py"), py, |p| known_py.contains(p));
+        deps.sort();
+        assert_eq!(
+            deps,
+            vec![PathBuf::from("app/api/models.py"), PathBuf::from("app/core/__init__.py")]
+        );
+    }
+}
@@ -1,12 +1,13 @@
+use crate::config::DirtyFilePolicy;
 use crate::process::extract_file_stem;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead, Write as IoWrite};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Represents the git state captured before starting the task runner
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -15,10 +16,20 @@ pub struct GitState {
     pub original_branch: Option<String>,
     /// The branch created for this task
     pub task_branch: Option<String>,
+    /// Commit HEAD pointed at when the task first started (squash base)
+    #[serde(default)]
+    pub base_commit: Option<String>,
     /// Files that were dirty (modified/untracked) before we started
     pub pre_existing_dirty_files: HashSet<PathBuf>,
+    /// Git blob hash of each pre-existing dirty file at capture time (None if it didn't exist).
+    /// The blobs are written to the object store so the content can be restored.
+    #[serde(default)]
+    pub pre_existing_dirty_hashes: HashMap<PathBuf, Option<String>>,
     /// Whether git operations are enabled
     pub enabled: bool,
+    /// Track changes with a filesystem snapshot instead (working dir is not a git repo)
+    #[serde(default)]
+    pub snapshot_tracking: bool,
     /// Global allowlist patterns for all files being processed
     /// This prevents false "unauthorized" warnings when multiple workers run in parallel
     pub global_allowlist_patterns: Vec<String>,
@@ -37,32 +48,53 @@ impl GitState {
         }
 
         let original_branch = get_current_branch(working_dir).await?;
+        let base_commit = get_head_commit(working_dir).await.ok();
         let dirty_files = get_dirty_files(working_dir).await?;
 
+        let mut dirty_hashes = HashMap::new();
         if !dirty_files.is_empty() {
             info!(
                 count = dirty_files.len(),
                 "Captured pre-existing dirty files"
             );
             for file in &dirty_files {
-                debug!(file = %file.display(), "Pre-existing dirty file");
+                let hash = hash_object(working_dir, file, true).await?;
+                debug!(file = %file.display(), hash = ?hash, "Pre-existing dirty file");
+                dirty_hashes.insert(file.clone(), hash);
             }
         }
 
         Ok(Self {
             original_branch: Some(original_branch),
             task_branch: None,
+            base_commit,
             pre_existing_dirty_files: dirty_files,
+            pre_existing_dirty_hashes: dirty_hashes,
             enabled: true,
+            snapshot_tracking: false,
             global_allowlist_patterns: Vec::new(),
         })
     }
 
+    /// Whether any change tracking backend (git or filesystem snapshot) is active
+    pub fn tracking_enabled(&self) -> bool {
+        self.enabled || self.snapshot_tracking
+    }
+
     /// Check if a file was dirty before we started (should be ignored for unauthorized checks)
     pub fn was_pre_existing_dirty(&self, path: &Path) -> bool {
         self.pre_existing_dirty_files.contains(path)
     }
 
+    /// Check whether a pre-existing dirty file's content differs from what was captured.
+    /// Files captured without a hash (older state files) are assumed unchanged.
+    pub async fn dirty_file_changed(&self, working_dir: &Path, path: &Path) -> Result<bool> {
+        match self.pre_existing_dirty_hashes.get(path) {
+            Some(captured) => Ok(hash_object(working_dir, path, false).await? != *captured),
+            None => Ok(false),
+        }
+    }
+
     /// Get files that are newly modified (not pre-existing dirty)
     #[allow(dead_code)]
     pub fn filter_new_changes(&self, changed_files: &[PathBuf]) -> Vec<PathBuf> {
@@ -80,6 +112,15 @@ impl GitState {
         }
     }
 
+    /// Add the allowlist patterns for a file and its related test/snapshot files
+    pub fn allow_file(&mut self, allowlist_pattern: &str, path: &Path, working_dir: &Path) {
+        use crate::process::{expand_pattern, find_related_files};
+        self.add_allowlist_pattern(expand_pattern(allowlist_pattern, path));
+        for related in find_related_files(path, working_dir) {
+            self.add_allowlist_pattern(expand_pattern(allowlist_pattern, &related));
+        }
+    }
+
     /// Check if a path matches any of the global allowlist patterns
     pub fn matches_global_allowlist(&self, path: &Path) -> bool {
         use crate::process::matches_allowlist;
@@ -307,6 +348,27 @@ pub async fn get_current_branch(working_dir: &Path) -> Result<String> {
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
+/// Get the full hash of the current HEAD commit
+pub async fn get_head_commit(working_dir: &Path) -> Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to get HEAD commit")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get HEAD commit: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
 /// Get all dirty files (modified, added, deleted, untracked)
 pub async fn get_dirty_files(working_dir: &Path) -> Result<HashSet<PathBuf>> {
     let output = Command::new("git")
@@ -339,6 +401,126 @@ pub async fn get_dirty_files(working_dir: &Path) -> Result<HashSet<PathBuf>> {
     Ok(files)
 }
 
+/// Compute the git blob hash of a file, optionally writing it to the object store.
+/// Returns None if the path is not a regular file.
+pub async fn hash_object(working_dir: &Path, path: &Path, write: bool) -> Result<Option<String>> {
+    if !working_dir.join(path).is_file() {
+        return Ok(None);
+    }
+
+    let mut args = vec!["hash-object"];
+    if write {
+        args.push("-w");
+    }
+    args.push("--");
+
+    let output = Command::new("git")
+        .args(&args)
+        .arg(path)
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to hash file")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to hash '{}': {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// Restore a file to the content of a blob captured with `hash_object`.
+/// A `None` blob means the file didn't exist, so it is removed.
+pub async fn restore_blob(working_dir: &Path, path: &Path, blob: Option<&str>) -> Result<()> {
+    let full_path = working_dir.join(path);
+
+    let Some(blob) = blob else {
+        if full_path.exists() {
+            std::fs::remove_file(&full_path)
+                .with_context(|| format!("Failed to remove {}", full_path.display()))?;
+        }
+        return Ok(());
+    };
+
+    let output = Command::new("git")
+        .args(["cat-file", "blob", blob])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to read captured blob")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to read blob {}: {}",
+            blob,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    if let Some(parent) = full_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(&full_path, &output.stdout)
+        .with_context(|| format!("Failed to restore {}", full_path.display()))?;
+
+    info!(file = %path.display(), "Restored pre-existing dirty file");
+    Ok(())
+}
+
+/// Commit history summary for a single file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileHistory {
+    /// Number of commits touching the file
+    pub commits: u32,
+    /// Unix timestamp of the most recent commit touching the file
+    pub last_commit: i64,
+}
+
+/// Summarise commit history per file (used for churn/recency ordering). Paths are
+/// relative to `working_dir`, which may be a subdirectory of the repository.
+pub async fn file_history(working_dir: &Path) -> Result<HashMap<PathBuf, FileHistory>> {
+    let output = Command::new("git")
+        .args(["log", "--relative", "--format=%x00%ct", "--name-only"])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to run git log")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to read git history: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut history: HashMap<PathBuf, FileHistory> = HashMap::new();
+    let mut timestamp = 0i64;
+
+    for line in stdout.lines() {
+        if let Some(ts) = line.strip_prefix('\0') {
+            timestamp = ts.trim().parse().unwrap_or(0);
+        } else if !line.trim().is_empty() {
+            let entry = history.entry(PathBuf::from(line.trim())).or_default();
+            entry.commits += 1;
+            entry.last_commit = entry.last_commit.max(timestamp);
+        }
+    }
+
+    Ok(history)
+}
+
 /// Create and checkout a new branch for the task
 pub async fn create_task_branch(working_dir: &Path, task_id: &str) -> Result<String> {
     let timestamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
@@ -366,7 +548,6 @@ pub async fn create_task_branch(working_dir: &Path, task_id: &str) -> Result<Str
 }
 
 /// Checkout an existing branch
-#[allow(dead_code)]
 pub async fn checkout_branch(working_dir: &Path, branch: &str) -> Result<()> {
     let output = Command::new("git")
         .args(["checkout", branch])
@@ -504,6 +685,128 @@ pub async fn commit_file_changes(
     }
 }
 
+/// Squash every commit after `base` into a single commit with the given message.
+///
+/// Uses `commit-tree` on HEAD's tree so the index and working tree are left untouched
+/// (pre-existing staged or dirty work is not swept into the squashed commit).
+/// Returns the short hash of the new commit, or None if there was nothing to squash.
+pub async fn squash_since(working_dir: &Path, base: &str, message: &str) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .args(["rev-list", "--count", &format!("{}..HEAD", base)])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to count commits to squash")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to count commits since {}: {}",
+            base,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let count: u32 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0);
+    if count == 0 {
+        debug!(base = %base, "No commits to squash");
+        return Ok(None);
+    }
+
+    let output = Command::new("git")
+        .args(["commit-tree", "HEAD^{tree}", "-p", base, "-m", message])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to create squashed commit")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to create squashed commit: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let squashed = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let output = Command::new("git")
+        .args(["reset", "--soft", &squashed])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to move branch to squashed commit")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to move branch to squashed commit: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let short = squashed.chars().take(7).collect::<String>();
+    info!(commits = count, hash = %short, "Squashed task commits");
+    Ok(Some(short))
+}
+
+/// Push a branch to a remote
+pub async fn push_branch(working_dir: &Path, remote: &str, branch: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args(["push", remote, branch])
+        .current_dir(working_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("Failed to push branch")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to push '{}' to '{}': {}",
+            branch,
+            remote,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    info!(branch = %branch, remote = %remote, "Pushed branch");
+    Ok(())
+}
+
+/// Revert unauthorized edits to pre-existing dirty files when the policy asks for it.
+/// Returns the files that were restored.
+pub async fn protect_dirty_files(
+    unauthorized: &[PathBuf],
+    working_dir: &Path,
+    git_state: &GitState,
+    policy: DirtyFilePolicy,
+) -> Vec<PathBuf> {
+    let mut restored = Vec::new();
+    if policy != DirtyFilePolicy::Revert {
+        return restored;
+    }
+
+    for path in unauthorized {
+        let Some(blob) = git_state.pre_existing_dirty_hashes.get(path) else {
+            continue;
+        };
+        match restore_blob(working_dir, path, blob.as_deref()).await {
+            Ok(()) => restored.push(path.clone()),
+            Err(e) => {
+                warn!(file = %path.display(), error = %e, "Failed to restore pre-existing dirty file");
+            }
+        }
+    }
+    restored
+}
+
 /// Get the diff for staged files
 #[allow(dead_code)]
 pub async fn get_staged_diff(working_dir: &Path) -> Result<String> {
@@ -603,8 +906,11 @@ pub async fn stash_pop(working_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Check git changes against allowlist, filtering out pre-existing dirty files
-/// and files that match any task's global allowlist (for parallel worker support)
+/// Check git changes against allowlist, filtering out untouched pre-existing dirty files
+/// and files that match any task's global allowlist (for parallel worker support).
+///
+/// Pre-existing dirty files whose content changed since capture are classified like any
+/// other change, so edits to uncommitted human work outside the allowlist are unauthorized.
 pub async fn check_git_changes_filtered(
     allowlist_pattern: &str,
     working_dir: &Path,
@@ -612,14 +918,24 @@ pub async fn check_git_changes_filtered(
 ) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
     use crate::process::matches_allowlist;
 
-    let current_dirty = get_dirty_files(working_dir).await?;
+    let mut current_dirty = get_dirty_files(working_dir).await?;
+
+    // A dirty file reverted to its committed content drops out of `git status`,
+    // but that is still a change to the captured work
+    for path in git_state.pre_existing_dirty_hashes.keys() {
+        current_dirty.insert(path.clone());
+    }
 
     let mut allowed = Vec::new();
     let mut unauthorized = Vec::new();
 
     for path in current_dirty {
-        // Skip files that were already dirty before we started
+        // Skip files that were already dirty before we started; any edit to one is
+        // unauthorized, even inside the allowlist, so the user's work isn't committed
         if git_state.was_pre_existing_dirty(&path) {
+            if git_state.dirty_file_changed(working_dir, &path).await? {
+                unauthorized.push(path);
+            }
             continue;
         }
 
@@ -658,6 +974,94 @@ mod tests {
         assert_eq!(new_only[0], PathBuf::from("new_change.txt"));
     }
 
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_squash_since_collapses_task_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q"]);
+        git(root, &["config", "user.name", "Test"]);
+        git(root, &["config", "user.email", "test@example.com"]);
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "base"]);
+        let base = get_head_commit(root).await.unwrap();
+
+        for name in ["b.txt", "c.txt"] {
+            std::fs::write(root.join(name), name).unwrap();
+            git(root, &["add", "."]);
+            git(root, &["commit", "-q", "-m", name]);
+        }
+        // Uncommitted work must survive the squash untouched
+        std::fs::write(root.join("a.txt"), "dirty").unwrap();
+
+        let squashed = squash_since(root, &base, "summary").await.unwrap();
+        assert!(squashed.is_some());
+        assert_eq!(git(root, &["rev-list", "--count", &format!("{}..HEAD", base)]), "1");
+        assert_eq!(git(root, &["log", "-1", "--format=%s"]), "summary");
+        assert!(root.join("c.txt").exists());
+        assert_eq!(std::fs::read_to_string(root.join("a.txt")).unwrap(), "dirty");
+
+        // Nothing left to squash
+        assert!(squash_since(root, &git(root, &["rev-parse", "HEAD"]), "again")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_edited_dirty_file_is_unauthorized_and_revertible() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "-q"]);
+        git(root, &["config", "user.name", "Test"]);
+        git(root, &["config", "user.email", "test@example.com"]);
+        std::fs::write(root.join("notes.md"), "committed").unwrap();
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "base"]);
+
+        // Human work in progress
+        std::fs::write(root.join("notes.md"), "my draft").unwrap();
+        let git_state = GitState::capture(root).await.unwrap();
+
+        // Untouched dirty file is ignored
+        let (_, unauthorized) = check_git_changes_filtered("parser*", root, &git_state)
+            .await
+            .unwrap();
+        assert!(unauthorized.is_empty());
+
+        // Agent clobbers it
+        std::fs::write(root.join("notes.md"), "overwritten").unwrap();
+        let (_, unauthorized) = check_git_changes_filtered("parser*", root, &git_state)
+            .await
+            .unwrap();
+        assert_eq!(unauthorized, vec![PathBuf::from("notes.md")]);
+
+        // Even when the allowlist covers it
+        let (allowed, unauthorized) = check_git_changes_filtered("notes*", root, &git_state)
+            .await
+            .unwrap();
+        assert!(allowed.is_empty());
+        assert_eq!(unauthorized, vec![PathBuf::from("notes.md")]);
+
+        let restored =
+            protect_dirty_files(&unauthorized, root, &git_state, DirtyFilePolicy::Revert).await;
+        assert_eq!(restored.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(root.join("notes.md")).unwrap(),
+            "my draft"
+        );
+    }
+
     #[test]
     fn test_was_pre_existing_dirty() {
         let mut git_state = GitState::default();
@@ -0,0 +1,231 @@
+use crate::types::{FileState, UsageTotals};
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Change to a task appended to its journal between rewrites of the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// A file's state after it changed
+    File { path: PathBuf, state: Box<FileState> },
+    /// Usage added since the last entry
+    Usage { added: UsageTotals },
+}
+
+/// First line of a journal: the state file generation its entries apply to
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalHeader {
+    generation: u64,
+}
+
+/// Journal next to a state file (`state_0.json` -> `state_0.journal`)
+pub fn journal_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("journal")
+}
+
+/// Read the entries after `offset` (0 to read them all) for state file `generation`.
+/// Returns the entries and the offset to continue from, or None if the state file has
+/// since been rewritten by another runner and must be read again.
+pub fn read_journal<T: DeserializeOwned>(
+    path: &Path,
+    generation: u64,
+    offset: u64,
+) -> Result<Option<(Vec<T>, u64)>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((offset == 0).then(|| (Vec::new(), 0)));
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to open journal: {}", path.display()))
+        }
+    };
+    let len = file
+        .metadata()
+        .with_context(|| format!("Failed to read journal: {}", path.display()))?
+        .len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = String::new();
+    reader
+        .read_line(&mut header)
+        .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+    let Ok(header) = serde_json::from_str::<JournalHeader>(header.trim()) else {
+        // Torn header from a crash while starting the journal: nothing was journaled
+        return Ok((offset == 0).then(|| (Vec::new(), 0)));
+    };
+    if header.generation < generation {
+        // Left over from before our last rewrite of the state file
+        return Ok((offset == 0).then(|| (Vec::new(), 0)));
+    }
+    if header.generation > generation || len < offset {
+        return Ok(None);
+    }
+
+    let mut position = reader
+        .stream_position()
+        .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+    if offset > position {
+        reader
+            .seek(SeekFrom::Start(offset))
+            .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+        position = offset;
+    }
+    let mut rest = String::new();
+    reader
+        .read_to_string(&mut rest)
+        .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+
+    // A trailing line without a newline is a write cut short by a crash
+    let mut entries = Vec::new();
+    let mut consumed = 0;
+    for line in rest.split_inclusive('\n') {
+        if !line.ends_with('\n') {
+            break;
+        }
+        consumed += line.len();
+        match serde_json::from_str(line.trim()) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(path = %path.display(), error = %e, "Skipping unreadable journal entry"),
+        }
+    }
+    Ok(Some((entries, position + consumed as u64)))
+}
+
+/// Append entries for state file `generation`, starting the journal over if it belongs to
+/// an older one. Returns the journal's new length.
+pub fn append_journal(path: &Path, generation: u64, entries: &[JournalEntry]) -> Result<u64> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open journal: {}", path.display()))?;
+
+    let mut header = String::new();
+    BufReader::new(&file)
+        .read_line(&mut header)
+        .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+    let current = serde_json::from_str::<JournalHeader>(header.trim())
+        .is_ok_and(|h| h.generation == generation);
+
+    let mut buf = Vec::new();
+    if current {
+        // Drop a line cut short by a crash, so the next entry starts a line of its own
+        let complete = complete_len(&mut file)
+            .with_context(|| format!("Failed to read journal: {}", path.display()))?;
+        file.set_len(complete)
+            .with_context(|| format!("Failed to truncate journal: {}", path.display()))?;
+    } else {
+        file.set_len(0)
+            .with_context(|| format!("Failed to truncate journal: {}", path.display()))?;
+        serde_json::to_writer(&mut buf, &JournalHeader { generation })?;
+        buf.push(b'\n');
+    }
+    for entry in entries {
+        serde_json::to_writer(&mut buf, entry).context("Failed to serialize journal entry")?;
+        buf.push(b'\n');
+    }
+
+    let len = file
+        .seek(SeekFrom::End(0))
+        .with_context(|| format!("Failed to write journal: {}", path.display()))?;
+    file.write_all(&buf)
+        .with_context(|| format!("Failed to write journal: {}", path.display()))?;
+    Ok(len + buf.len() as u64)
+}
+
+/// Length of a journal up to the end of its last complete line
+fn complete_len(file: &mut fs::File) -> std::io::Result<u64> {
+    const CHUNK: u64 = 4096;
+    let mut end = file.seek(SeekFrom::End(0))?;
+    let mut buf = vec![0; CHUNK as usize];
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(newline) = chunk.iter().rposition(|&b| b == b'\n') {
+            return Ok(start + newline as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Start an empty journal for a freshly written state file. Returns its length.
+pub fn reset_journal(path: &Path, generation: u64) -> Result<u64> {
+    let mut content = serde_json::to_vec(&JournalHeader { generation })?;
+    content.push(b'\n');
+    let temp_path = path.with_extension("journal.tmp");
+    fs::write(&temp_path, &content)
+        .with_context(|| format!("Failed to write journal: {}", temp_path.display()))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to rename journal to: {}", path.display()))?;
+    Ok(content.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_entry(name: &str) -> JournalEntry {
+        JournalEntry::File {
+            path: PathBuf::from(name),
+            state: Box::new(FileState::new(serde_json::Value::Null)),
+        }
+    }
+
+    fn paths(entries: &[JournalEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .filter_map(|e| match e {
+                JournalEntry::File { path, .. } => Some(path.display().to_string()),
+                JournalEntry::Usage { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_journal_reads_from_offset_and_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state_0.journal");
+        assert_eq!(read_journal::<JournalEntry>(&path, 1, 0).unwrap().unwrap().1, 0);
+
+        let start = reset_journal(&path, 1).unwrap();
+        let end = append_journal(&path, 1, &[file_entry("a.ts"), file_entry("b.ts")]).unwrap();
+        let (entries, offset) = read_journal::<JournalEntry>(&path, 1, 0).unwrap().unwrap();
+        assert_eq!(paths(&entries), ["a.ts", "b.ts"]);
+        assert_eq!(offset, end);
+        assert!(start < end);
+
+        // Only new entries after an offset; a torn last line waits for the rest
+        append_journal(&path, 1, &[file_entry("c.ts")]).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"event\":\"fi").unwrap();
+        let (entries, _) = read_journal::<JournalEntry>(&path, 1, end).unwrap().unwrap();
+        assert_eq!(paths(&entries), ["c.ts"]);
+
+        // The next append replaces the torn line instead of joining it
+        let after_torn = append_journal(&path, 1, &[file_entry("e.ts")]).unwrap();
+        let (entries, offset) = read_journal::<JournalEntry>(&path, 1, end).unwrap().unwrap();
+        assert_eq!(paths(&entries), ["c.ts", "e.ts"]);
+        assert_eq!(offset, after_torn);
+
+        // Rewritten by someone else: the state file must be read again
+        assert!(read_journal::<JournalEntry>(&path, 0, end).unwrap().is_none());
+        // Older than our state file: nothing in it applies
+        assert!(read_journal::<JournalEntry>(&path, 2, 0).unwrap().unwrap().0.is_empty());
+
+        // Appending for a newer generation starts the journal over
+        append_journal(&path, 2, &[file_entry("d.ts")]).unwrap();
+        let (entries, _) = read_journal::<JournalEntry>(&path, 2, 0).unwrap().unwrap();
+        assert_eq!(paths(&entries), ["d.ts"]);
+    }
+}
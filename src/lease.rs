@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A runner's claim on a file, so several runners can work through one task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    /// Runner holding the lease (see `runner_id`)
+    pub owner: String,
+    /// When the owner last renewed the lease
+    pub heartbeat: DateTime<Utc>,
+    /// When other runners may reclaim the file if the owner stops renewing
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    pub fn new(owner: &str, ttl_secs: u64) -> Self {
+        let now = Utc::now();
+        Self {
+            owner: owner.to_string(),
+            heartbeat: now,
+            expires_at: now + Duration::seconds(ttl_secs as i64),
+        }
+    }
+
+    pub fn renew(&mut self, ttl_secs: u64) {
+        let now = Utc::now();
+        self.heartbeat = now;
+        self.expires_at = now + Duration::seconds(ttl_secs as i64);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    /// Held by a runner other than `runner` that is still renewing it
+    pub fn blocks(&self, runner: Option<&str>) -> bool {
+        Some(self.owner.as_str()) != runner && !self.is_expired()
+    }
+}
+
+/// Identify this runner process: host, pid and start time, so runners in different
+/// containers sharing a volume don't collide
+pub fn runner_id() -> String {
+    let host = host_name();
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    format!("{}-{}-{:x}", host, std::process::id(), started & 0xffff)
+}
+
+/// Name of the machine this runner is on
+pub fn host_name() -> String {
+    sysinfo::System::host_name().unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Lock file guarding reads and writes of a state file
+fn lock_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("json.lock")
+}
+
+/// Take the exclusive lock on a state file; released when the returned file is dropped
+pub fn lock_state(state_path: &Path) -> Result<File> {
+    let path = lock_path(state_path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open state lock: {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("Failed to lock state: {}", path.display()))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_blocks_other_runners_until_expiry() {
+        let mut lease = Lease::new("runner-a", 60);
+        assert!(lease.blocks(Some("runner-b")));
+        assert!(lease.blocks(None));
+        assert!(!lease.blocks(Some("runner-a")));
+
+        lease.expires_at = Utc::now() - Duration::seconds(1);
+        assert!(lease.is_expired());
+        assert!(!lease.blocks(Some("runner-b")));
+
+        lease.renew(60);
+        assert!(lease.blocks(Some("runner-b")));
+    }
+}
@@ -1,23 +1,43 @@
+mod attempts;
+mod autoscale;
+mod batch;
+mod bisect;
+mod budget;
+mod bundle;
+mod cache;
 mod claude;
 mod cli;
+mod commands;
 mod config;
+mod control;
+mod deps;
 mod git;
+mod journal;
+mod lease;
 mod memory;
+mod migrate;
+mod ordering;
+mod pipeline;
 mod pools;
 mod process;
+mod recovery;
 mod runner;
+mod snapshot;
 mod state;
 mod task_list;
+mod timeline;
 mod types;
 mod usage;
+mod verify_groups;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use cli::Cli;
 use config::Config;
 use git::GitState;
+use snapshot::FsSnapshot;
 use state::State;
-use task_list::TaskList;
+use task_list::{describe_prompt, TaskList};
 use tokio::sync::broadcast;
 use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
@@ -35,6 +55,10 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     cli.validate()?;
 
+    if let Some(ref command) = cli.command {
+        return commands::run(command, &cli).await;
+    }
+
     // Validate concurrency
     if cli.concurrency == 0 {
         anyhow::bail!("--concurrency must be at least 1");
@@ -43,13 +67,7 @@ async fn main() -> Result<()> {
     // Load or create task list
     let mut task_list = TaskList::load_or_create(&cli.tasks_dir)?;
 
-    // Determine working directory
-    let working_dir = cli
-        .working_dir
-        .clone()
-        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
-
-    let (mut config, state, state_path, task_id) = if cli.is_resume() {
+    let (mut config, state, state_path, task_id, working_dir) = if cli.is_resume() {
         // Resume mode
         if let Some(specific_task_id) = cli.resume_task_id() {
             // Resume a specific task
@@ -63,8 +81,9 @@ async fn main() -> Result<()> {
 
             info!(task_id = %specific_task_id, state_file = %entry.state_file, "Resuming task");
 
-            let config = state.config.clone().merge_with_cli(&cli);
-            (config, state, state_path, specific_task_id.to_string())
+            let config = state.config.clone().merge_with_cli(&cli)?;
+            let working_dir = resolve_working_dir(&cli, Some(&entry.working_dir))?;
+            (config, state, state_path, specific_task_id.to_string(), working_dir)
         } else {
             // Resume first incomplete task
             let incomplete = task_list.get_incomplete_tasks();
@@ -81,22 +100,18 @@ async fn main() -> Result<()> {
 
             info!(task_id = %task_id, state_file = %entry.state_file, "Resuming first incomplete task");
 
-            let config = state.config.clone().merge_with_cli(&cli);
-            (config, state, state_path, task_id.to_string())
+            let config = state.config.clone().merge_with_cli(&cli)?;
+            let working_dir = resolve_working_dir(&cli, Some(&entry.working_dir))?;
+            (config, state, state_path, task_id.to_string(), working_dir)
         }
     } else {
         // New task mode
         let config = Config::from_cli(&cli)?;
+        let working_dir = resolve_working_dir(&cli, None)?;
         let mut state = State::new(config.clone());
 
         // Create new task entry
-        let description = cli.prompt.as_ref().map(|p| {
-            if p.len() > 50 {
-                format!("{}...", &p[..47])
-            } else {
-                p.clone()
-            }
-        });
+        let description = cli.prompt.as_deref().map(describe_prompt);
         let task_id = task_list.create_task(working_dir.clone(), description);
         let state_path = cli
             .tasks_dir
@@ -112,17 +127,11 @@ async fn main() -> Result<()> {
         }
 
         // Build global allowlist from all files (+ related tests/snapshots) so it's available on resume
-        if cli.git || cli.git_branch || cli.git_commit {
+        if cli.git_implied() {
             let file_paths: Vec<_> = state.files.keys().cloned().collect();
             for path in &file_paths {
-                let expanded = process::expand_pattern(&cli.allowlist, path);
-                state.git_state.add_allowlist_pattern(expanded);
-
-                // Discover related test/snapshot files and add their patterns too
-                for related in process::find_related_files(path, &working_dir) {
-                    let related_pattern = process::expand_pattern(&cli.allowlist, &related);
-                    state.git_state.add_allowlist_pattern(related_pattern);
-                }
+                // Also discovers related test/snapshot files and adds their patterns
+                state.git_state_mut().allow_file(&cli.allowlist, path, &working_dir);
             }
             if !state.git_state.global_allowlist_patterns.is_empty() {
                 info!(
@@ -134,16 +143,59 @@ async fn main() -> Result<()> {
 
         // Save task list and initial state
         task_list.save(&cli.tasks_dir)?;
-        state.config = config.clone();
+        *state.config_mut() = config.clone();
         state
             .save(&state_path)
             .context("Failed to save initial state")?;
 
         info!(task_id = %task_id, "Created new task");
 
-        (config, state, state_path, task_id)
+        (config, state, state_path, task_id, working_dir)
     };
 
+    // Bring a resumed task in line with its input file (before any tmux relaunch, which
+    // doesn't pass --resync on)
+    let mut state = state;
+    // Settings given with --resume stay with the task, so finalizing sees them too
+    if cli.is_resume() {
+        *state.config_mut() = config.clone();
+        state
+            .save(&state_path)
+            .context("Failed to save resumed task settings")?;
+    }
+    if cli.resync {
+        let report = state
+            .sync_input(
+                &state_path,
+                &config.input_file,
+                cli.removed.unwrap_or_default(),
+                cli.reset_changed,
+                &working_dir,
+            )
+            .context("Failed to sync task with its input file")?;
+        for path in &report.added {
+            info!(file = %path.display(), "Added from input file");
+        }
+        for path in &report.removed {
+            info!(file = %path.display(), "No longer in input file, dropped from task");
+        }
+        for path in &report.changed {
+            info!(
+                file = %path.display(),
+                reset = report.reset.contains(path),
+                "Metadata changed in input file"
+            );
+        }
+        info!(
+            input = %config.input_file.display(),
+            added = report.added.len(),
+            removed = report.removed.len(),
+            changed = report.changed.len(),
+            reset = report.reset.len(),
+            "Synced task with input file"
+        );
+    }
+
     // Auto-launch into tmux if not already in one
     if !cli.no_tmux && !cli.dry_run && std::env::var("TMUX").is_err() {
         if let Ok(output) = std::process::Command::new("tmux").arg("-V").output() {
@@ -186,7 +238,7 @@ async fn main() -> Result<()> {
 
     // Handle --no-git override
     if cli.no_git {
-        if cli.git || cli.git_branch || cli.git_commit {
+        if cli.git_implied() {
             warn!("--no-git specified: all git features disabled for this run");
         }
         config.git.enabled = false;
@@ -259,25 +311,31 @@ async fn main() -> Result<()> {
     }
 
     // Capture git state and set up branch if git features are enabled
-    let mut state = state;
     if config.git.enabled || config.git.auto_branch || config.git.auto_commit {
         info!("Git features enabled, capturing initial git state");
 
         match GitState::capture(&working_dir).await {
-            Ok(mut git_state) => {
+            Ok(git_state) => {
                 if git_state.enabled {
-                    if !git_state.pre_existing_dirty_files.is_empty() {
+                    state.set_git_state(git_state);
+                    if !state.git_state.pre_existing_dirty_files.is_empty() {
                         warn!(
-                            count = git_state.pre_existing_dirty_files.len(),
+                            count = state.git_state.pre_existing_dirty_files.len(),
                             "Found pre-existing dirty files that will be excluded from unauthorized checks"
                         );
                     }
 
-                    // Create task branch if requested
-                    if config.git.auto_branch && git_state.task_branch.is_none() {
+                    // Create task branch if requested, or get back onto it when resuming
+                    if let Some(ref branch) = state.git_state.task_branch {
+                        if head_is_off_branch(&working_dir, branch).await {
+                            if let Err(e) = git::checkout_branch(&working_dir, branch).await {
+                                warn!(error = %e, branch = %branch, "Failed to checkout existing task branch");
+                            }
+                        }
+                    } else if config.git.auto_branch {
                         match git::create_task_branch(&working_dir, &task_id).await {
                             Ok(branch_name) => {
-                                git_state.task_branch = Some(branch_name);
+                                state.git_state_mut().task_branch = Some(branch_name);
                                 info!(task_id = %task_id, "Created task branch");
                             }
                             Err(e) => {
@@ -286,10 +344,33 @@ async fn main() -> Result<()> {
                         }
                     }
 
-                    state.set_git_state(git_state);
                     state
                         .save(&state_path)
                         .context("Failed to save state with git info")?;
+                } else {
+                    // Not a git repo: fall back to a content-hash manifest of the tree
+                    let snapshot_path = snapshot::snapshot_path(&state_path);
+                    match FsSnapshot::load_or_capture(
+                        &snapshot_path,
+                        &working_dir,
+                        std::slice::from_ref(&cli.tasks_dir),
+                    ) {
+                        Ok(snapshot) => {
+                            info!(
+                                files = snapshot.files.len(),
+                                "Tracking changes with a filesystem snapshot"
+                            );
+                            let mut git_state = git_state;
+                            git_state.snapshot_tracking = true;
+                            state.set_git_state(git_state);
+                            state
+                                .save(&state_path)
+                                .context("Failed to save state with snapshot info")?;
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Failed to capture filesystem snapshot, continuing without change tracking");
+                        }
+                    }
                 }
             }
             Err(e) => {
@@ -331,12 +412,15 @@ async fn main() -> Result<()> {
         let _ = shutdown_tx_clone.send(());
     });
 
+    let config_git_finalize = config.git.enabled && config.git.auto_finalize;
+
     // Run the task
     let result = runner::run(
         config,
         state,
         state_path.clone(),
         cli.tasks_dir.clone(),
+        working_dir.clone(),
         shutdown_rx,
         if cli.limit > 0.0 { Some(cli.limit) } else { None },
     )
@@ -345,14 +429,30 @@ async fn main() -> Result<()> {
     // Check if task completed successfully
     if result.is_ok() {
         let state = State::load(&state_path)?;
-        let summary = state.get_summary();
-        if summary.pending == 0
-            && summary.prompt_in_progress == 0
-            && summary.verify_in_progress == 0
-        {
+
+        task_list.set_paused_for_budget(&task_id, state.budget_paused.is_some());
+        task_list.save(&cli.tasks_dir)?;
+        if state.budget_paused.is_some() {
+            warn!(task_id = %task_id, "Task paused for budget, resume with: claude-loop-runner --resume {}", task_id);
+        }
+
+        // A task paused for budget or stopped early still has files to process
+        let finished = state.budget_paused.is_none()
+            && state.files.values().all(|file| file.status.is_terminal());
+        if finished {
             task_list.mark_completed(&task_id);
             task_list.save(&cli.tasks_dir)?;
             info!(task_id = %task_id, "Task marked as completed");
+
+            if config_git_finalize {
+                let mut state = state;
+                match commands::finalize::finalize_task(&task_id, &mut state, &working_dir).await {
+                    Ok(()) => state
+                        .save(&state_path)
+                        .context("Failed to save state after finalize")?,
+                    Err(e) => warn!(error = %e, "Failed to finalize task git state"),
+                }
+            }
         }
     }
 
@@ -363,6 +463,26 @@ async fn main() -> Result<()> {
     result
 }
 
+/// The task's root directory: `-w` if given, else the directory a resumed task was
+/// created in, else the current directory
+fn resolve_working_dir(cli: &Cli, task_dir: Option<&std::path::Path>) -> Result<std::path::PathBuf> {
+    let dir = match (&cli.working_dir, task_dir) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(dir)) => dir.to_path_buf(),
+        (None, None) => std::env::current_dir().context("Failed to get current directory")?,
+    };
+    std::path::absolute(&dir)
+        .with_context(|| format!("Invalid working directory: {}", dir.display()))
+}
+
+/// Check whether HEAD is on a different branch than the given one
+async fn head_is_off_branch(working_dir: &std::path::Path, branch: &str) -> bool {
+    match git::get_current_branch(working_dir).await {
+        Ok(current) => current != branch,
+        Err(_) => false,
+    }
+}
+
 /// Build a tmux session name from the working directory and task ID.
 ///
 /// Takes first letter of each path component (lowercased) except the last,
@@ -0,0 +1,163 @@
+//! Upgrades of state files and the task list written by older versions of the runner.
+//!
+//! Files carry a `schema_version`. On load, the raw JSON is passed through each migration
+//! newer than its version before it is deserialized, and the original is copied aside first.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Version of state files (and their journals) written by this runner
+pub const STATE_VERSION: u32 = 1;
+
+/// Version of task lists written by this runner
+pub const TASK_LIST_VERSION: u32 = 1;
+
+/// One step of a state migration chain, from version `to - 1` to `to`
+struct StateMigration {
+    to: u32,
+    /// Upgrade task-level fields (config, usage, git state...)
+    task: fn(&mut Value),
+    /// Upgrade one file's state, in the state file or a journal entry
+    file: fn(&mut Value),
+}
+
+/// One step of the task list migration chain, from version `to - 1` to `to`
+struct TaskListMigration {
+    to: u32,
+    apply: fn(&mut Value),
+}
+
+/// Version 1 is the first versioned format. Earlier files only ever gained fields with
+/// defaults, so they are stamped as they are.
+const STATE_MIGRATIONS: &[StateMigration] = &[StateMigration {
+    to: 1,
+    task: |_| {},
+    file: |_| {},
+}];
+
+const TASK_LIST_MIGRATIONS: &[TaskListMigration] = &[TaskListMigration {
+    to: 1,
+    apply: |_| {},
+}];
+
+/// Schema version of a file's JSON (files from before versioning count as 0)
+pub fn schema_version(value: &Value) -> u32 {
+    value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32
+}
+
+fn check_supported(version: u32, current: u32, path: &Path) -> Result<()> {
+    if version > current {
+        anyhow::bail!(
+            "{} has schema version {}, but this runner only understands up to {}; upgrade claude-loop-runner",
+            path.display(),
+            version,
+            current
+        );
+    }
+    Ok(())
+}
+
+/// Bring a state file's JSON and its journal entries up to `STATE_VERSION`.
+/// Returns the version the file was at.
+pub fn migrate_state(state: &mut Value, journal: &mut [Value], path: &Path) -> Result<u32> {
+    let version = schema_version(state);
+    check_supported(version, STATE_VERSION, path)?;
+
+    for step in STATE_MIGRATIONS.iter().filter(|m| m.to > version) {
+        (step.task)(state);
+        if let Some(files) = state.get_mut("files").and_then(Value::as_object_mut) {
+            files.values_mut().for_each(step.file);
+        }
+        for entry in journal.iter_mut() {
+            if entry.get("event").and_then(Value::as_str) == Some("file") {
+                if let Some(file) = entry.get_mut("state") {
+                    (step.file)(file);
+                }
+            }
+        }
+        state["schema_version"] = step.to.into();
+    }
+    Ok(version)
+}
+
+/// Bring a task list's JSON up to `TASK_LIST_VERSION`. Returns the version it was at.
+pub fn migrate_task_list(list: &mut Value, path: &Path) -> Result<u32> {
+    let version = schema_version(list);
+    check_supported(version, TASK_LIST_VERSION, path)?;
+
+    for step in TASK_LIST_MIGRATIONS.iter().filter(|m| m.to > version) {
+        (step.apply)(list);
+        list["schema_version"] = step.to.into();
+    }
+    Ok(version)
+}
+
+/// Copy a file aside before it is rewritten in a newer format
+/// (`state_0.json` at version 0 -> `state_0.json.v0.bak`). An existing backup is kept.
+pub fn backup(path: &Path, version: u32) -> Result<Option<PathBuf>> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup_path = path.with_file_name(name);
+    if backup_path.exists() || !path.exists() {
+        return Ok(None);
+    }
+    fs::copy(path, &backup_path).with_context(|| {
+        format!(
+            "Failed to back up {} to {}",
+            path.display(),
+            backup_path.display()
+        )
+    })?;
+    info!(
+        file = %path.display(),
+        backup = %backup_path.display(),
+        version,
+        "Backed up file before migrating it"
+    );
+    Ok(Some(backup_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrates_unversioned_files_and_rejects_newer_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state_0.json");
+        fs::write(&path, "{}").unwrap();
+
+        let mut state = json!({"files": {"a.ts": {"status": "pending"}}});
+        let mut journal = vec![json!({"event": "file", "path": "a.ts", "state": {}})];
+        assert_eq!(migrate_state(&mut state, &mut journal, &path).unwrap(), 0);
+        assert_eq!(schema_version(&state), STATE_VERSION);
+        assert_eq!(state["files"]["a.ts"]["status"], "pending");
+
+        // Already current: nothing to do
+        assert_eq!(
+            migrate_state(&mut state, &mut journal, &path).unwrap(),
+            STATE_VERSION
+        );
+
+        let mut newer = json!({"schema_version": STATE_VERSION + 1});
+        let err = migrate_state(&mut newer, &mut [], &path).unwrap_err();
+        assert!(err.to_string().contains("upgrade claude-loop-runner"));
+
+        let backup_path = backup(&path, 0).unwrap().unwrap();
+        assert_eq!(backup_path, dir.path().join("state_0.json.v0.bak"));
+        fs::write(&path, "rewritten").unwrap();
+        assert_eq!(backup(&path, 0).unwrap(), None);
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "{}");
+
+        let mut list = json!({"tasks": {}, "next_id": 3});
+        assert_eq!(migrate_task_list(&mut list, &path).unwrap(), 0);
+        assert_eq!(list["schema_version"], TASK_LIST_VERSION);
+    }
+}
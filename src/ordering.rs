@@ -0,0 +1,176 @@
+use crate::git;
+use crate::types::FileState;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Order in which files are queued (and selected when `--max-files` is set)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStrategy {
+    /// Order of entries in the input file
+    #[default]
+    Input,
+    /// Alphabetical by path
+    Path,
+    /// Highest numeric `priority` in the file's metadata first
+    Priority,
+    /// Smallest files first
+    Size,
+    /// Files touched by the most commits first
+    Churn,
+    /// Most recently committed files first
+    Recent,
+}
+
+/// Sort `paths` according to the strategy. Ties (and files the strategy has no
+/// information about) fall back to input order, then path, so the result is deterministic.
+pub async fn order_files(
+    paths: &mut [PathBuf],
+    files: &HashMap<PathBuf, FileState>,
+    strategy: OrderStrategy,
+    working_dir: &Path,
+) {
+    let fallback = |a: &PathBuf, b: &PathBuf| {
+        let index = |p: &PathBuf| files.get(p).and_then(|f| f.input_index).unwrap_or(usize::MAX);
+        index(a).cmp(&index(b)).then_with(|| a.cmp(b))
+    };
+
+    match strategy {
+        OrderStrategy::Input => paths.sort_by(fallback),
+        OrderStrategy::Path => paths.sort(),
+        OrderStrategy::Priority => {
+            let priority = |p: &PathBuf| {
+                files
+                    .get(p)
+                    .and_then(|f| f.original_data.get("priority"))
+                    .and_then(|v| v.as_f64())
+            };
+            paths.sort_by(|a, b| {
+                descending(priority(a), priority(b)).then_with(|| fallback(a, b))
+            });
+        }
+        OrderStrategy::Size => {
+            let sizes: HashMap<PathBuf, u64> = paths
+                .iter()
+                .filter_map(|p| {
+                    std::fs::metadata(working_dir.join(p))
+                        .ok()
+                        .map(|m| (p.clone(), m.len()))
+                })
+                .collect();
+            paths.sort_by(|a, b| {
+                match (sizes.get(a), sizes.get(b)) {
+                    (Some(x), Some(y)) => x.cmp(y),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+                .then_with(|| fallback(a, b))
+            });
+        }
+        OrderStrategy::Churn | OrderStrategy::Recent => {
+            let history = match git::file_history(working_dir).await {
+                Ok(history) => history,
+                Err(e) => {
+                    warn!(error = %e, "Failed to read git history, falling back to input order");
+                    HashMap::new()
+                }
+            };
+            let key = |p: &PathBuf| {
+                history.get(p).map(|h| {
+                    if strategy == OrderStrategy::Churn {
+                        h.commits as f64
+                    } else {
+                        h.last_commit as f64
+                    }
+                })
+            };
+            paths.sort_by(|a, b| descending(key(a), key(b)).then_with(|| fallback(a, b)));
+        }
+    }
+}
+
+/// Compare optional keys so that larger values come first and missing values last
+fn descending(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => y.partial_cmp(&x).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn files(entries: &[(&str, serde_json::Value)]) -> HashMap<PathBuf, FileState> {
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, (path, data))| {
+                let mut state = FileState::new(data.clone());
+                state.input_index = Some(i);
+                (PathBuf::from(path), state)
+            })
+            .collect()
+    }
+
+    fn paths(files: &HashMap<PathBuf, FileState>) -> Vec<PathBuf> {
+        files.keys().cloned().collect()
+    }
+
+    #[tokio::test]
+    async fn test_input_and_path_order() {
+        let files = files(&[("b.ts", json!({})), ("c.ts", json!({})), ("a.ts", json!({}))]);
+        let dir = Path::new(".");
+
+        let mut ordered = paths(&files);
+        order_files(&mut ordered, &files, OrderStrategy::Input, dir).await;
+        assert_eq!(ordered, vec![PathBuf::from("b.ts"), PathBuf::from("c.ts"), PathBuf::from("a.ts")]);
+
+        order_files(&mut ordered, &files, OrderStrategy::Path, dir).await;
+        assert_eq!(ordered, vec![PathBuf::from("a.ts"), PathBuf::from("b.ts"), PathBuf::from("c.ts")]);
+    }
+
+    #[tokio::test]
+    async fn test_priority_order_with_missing_priorities_last() {
+        let files = files(&[
+            ("low.ts", json!({"priority": 1})),
+            ("none_first.ts", json!({})),
+            ("high.ts", json!({"priority": 10})),
+            ("none_second.ts", json!({})),
+        ]);
+
+        let mut ordered = paths(&files);
+        order_files(&mut ordered, &files, OrderStrategy::Priority, Path::new(".")).await;
+        assert_eq!(
+            ordered,
+            vec![
+                PathBuf::from("high.ts"),
+                PathBuf::from("low.ts"),
+                PathBuf::from("none_first.ts"),
+                PathBuf::from("none_second.ts"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_size_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("big.ts"), "x".repeat(100)).unwrap();
+        std::fs::write(dir.path().join("small.ts"), "x").unwrap();
+        let files = files(&[("big.ts", json!({})), ("missing.ts", json!({})), ("small.ts", json!({}))]);
+
+        let mut ordered = paths(&files);
+        order_files(&mut ordered, &files, OrderStrategy::Size, dir.path()).await;
+        assert_eq!(
+            ordered,
+            vec![PathBuf::from("small.ts"), PathBuf::from("big.ts"), PathBuf::from("missing.ts")]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::types::ProcessOutput;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// What a stage runs for each file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageAction {
+    /// Prompt Claude (with the same file context and allowlist as the main prompt)
+    Prompt(String),
+    /// Run a shell command (with the same substitutions as --verify)
+    Command(String),
+}
+
+/// When a stage's run counts as passing
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PassCriteria {
+    /// Exit code the run must return
+    #[serde(default)]
+    pub exit_code: i32,
+    /// Text that must appear in stdout or stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_contains: Option<String>,
+    /// Text that must not appear in stdout or stderr
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_excludes: Option<String>,
+    /// Fields the agent's RESULT must have, e.g. `{"approved": true}`
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub result: serde_json::Map<String, serde_json::Value>,
+}
+
+impl PassCriteria {
+    /// Check a run against the criteria, describing the first one it misses
+    pub fn check(&self, output: &ProcessOutput, result: Option<&serde_json::Value>) -> Result<(), String> {
+        if output.exit_code != self.exit_code {
+            return Err(format!(
+                "exit code {} (expected {})",
+                output.exit_code, self.exit_code
+            ));
+        }
+        let contains = |text: &str| output.stdout.contains(text) || output.stderr.contains(text);
+        if let Some(ref text) = self.output_contains {
+            if !contains(text) {
+                return Err(format!("output does not contain '{}'", text));
+            }
+        }
+        if let Some(ref text) = self.output_excludes {
+            if contains(text) {
+                return Err(format!("output contains '{}'", text));
+            }
+        }
+        for (key, expected) in &self.result {
+            let actual = result.and_then(|r| r.get(key));
+            if actual != Some(expected) {
+                return Err(format!(
+                    "result field '{}' is {} (expected {})",
+                    key,
+                    actual.map(|v| v.to_string()).unwrap_or_else(|| "missing".to_string()),
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One stage of a user-defined pipeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub action: StageAction,
+    /// Most workers for this stage (defaults to its pool's limit)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Times the stage is rerun after missing its pass criteria
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Prompt sent with the failing output before each rerun
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixup_prompt: Option<String>,
+    #[serde(default)]
+    pub pass: PassCriteria,
+    /// Kill a command stage after this many seconds and count it as a failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// Pipeline file: either a list of stages or `{"stages": [...]}`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PipelineFile {
+    Stages(Vec<StageConfig>),
+    Object { stages: Vec<StageConfig> },
+}
+
+/// Load and check a pipeline definition
+pub fn load_pipeline(path: &Path) -> Result<Vec<StageConfig>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read pipeline file: {}", path.display()))?;
+    let stages = match serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse pipeline file: {}", path.display()))?
+    {
+        PipelineFile::Stages(stages) | PipelineFile::Object { stages } => stages,
+    };
+    validate(&stages).with_context(|| format!("Invalid pipeline: {}", path.display()))?;
+    Ok(stages)
+}
+
+fn validate(stages: &[StageConfig]) -> Result<()> {
+    if stages.is_empty() {
+        anyhow::bail!("pipeline has no stages");
+    }
+    let mut names = HashSet::new();
+    for stage in stages {
+        if stage.name.trim().is_empty() {
+            anyhow::bail!("stage name is empty");
+        }
+        if !names.insert(stage.name.as_str()) {
+            anyhow::bail!("duplicate stage name '{}'", stage.name);
+        }
+        if stage.concurrency == Some(0) {
+            anyhow::bail!("stage '{}' needs a concurrency of at least 1", stage.name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_stages() {
+        let file: PipelineFile = serde_json::from_value(json!({
+            "stages": [
+                {"name": "generate", "prompt": "Write tests", "concurrency": 3},
+                {"name": "test", "command": "npm test -- {file}", "max_retries": 2, "fixup_prompt": "Fix it"},
+                {"name": "review", "prompt": "Review", "pass": {"result": {"approved": true}}}
+            ]
+        }))
+        .unwrap();
+        let PipelineFile::Object { stages } = file else {
+            panic!("expected object form");
+        };
+
+        assert_eq!(stages[0].action, StageAction::Prompt("Write tests".to_string()));
+        assert_eq!(stages[0].concurrency, Some(3));
+        assert_eq!(stages[1].action, StageAction::Command("npm test -- {file}".to_string()));
+        assert_eq!(stages[1].max_retries, 2);
+        assert_eq!(stages[2].pass.result["approved"], json!(true));
+        assert!(validate(&stages).is_ok());
+
+        let mut duplicate = stages.clone();
+        duplicate[1].name = "generate".to_string();
+        assert!(validate(&duplicate).is_err());
+    }
+
+    #[test]
+    fn test_pass_criteria() {
+        let output = |exit_code: i32, stdout: &str| ProcessOutput {
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+            exit_code,
+            agent_usage: None,
+            is_error: false,
+        };
+        let criteria = PassCriteria {
+            output_excludes: Some("warning".to_string()),
+            result: serde_json::from_value(json!({"approved": true})).unwrap(),
+            ..Default::default()
+        };
+
+        let approved = json!({"approved": true, "notes": "ok"});
+        assert!(criteria.check(&output(0, "fine"), Some(&approved)).is_ok());
+        assert_eq!(
+            criteria.check(&output(1, "fine"), Some(&approved)).unwrap_err(),
+            "exit code 1 (expected 0)"
+        );
+        assert!(criteria.check(&output(0, "1 warning"), Some(&approved)).is_err());
+        assert_eq!(
+            criteria.check(&output(0, "fine"), None).unwrap_err(),
+            "result field 'approved' is missing (expected true)"
+        );
+    }
+}
@@ -1,15 +1,40 @@
 pub mod prompt;
+pub mod stage;
 pub mod verify;
 
+use crate::attempts::{self, ScopeContents};
+use crate::autoscale::VerifyStats;
+use crate::budget::BudgetHandle;
+use crate::cache;
+use crate::claude::{agent_failure, spawn_claude, wait_claude};
 use crate::config::Config;
+use crate::control::{ControlHandle, PoolGate};
+use crate::git::{check_git_changes_filtered, protect_dirty_files};
+use crate::lease;
 use crate::memory::MemoryHandle;
+use crate::process::{
+    expand_pattern_for_files, expand_pattern_with_allowlist, matches_allowlist, path_from_dir,
+    spawn_command, wait_command,
+};
+use crate::recovery;
+use crate::snapshot::{check_snapshot_changes_filtered, FsSnapshot};
 use crate::state::State;
+use crate::types::{AgentUsage, AttemptKind, FileStatus, FileTask, ProcessOutput, ProcessRecord};
 use crate::usage::UsageHandle;
-use std::path::PathBuf;
+use crate::verify_groups::VerifyGroups;
+use anyhow::Result;
+use async_channel::Sender;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Child;
 use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{error, info, warn};
 
 pub use prompt::spawn_prompt_pool;
+pub use stage::spawn_pipeline;
 pub use verify::spawn_verify_pool;
 
 /// Shared context passed to all pool workers
@@ -21,4 +46,533 @@ pub struct WorkerContext {
     pub working_dir: PathBuf,
     pub memory: MemoryHandle,
     pub usage: UsageHandle,
+    /// Operator pause state and pool sizes (changed through the control file)
+    pub control: ControlHandle,
+    /// Verify latencies and timeouts, read by the autoscaler
+    pub verify_stats: VerifyStats,
+    /// Task and per-file budgets
+    pub budget: BudgetHandle,
+    /// Limits on verifications that can't all run at once
+    pub verify_groups: Arc<VerifyGroups>,
+    /// Baseline manifest when tracking changes without git
+    pub snapshot: Option<Arc<FsSnapshot>>,
+    /// Where per-attempt diffs and transcripts are written
+    pub artifacts_dir: PathBuf,
+    /// `claude --version`, recorded in the fingerprint of each completed file.
+    /// None when the run isn't incremental, so no fingerprints are taken.
+    pub agent_version: Option<String>,
+    /// Notifies the dispatcher when a worker lets go of a file
+    pub done_tx: Sender<Released>,
+}
+
+/// A file a worker let go of, as reported to the dispatcher
+#[derive(Debug)]
+pub enum Released {
+    /// Finished (or left for --resume), so its dependents can be queued
+    Finished(PathBuf),
+    /// Leased by another runner, which is still working on it
+    LeasedElsewhere(PathBuf),
+}
+
+impl WorkerContext {
+    /// Tell the dispatcher a file is finished (completed or failed) so its dependents can be queued,
+    /// and release this runner's lease on it. In incremental runs, completed files get a fingerprint.
+    pub async fn file_finished(&self, path: &Path) {
+        let completed = {
+            let state = self.state.lock().await;
+            state
+                .files
+                .get(path)
+                .filter(|file| file.status == FileStatus::Completed)
+                .map(|file| file.original_data.clone())
+        };
+        let fingerprint = completed
+            .zip(self.agent_version.as_deref())
+            .and_then(|(original_data, agent)| {
+                cache::fingerprint(path, &original_data, &self.config, &self.working_dir, agent)
+            });
+        {
+            let mut state = self.state.lock().await;
+            if let Some(file) = state.file_mut(path) {
+                file.fingerprint = fingerprint;
+            }
+            state.release_lease(path);
+            if let Err(e) = state.save(&self.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+        }
+        let _ = self.done_tx.send(Released::Finished(path.to_path_buf())).await;
+    }
+
+    /// Wait until the operator, memory pressure and the API usage limit let a worker
+    /// start on its next file. `stage` names the pipeline stage in the log, if any.
+    pub async fn wait_until_clear(&self, worker_id: usize, stage: Option<&str>) {
+        // Wait if paused by the operator
+        if self.control.is_paused() {
+            info!(worker = worker_id, stage, "Paused, waiting for resume...");
+            self.control.wait_if_paused().await;
+            info!(worker = worker_id, stage, "Resuming after pause");
+        }
+
+        // Wait if memory pressure is high
+        if self.memory.is_paused() {
+            info!(worker = worker_id, stage, "Waiting for memory pressure to ease...");
+            self.memory.wait_if_paused().await;
+            info!(worker = worker_id, stage, "Resuming after memory recovery");
+        }
+
+        // Wait if API usage limit exceeded
+        if self.usage.is_paused() {
+            info!(worker = worker_id, stage, "Waiting for API usage quota to reset...");
+            self.usage.wait_if_paused().await;
+            info!(worker = worker_id, stage, "Resuming after usage quota reset");
+        }
+    }
+
+    /// Run Claude for `paths`, recording its usage against the task and each file.
+    /// Launch errors, failed runs (non-zero exit or an error reported by Claude) and timeouts
+    /// are retried (with backoff) up to the retry policy's limits.
+    /// Returns None if the run was aborted because the budget ran out (abort policy).
+    pub async fn run_agent(&self, prompt: &str, paths: &[PathBuf]) -> Option<Result<ProcessOutput>> {
+        // Kept until the run finishes so a resume after a crash can revert partial edits
+        for path in paths {
+            let scope = attempts::capture_scope(path, &self.config.allowlist_pattern, &self.working_dir);
+            if let Err(e) = attempts::save_inflight_scope(&self.artifacts_dir, path, &scope) {
+                warn!(file = %path.display(), error = %e, "Failed to save scope before agent run");
+            }
+        }
+        let result = self.run_agent_with_retries(prompt, paths).await;
+        for path in paths {
+            if let Err(e) = attempts::clear_inflight_scope(&self.artifacts_dir, path) {
+                warn!(file = %path.display(), error = %e, "Failed to remove scope snapshot");
+            }
+        }
+        result
+    }
+
+    async fn run_agent_with_retries(&self, prompt: &str, paths: &[PathBuf]) -> Option<Result<ProcessOutput>> {
+        let policy = &self.config.retry;
+        let timeout = policy.agent_timeout();
+        let (mut errors, mut timeouts) = (0, 0);
+
+        loop {
+            let result = match spawn_claude(prompt, &self.working_dir) {
+                Ok(child) => {
+                    self.track_process(paths, &child).await;
+                    let result = tokio::select! {
+                        result = wait_claude(child, timeout) => Some(result),
+                        _ = self.budget.aborted() => None,
+                    };
+                    self.untrack_process(paths).await;
+                    match result {
+                        Some(result) => result,
+                        None => return None,
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            let failure = match &result {
+                Ok(Some(output)) => agent_failure(output),
+                _ => None,
+            };
+            let (usage, retry) = match &result {
+                Ok(Some(output)) if failure.is_some() => {
+                    errors += 1;
+                    (output.agent_usage, (errors <= policy.agent_errors).then_some(errors))
+                }
+                Ok(Some(output)) => (output.agent_usage, None),
+                Ok(None) => {
+                    // Timed out runs still cost a call and their wall time
+                    let usage = AgentUsage {
+                        duration_secs: timeout.unwrap_or_default().as_secs_f64(),
+                        ..Default::default()
+                    };
+                    timeouts += 1;
+                    (Some(usage), (timeouts <= policy.agent_timeouts).then_some(timeouts))
+                }
+                Err(_) => {
+                    errors += 1;
+                    (None, (errors <= policy.agent_errors).then_some(errors))
+                }
+            };
+
+            let exhausted = match usage {
+                Some(usage) => {
+                    let totals = {
+                        let mut state = self.state.lock().await;
+                        state.record_usage(paths, &usage);
+                        state.usage.clone()
+                    };
+                    self.budget.check_task(&totals)
+                }
+                None => self.budget.is_exhausted(),
+            };
+
+            let error = match result {
+                Ok(Some(output)) => match failure {
+                    Some(failure) => anyhow::anyhow!(failure),
+                    None => return Some(Ok(output)),
+                },
+                Ok(None) => anyhow::anyhow!(
+                    "Claude timed out after {}s",
+                    timeout.unwrap_or_default().as_secs()
+                ),
+                Err(e) => e,
+            };
+            let Some(retry) = retry.filter(|_| !exhausted) else {
+                return Some(Err(error));
+            };
+
+            let delay = policy.backoff(retry);
+            warn!(
+                files = ?paths,
+                error = %error,
+                retry,
+                delay_secs = delay.as_secs(),
+                "Claude run failed, retrying"
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.budget.aborted() => return None,
+            }
+        }
+    }
+
+    /// Directory a file's verification and stage commands run in: `working_dir` in its
+    /// input data (relative to the task's working directory, for monorepo packages), or
+    /// the task's working directory. Fails if it is absolute or leaves the task's
+    /// working directory.
+    pub fn command_dir(&self, original_data: &serde_json::Value) -> Result<PathBuf> {
+        let Some(dir) = original_data.get("working_dir").and_then(|v| v.as_str()) else {
+            return Ok(self.working_dir.clone());
+        };
+        let inside = Path::new(dir)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            anyhow::bail!(
+                "Invalid working_dir {:?}: must be a relative path without '..'",
+                dir
+            );
+        }
+        Ok(self.working_dir.join(dir))
+    }
+
+    /// Expand a verification or stage command for `paths`, to be run in `dir`.
+    /// Paths in the command are relative to `dir`.
+    pub fn expand_command(&self, command: &str, paths: &[PathBuf], dir: &Path) -> String {
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| path_from_dir(path, &self.working_dir, dir))
+            .collect();
+        let allowlist = &self.config.allowlist_pattern;
+        match paths.as_slice() {
+            [path] => expand_pattern_with_allowlist(command, path, allowlist, dir),
+            paths => expand_pattern_for_files(command, paths, allowlist, dir),
+        }
+    }
+
+    /// Run a shell command (verification or a pipeline stage) for `paths` in `dir`.
+    /// Returns None if it is still running after `timeout` (it is killed).
+    pub async fn run_command(
+        &self,
+        command: &str,
+        paths: &[PathBuf],
+        dir: &Path,
+        timeout: Option<Duration>,
+    ) -> Result<Option<ProcessOutput>> {
+        let child = spawn_command(command, dir)?;
+        self.track_process(paths, &child).await;
+        let output = wait_command(child, timeout).await;
+        self.untrack_process(paths).await;
+        output
+    }
+
+    /// Record a spawned agent or command as working on `paths`, so a run after a crash
+    /// can find it if it outlives this runner
+    async fn track_process(&self, paths: &[PathBuf], child: &Child) {
+        let Some(child) = child.id().and_then(recovery::process_id) else {
+            return;
+        };
+        let record = ProcessRecord {
+            host: lease::host_name(),
+            runner: recovery::current_process(),
+            children: vec![child],
+        };
+        let mut state = self.state.lock().await;
+        for path in paths {
+            if let Some(file) = state.file_mut(path) {
+                file.process = Some(record.clone());
+            }
+        }
+        if let Err(e) = state.journal_files(&self.state_path, paths) {
+            error!(error = %e, "Failed to save state");
+        }
+    }
+
+    /// Forget the process working on `paths` once it has exited (saved with the next change)
+    async fn untrack_process(&self, paths: &[PathBuf]) {
+        let mut state = self.state.lock().await;
+        for path in paths {
+            if let Some(file) = state.file_mut(path) {
+                file.process = None;
+            }
+        }
+    }
+
+    /// Move files a worker picked up to `status`, dropping (and reporting finished) files
+    /// skipped by the operator while they were queued. Both happen under one lock, so a
+    /// skip can't land between the check and the status change.
+    pub async fn start_files(
+        &self,
+        tasks: Vec<FileTask>,
+        status: FileStatus,
+        worker_id: usize,
+    ) -> Vec<FileTask> {
+        let (skipped, tasks): (Vec<_>, Vec<_>) = {
+            let mut state = self.state.lock().await;
+            let (skipped, tasks): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|t| {
+                state.files.get(&t.path).map(|f| &f.status) == Some(&FileStatus::Skipped)
+            });
+            if !tasks.is_empty() {
+                for task in &tasks {
+                    state.update_status(&task.path, status.clone(), Some(worker_id));
+                }
+                if let Err(e) = state.save(&self.state_path) {
+                    error!(error = %e, "Failed to save state");
+                }
+            }
+            (skipped, tasks)
+        };
+        for task in skipped {
+            info!(file = %task.path.display(), "Skipping file");
+            self.file_finished(&task.path).await;
+        }
+        tasks
+    }
+
+    /// Lease files for this runner as a worker picks them up, dropping files another runner
+    /// on the same task has already finished (reported finished) or still holds (handed
+    /// back to the dispatcher, which watches them until they finish or their lease expires)
+    pub async fn claim(&self, tasks: Vec<FileTask>) -> Vec<FileTask> {
+        if tasks.is_empty() {
+            return tasks;
+        }
+        let paths: Vec<PathBuf> = tasks.iter().map(|t| t.path.clone()).collect();
+        let (claimed, finished): (Vec<PathBuf>, Vec<PathBuf>) = {
+            let mut state = self.state.lock().await;
+            let claimed =
+                match state.claim_files(&self.state_path, &paths, self.config.lease_ttl_secs) {
+                    Ok(claimed) => claimed,
+                    Err(e) => {
+                        error!(error = %e, "Failed to claim files, working on them anyway");
+                        paths.clone()
+                    }
+                };
+            let finished = paths
+                .into_iter()
+                .filter(|path| {
+                    state.files.get(path).is_some_and(|f| f.status.is_terminal())
+                })
+                .collect();
+            (claimed, finished)
+        };
+        let (tasks, taken): (Vec<_>, Vec<_>) =
+            tasks.into_iter().partition(|t| claimed.contains(&t.path));
+        for task in taken {
+            if finished.contains(&task.path) {
+                info!(file = %task.path.display(), "File was finished by another runner");
+                self.file_finished(&task.path).await;
+            } else {
+                info!(file = %task.path.display(), "File is leased by another runner, leaving it to them");
+                let _ = self.done_tx.send(Released::LeasedElsewhere(task.path)).await;
+            }
+        }
+        tasks
+    }
+}
+
+/// Spawn a pool's workers as its gate's limit grows, up to `max` workers (the gate's
+/// ceiling if None). Workers stay once spawned (a lower limit parks them at the gate).
+/// The returned task finishes once the workers do, when the pool's queue is closed.
+pub fn spawn_workers<F, Fut>(gate: Arc<PoolGate>, max: Option<usize>, worker: F) -> JoinHandle<()>
+where
+    F: Fn(usize) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let max = max.map_or(gate.ceiling(), |max| max.min(gate.ceiling()));
+    tokio::spawn(async move {
+        let mut workers = JoinSet::new();
+        let mut spawned = 0;
+        // Set when a worker exits: the queue is closed, so no more are needed
+        let mut closed = false;
+        loop {
+            tokio::select! {
+                _ = gate.wait_for_limit_above(spawned), if !closed && spawned < max => {
+                    workers.spawn(worker(spawned));
+                    spawned += 1;
+                }
+                Some(_) = workers.join_next(), if !workers.is_empty() => closed = true,
+                else => break,
+            }
+        }
+    })
+}
+
+/// Check for file changes outside `allowlists` (one expanded allowlist per file in the run)
+/// after an agent run, and handle edits to pre-existing dirty files per the dirty file policy.
+pub async fn check_unauthorized_changes(
+    ctx: &WorkerContext,
+    worker_id: usize,
+    file_display: &str,
+    allowlists: &[String],
+) {
+    let git_state = {
+        let state = ctx.state.lock().await;
+        state.git_state.clone()
+    };
+
+    let changes = if git_state.enabled {
+        check_git_changes_filtered(&allowlists[0], &ctx.working_dir, &git_state)
+            .await
+            .ok()
+    } else if let Some(ref snapshot) = ctx.snapshot {
+        let snapshot = Arc::clone(snapshot);
+        let allowlist = allowlists[0].clone();
+        let working_dir = ctx.working_dir.clone();
+        let git_state = git_state.clone();
+        tokio::task::spawn_blocking(move || {
+            check_snapshot_changes_filtered(&allowlist, &working_dir, &snapshot, &git_state)
+        })
+        .await
+        .ok()
+        .and_then(|r| r.ok())
+    } else {
+        None
+    };
+
+    if let Some((_, unauthorized)) = changes {
+        // In a batch, a change is only unauthorized if no file's allowlist covers it
+        let unauthorized: Vec<PathBuf> = unauthorized
+            .into_iter()
+            .filter(|p| !allowlists[1..].iter().any(|a| matches_allowlist(p, a)))
+            .collect();
+
+        if !unauthorized.is_empty() {
+            let unauthorized_list: Vec<_> = unauthorized
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            warn!(
+                worker = worker_id,
+                file = %file_display,
+                unauthorized = ?unauthorized_list,
+                "Detected unauthorized file changes (including edits to pre-existing dirty files)"
+            );
+            // Note: We log but don't fail - the verification step will catch issues
+
+            let restored = protect_dirty_files(
+                &unauthorized,
+                &ctx.working_dir,
+                &git_state,
+                ctx.config.git.dirty_file_policy,
+            )
+            .await;
+            if !restored.is_empty() {
+                warn!(
+                    worker = worker_id,
+                    file = %file_display,
+                    restored = ?restored,
+                    "Reverted edits to pre-existing dirty files"
+                );
+            }
+        }
+    }
+}
+
+/// Diff the file's scope against its content before an agent run, write the diff and
+/// transcript to the artifacts directory and append the attempt to the file's history.
+/// The caller is responsible for saving state afterwards.
+pub async fn record_attempt(
+    ctx: &WorkerContext,
+    path: &Path,
+    kind: AttemptKind,
+    before: &ScopeContents,
+    prompt: &str,
+    output: &ProcessOutput,
+) {
+    let after = attempts::recapture_scope(
+        before,
+        path,
+        &ctx.config.allowlist_pattern,
+        &ctx.working_dir,
+    );
+    let (diff, diffstat) = attempts::diff_scope(before, &after);
+    let transcript = format!(
+        "PROMPT:\n{}\n\nSTDOUT:\n{}\n\nSTDERR:\n{}\n\nEXIT CODE: {}\n",
+        prompt, output.stdout, output.stderr, output.exit_code
+    );
+
+    let mut state = ctx.state.lock().await;
+    let number = state
+        .files
+        .get(path)
+        .map(|f| f.attempt_log.len() as u32 + 1)
+        .unwrap_or(1);
+
+    match attempts::write_attempt(
+        &ctx.artifacts_dir,
+        path,
+        kind,
+        number,
+        &diff,
+        diffstat,
+        &transcript,
+    ) {
+        Ok(mut record) => {
+            record.changed_files = attempts::changed_paths(before, &after);
+            state.add_attempt(path, record)
+        }
+        Err(e) => warn!(file = %path.display(), error = %e, "Failed to record attempt"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_spawn_workers_grows_with_the_gate_limit() {
+        let gate = PoolGate::new(1, 4);
+        let started = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = async_channel::unbounded::<()>();
+        let pool = spawn_workers(Arc::clone(&gate), None, {
+            let started = Arc::clone(&started);
+            move |_| {
+                let started = Arc::clone(&started);
+                let rx = rx.clone();
+                async move {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    while rx.recv().await.is_ok() {}
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        // Raising the limit past the ceiling spawns up to the ceiling
+        gate.set_limit(10);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 4);
+
+        // Closing the queue ends the pool
+        drop(tx);
+        tokio::time::timeout(Duration::from_secs(1), pool)
+            .await
+            .unwrap()
+            .unwrap();
+    }
 }
@@ -1,117 +1,157 @@
-use super::WorkerContext;
-use crate::claude::{build_prompt, run_claude};
-use crate::git::check_git_changes_filtered;
-use crate::process::{expand_pattern, parse_result};
-use crate::types::{FileStatus, FileTask};
+use super::{check_unauthorized_changes, record_attempt, spawn_workers, WorkerContext};
+use crate::attempts::{capture_scope, save_original_scope, ScopeContents};
+use crate::claude::{build_batch_prompt, build_prompt};
+use crate::process::{expand_pattern, parse_batch_result, parse_result};
+use crate::types::{AttemptKind, FileStatus, FileTask};
 use async_channel::{Receiver, Sender};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::task::JoinHandle;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-/// Spawn a pool of prompt workers
+/// Spawn a pool of prompt workers, sized by the prompt gate.
+/// Each job is a batch of files sent to Claude in one prompt (a single file unless batching is on).
 pub fn spawn_prompt_pool(
-    concurrency: usize,
-    rx: Receiver<FileTask>,
+    rx: Receiver<Vec<FileTask>>,
     verify_tx: Sender<FileTask>,
     ctx: WorkerContext,
-) -> Vec<JoinHandle<()>> {
-    (0..concurrency)
-        .map(|worker_id| {
-            let rx = rx.clone();
-            let verify_tx = verify_tx.clone();
-            let ctx = ctx.clone();
-
-            tokio::spawn(async move {
-                prompt_worker(worker_id, rx, verify_tx, ctx).await;
-            })
-        })
-        .collect()
+) -> JoinHandle<()> {
+    let gate = Arc::clone(&ctx.control.prompt_gate);
+    spawn_workers(gate, None, move |worker_id| {
+        prompt_worker(worker_id, rx.clone(), verify_tx.clone(), ctx.clone())
+    })
 }
 
 async fn prompt_worker(
     worker_id: usize,
-    rx: Receiver<FileTask>,
+    rx: Receiver<Vec<FileTask>>,
     verify_tx: Sender<FileTask>,
     ctx: WorkerContext,
 ) {
-    while let Ok(task) = rx.recv().await {
-        // Wait if memory pressure is high
-        if ctx.memory.is_paused() {
-            info!(worker = worker_id, "Waiting for memory pressure to ease...");
-            ctx.memory.wait_if_paused().await;
-            info!(worker = worker_id, "Resuming after memory recovery");
+    loop {
+        // Only as many workers as the pool's current limit take files
+        let _permit = ctx.control.prompt_gate.acquire().await;
+        let Ok(batch) = rx.recv().await else {
+            break;
+        };
+
+        ctx.wait_until_clear(worker_id, None).await;
+
+        // Budget ran out while the files were queued: leave them pending for --resume
+        if ctx.budget.is_exhausted() {
+            for task in &batch {
+                debug!(file = %task.path.display(), "Budget exhausted, leaving file pending");
+                ctx.file_finished(&task.path).await;
+            }
+            continue;
         }
 
-        // Wait if API usage limit exceeded
-        if ctx.usage.is_paused() {
-            info!(worker = worker_id, "Waiting for API usage quota to reset...");
-            ctx.usage.wait_if_paused().await;
-            info!(worker = worker_id, "Resuming after usage quota reset");
+        let batch = ctx.claim(batch).await;
+        let batch = ctx
+            .start_files(batch, FileStatus::PromptInProgress, worker_id)
+            .await;
+        if batch.is_empty() {
+            continue;
         }
 
-        let file_display = task.path.display().to_string();
-        info!(worker = worker_id, file = %file_display, "Starting prompt task");
-
-        // Update status to in progress
-        {
-            let mut state = ctx.state.lock().await;
-            state.update_status(&task.path, FileStatus::PromptInProgress);
-            if let Err(e) = state.save(&ctx.state_path) {
-                error!(error = %e, "Failed to save state");
-            }
+        let file_display = batch
+            .iter()
+            .map(|t| t.path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if batch.len() > 1 {
+            info!(worker = worker_id, files = %file_display, count = batch.len(), "Starting batched prompt task");
+        } else {
+            info!(worker = worker_id, file = %file_display, "Starting prompt task");
         }
 
         // Build prompt
-        let prompt = build_prompt(
-            &ctx.config.prompt,
-            &task.path,
-            &task.original_data,
-            &ctx.config.allowlist_pattern,
-        );
-        let allowlist = expand_pattern(&ctx.config.allowlist_pattern, &task.path);
+        let prompt = match batch.as_slice() {
+            [task] => build_prompt(
+                &ctx.config.prompt,
+                &task.path,
+                &task.original_data,
+                &ctx.config.allowlist_pattern,
+            ),
+            tasks => build_batch_prompt(&ctx.config.prompt, tasks, &ctx.config.allowlist_pattern),
+        };
+        let allowlists: Vec<String> = batch
+            .iter()
+            .map(|t| expand_pattern(&ctx.config.allowlist_pattern, &t.path))
+            .collect();
+
+        let scopes_before: Vec<ScopeContents> = batch
+            .iter()
+            .map(|t| capture_scope(&t.path, &ctx.config.allowlist_pattern, &ctx.working_dir))
+            .collect();
+        if ctx.config.saves_original_scope() {
+            for (task, scope) in batch.iter().zip(&scopes_before) {
+                if let Err(e) = save_original_scope(&ctx.artifacts_dir, &task.path, scope) {
+                    warn!(file = %task.path.display(), error = %e, "Failed to save original scope");
+                }
+            }
+        }
 
         // Run Claude
-        match run_claude(&prompt, &ctx.working_dir).await {
-            Ok(output) => {
-                // Check for unauthorized file changes (filtering out pre-existing dirty files)
-                let git_state = {
-                    let state = ctx.state.lock().await;
-                    state.git_state.clone()
-                };
-
-                if git_state.enabled {
-                    if let Ok((_, unauthorized)) =
-                        check_git_changes_filtered(&allowlist, &ctx.working_dir, &git_state).await
-                    {
-                        if !unauthorized.is_empty() {
-                            let unauthorized_list: Vec<_> = unauthorized
-                                .iter()
-                                .map(|p| p.display().to_string())
-                                .collect();
-                            warn!(
-                                worker = worker_id,
-                                file = %file_display,
-                                unauthorized = ?unauthorized_list,
-                                "Detected unauthorized file changes (excluding pre-existing dirty files)"
-                            );
-                            // Note: We log but don't fail - the verification step will catch issues
-                        }
+        let paths: Vec<PathBuf> = batch.iter().map(|t| t.path.clone()).collect();
+        match ctx.run_agent(&prompt, &paths).await {
+            None => {
+                warn!(worker = worker_id, file = %file_display, "Prompt aborted, budget exhausted");
+                {
+                    let mut state = ctx.state.lock().await;
+                    for task in &batch {
+                        state.update_status(&task.path, FileStatus::Pending, Some(worker_id));
+                    }
+                    if let Err(e) = state.save(&ctx.state_path) {
+                        error!(error = %e, "Failed to save state");
                     }
                 }
+                for task in &batch {
+                    ctx.file_finished(&task.path).await;
+                }
+            }
+            Some(Ok(output)) => {
+                for (task, scope_before) in batch.iter().zip(&scopes_before) {
+                    record_attempt(
+                        &ctx,
+                        &task.path,
+                        AttemptKind::Prompt,
+                        scope_before,
+                        &prompt,
+                        &output,
+                    )
+                    .await;
+                }
 
-                // Parse result from output
-                let result = parse_result(&output.stdout);
+                check_unauthorized_changes(&ctx, worker_id, &file_display, &allowlists).await;
+
+                // Parse result from output (one result per file for a batch)
+                let mut results = if batch.len() > 1 {
+                    parse_batch_result(&output.stdout, &paths)
+                } else {
+                    HashMap::from([(batch[0].path.clone(), parse_result(&output.stdout))])
+                };
 
                 // Update state with result
                 let (prompt_done, total_files) = {
                     let mut state = ctx.state.lock().await;
-                    state.set_result(&task.path, result);
+                    for task in &batch {
+                        if let Some(result) = results.remove(&task.path) {
+                            state.set_result(&task.path, result);
+                        }
 
-                    if ctx.config.verification_cmd.is_some() {
-                        // Queue for verification
-                        state.update_status(&task.path, FileStatus::AwaitingVerification);
-                    } else {
-                        // No verification, mark as complete
-                        state.update_status(&task.path, FileStatus::Completed);
+                        if ctx.config.verification_cmd.is_some() {
+                            // Queue for verification
+                            state.update_status(
+                                &task.path,
+                                FileStatus::AwaitingVerification,
+                                Some(worker_id),
+                            );
+                        } else {
+                            // No verification, mark as complete
+                            state.update_status(&task.path, FileStatus::Completed, Some(worker_id));
+                        }
                     }
 
                     if let Err(e) = state.save(&ctx.state_path) {
@@ -133,24 +173,36 @@ async fn prompt_worker(
                     (total - not_prompted, total)
                 };
 
-                if ctx.config.verification_cmd.is_some() {
-                    // Send to verification queue
-                    if let Err(e) = verify_tx.send(task.clone()).await {
-                        error!(error = %e, file = %file_display, "Failed to queue for verification");
+                // Verification always runs per file
+                for task in &batch {
+                    if ctx.config.verification_cmd.is_some() {
+                        // Send to verification queue
+                        if let Err(e) = verify_tx.send(task.clone()).await {
+                            error!(error = %e, file = %task.path.display(), "Failed to queue for verification");
+                        }
+                    } else {
+                        ctx.file_finished(&task.path).await;
                     }
                 }
 
                 info!(worker = worker_id, file = %file_display, "Prompt task [{prompt_done} of {total_files}] complete");
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 error!(worker = worker_id, file = %file_display, error = %e, "Prompt task failed");
 
                 // Mark as failed
-                let mut state = ctx.state.lock().await;
-                state.update_status(&task.path, FileStatus::Failed);
-                state.set_error(&task.path, e.to_string());
-                if let Err(e) = state.save(&ctx.state_path) {
-                    error!(error = %e, "Failed to save state");
+                {
+                    let mut state = ctx.state.lock().await;
+                    for task in &batch {
+                        state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+                        state.set_error(&task.path, e.to_string());
+                    }
+                    if let Err(e) = state.save(&ctx.state_path) {
+                        error!(error = %e, "Failed to save state");
+                    }
+                }
+                for task in &batch {
+                    ctx.file_finished(&task.path).await;
                 }
             }
         }
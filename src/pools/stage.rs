@@ -0,0 +1,462 @@
+use super::verify::{append_to_failure_log, complete_file};
+use super::{check_unauthorized_changes, record_attempt, spawn_workers, WorkerContext};
+use crate::attempts::capture_scope;
+use crate::claude::{build_fixup_prompt, build_prompt};
+use crate::control::PoolGate;
+use crate::pipeline::{StageAction, StageConfig};
+use crate::process::{expand_pattern, parse_result};
+use crate::types::{AttemptKind, FileStatus, FileTask, ProcessOutput, StageStatus};
+use async_channel::{bounded, Receiver, Sender};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// How a file left a stage
+enum StageOutcome {
+    /// Passed; on to the next stage
+    Passed,
+    /// Failed or left for --resume; the file is finished for this run
+    Stopped,
+}
+
+/// Spawn a pool of workers for each pipeline stage, plus a router that sends each file
+/// from `rx` to the first stage it hasn't passed (so resumed files pick up where they stopped).
+/// Each stage's workers grow with its gate, up to the stage's `concurrency` if set.
+pub fn spawn_pipeline(
+    stages: &[StageConfig],
+    rx: Receiver<Vec<FileTask>>,
+    ctx: WorkerContext,
+    capacity: usize,
+) -> Vec<JoinHandle<()>> {
+    let channels: Vec<(Sender<FileTask>, Receiver<FileTask>)> =
+        stages.iter().map(|_| bounded(capacity)).collect();
+    let stages: Arc<[StageConfig]> = stages.into();
+    let mut handles = Vec::new();
+
+    for (index, stage) in stages.iter().enumerate() {
+        let gate = stage_gate(stage, &ctx);
+        let stages = Arc::clone(&stages);
+        let rx = channels[index].1.clone();
+        let next_tx = channels.get(index + 1).map(|(tx, _)| tx.clone());
+        let ctx = ctx.clone();
+
+        handles.push(spawn_workers(gate, stage.concurrency, move |worker_id| {
+            let stages = Arc::clone(&stages);
+            let (rx, next_tx, ctx) = (rx.clone(), next_tx.clone(), ctx.clone());
+            async move { stage_worker(worker_id, &stages[index], rx, next_tx, ctx).await }
+        }));
+    }
+
+    let senders: Vec<Sender<FileTask>> = channels.into_iter().map(|(tx, _)| tx).collect();
+    handles.push(tokio::spawn(route_files(stages, rx, senders, ctx)));
+    handles
+}
+
+/// Send each queued file to its first unfinished stage
+async fn route_files(
+    stages: Arc<[StageConfig]>,
+    rx: Receiver<Vec<FileTask>>,
+    senders: Vec<Sender<FileTask>>,
+    ctx: WorkerContext,
+) {
+    while let Ok(batch) = rx.recv().await {
+        for task in batch {
+            let next = {
+                let state = ctx.state.lock().await;
+                let file = state.files.get(&task.path);
+                stages.iter().position(|stage| {
+                    file.and_then(|f| f.stages.get(&stage.name))
+                        .map(|s| s.status)
+                        != Some(StageStatus::Passed)
+                })
+            };
+            match next {
+                Some(index) => {
+                    if index > 0 {
+                        debug!(file = %task.path.display(), stage = %stages[index].name, "Resuming at stage");
+                    }
+                    if let Err(e) = senders[index].send(task.clone()).await {
+                        error!(error = %e, file = %task.path.display(), "Failed to queue file for stage");
+                        ctx.file_finished(&task.path).await;
+                    }
+                }
+                None => {
+                    // Every stage passed before the last run stopped
+                    complete_file(0, &ctx, &task.path).await;
+                    ctx.file_finished(&task.path).await;
+                }
+            }
+        }
+    }
+}
+
+/// Prompt stages share the prompt gate and command stages the verify gate, so
+/// `ctl concurrency` and --auto-concurrency size them like the usual pools
+fn stage_gate(stage: &StageConfig, ctx: &WorkerContext) -> Arc<PoolGate> {
+    match stage.action {
+        StageAction::Prompt(_) => Arc::clone(&ctx.control.prompt_gate),
+        StageAction::Command(_) => Arc::clone(&ctx.control.verify_gate),
+    }
+}
+
+async fn stage_worker(
+    worker_id: usize,
+    stage: &StageConfig,
+    rx: Receiver<FileTask>,
+    next_tx: Option<Sender<FileTask>>,
+    ctx: WorkerContext,
+) {
+    let gate = stage_gate(stage, &ctx);
+    while let Ok(task) = rx.recv().await {
+        // The slot is taken after the file so that idle workers of one stage
+        // don't hold slots another stage of the same kind could use
+        let _permit = gate.acquire().await;
+        ctx.wait_until_clear(worker_id, Some(&stage.name)).await;
+
+        // Budget ran out while the file was queued: it resumes at this stage
+        if ctx.budget.is_exhausted() {
+            leave_stage(stage, &ctx, &task.path).await;
+            ctx.file_finished(&task.path).await;
+            continue;
+        }
+
+        let task = ctx.claim(vec![task]).await;
+        let Some(task) = ctx.start_files(task, FileStatus::InStage, worker_id).await.pop() else {
+            continue;
+        };
+
+        match run_stage(worker_id, stage, &task, &ctx).await {
+            StageOutcome::Passed => match next_tx {
+                Some(ref next_tx) => {
+                    if let Err(e) = next_tx.send(task.clone()).await {
+                        error!(error = %e, file = %task.path.display(), "Failed to queue file for next stage");
+                        ctx.file_finished(&task.path).await;
+                    }
+                }
+                None => {
+                    complete_file(worker_id, &ctx, &task.path).await;
+                    info!(worker = worker_id, file = %task.path.display(), "Pipeline complete");
+                    ctx.file_finished(&task.path).await;
+                }
+            },
+            StageOutcome::Stopped => ctx.file_finished(&task.path).await,
+        }
+    }
+
+    info!(worker = worker_id, stage = %stage.name, "Stage worker shutting down");
+}
+
+/// Run a stage for one file until it passes, runs out of retries or has to stop
+async fn run_stage(
+    worker_id: usize,
+    stage: &StageConfig,
+    task: &FileTask,
+    ctx: &WorkerContext,
+) -> StageOutcome {
+    let file_display = task.path.display().to_string();
+    let path = task.path.as_path();
+    let mut attempts = {
+        let mut state = ctx.state.lock().await;
+        state.update_status(path, FileStatus::InStage, Some(worker_id));
+        state.update_stage(path, &stage.name, |s| s.status = StageStatus::Running);
+        if let Err(e) = state.save(&ctx.state_path) {
+            error!(error = %e, "Failed to save state");
+        }
+        state
+            .files
+            .get(path)
+            .and_then(|f| f.stages.get(&stage.name))
+            .map(|s| s.attempts)
+            .unwrap_or(0)
+    };
+
+    loop {
+        info!(worker = worker_id, stage = %stage.name, file = %file_display, attempt = attempts + 1, "Starting stage");
+
+        let (output, result) = match stage.action {
+            StageAction::Prompt(ref prompt) => {
+                let prompt = build_prompt(
+                    prompt,
+                    path,
+                    &task.original_data,
+                    &ctx.config.allowlist_pattern,
+                );
+                match run_agent_step(worker_id, ctx, path, AttemptKind::Prompt, &prompt).await {
+                    Some(Ok(output)) => {
+                        let parsed = parse_result(&output.stdout);
+                        let result = (!parsed.is_raw).then(|| parsed.value.clone());
+                        let mut state = ctx.state.lock().await;
+                        state.set_result(path, parsed);
+                        (output, result)
+                    }
+                    Some(Err(e)) => return fail_stage(worker_id, stage, ctx, path, e.to_string()).await,
+                    None => return leave_stage(stage, ctx, path).await,
+                }
+            }
+            StageAction::Command(ref command) => {
+                let paths = [path.to_path_buf()];
+                let dir = match ctx.command_dir(&task.original_data) {
+                    Ok(dir) => dir,
+                    Err(e) => return fail_stage(worker_id, stage, ctx, path, e.to_string()).await,
+                };
+                let cmd = ctx.expand_command(command, &paths, &dir);
+                let timeout = stage.timeout_secs.map(Duration::from_secs);
+                let started = Instant::now();
+                let output = ctx.run_command(&cmd, &paths, &dir, timeout).await;
+                if let Ok(ref output) = output {
+                    ctx.verify_stats.record(started.elapsed(), output.is_none());
+                }
+                match output {
+                    Ok(Some(output)) => (output, None),
+                    Ok(None) => (
+                        ProcessOutput {
+                            stdout: String::new(),
+                            stderr: format!("Timed out after {}s", stage.timeout_secs.unwrap_or_default()),
+                            exit_code: -1,
+                            agent_usage: None,
+                            is_error: false,
+                        },
+                        None,
+                    ),
+                    Err(e) => return fail_stage(worker_id, stage, ctx, path, e.to_string()).await,
+                }
+            }
+        };
+
+        let reason = match stage.pass.check(&output, result.as_ref()) {
+            Ok(()) => {
+                info!(worker = worker_id, stage = %stage.name, file = %file_display, "Stage PASSED");
+                let mut state = ctx.state.lock().await;
+                state.update_stage(path, &stage.name, |s| {
+                    s.status = StageStatus::Passed;
+                    s.last_error = None;
+                });
+                if let Err(e) = state.save(&ctx.state_path) {
+                    error!(error = %e, "Failed to save state");
+                }
+                return StageOutcome::Passed;
+            }
+            Err(reason) => reason,
+        };
+
+        attempts += 1;
+        {
+            let mut state = ctx.state.lock().await;
+            state.update_stage(path, &stage.name, |s| {
+                s.attempts = attempts;
+                s.last_error = Some(reason.clone());
+            });
+        }
+        let error_output = if output.stderr.is_empty() {
+            &output.stdout
+        } else {
+            &output.stderr
+        };
+        append_to_failure_log(
+            &ctx.artifacts_dir,
+            path,
+            &format!(
+                "STAGE '{}' FAILED (attempt {}/{}): {}\n\nOutput:\n{}",
+                stage.name,
+                attempts,
+                stage.max_retries + 1,
+                reason,
+                error_output
+            ),
+        );
+
+        if attempts > stage.max_retries {
+            let error = format!("Stage '{}' failed: {}", stage.name, reason);
+            return fail_stage(worker_id, stage, ctx, path, error).await;
+        }
+
+        // No new agent runs once the task budget is gone; the stage reruns on --resume
+        if ctx.budget.is_exhausted() {
+            return leave_stage(stage, ctx, path).await;
+        }
+        let file_usage = {
+            let state = ctx.state.lock().await;
+            state.files.get(path).map(|f| f.usage.clone()).unwrap_or_default()
+        };
+        if let Some(reason) = ctx.budget.check_file(&file_usage) {
+            let error = format!("File budget exhausted: {}", reason);
+            return fail_stage(worker_id, stage, ctx, path, error).await;
+        }
+
+        warn!(
+            worker = worker_id,
+            stage = %stage.name,
+            file = %file_display,
+            attempt = attempts,
+            max = stage.max_retries,
+            reason = %reason,
+            "Stage did not pass, retrying"
+        );
+
+        let delay = ctx.config.retry.backoff(attempts);
+        if !delay.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = ctx.budget.aborted() => return leave_stage(stage, ctx, path).await,
+            }
+        }
+
+        if let Some(ref fixup_prompt) = stage.fixup_prompt {
+            let error = format!("{}\n\n{}", reason, error_output);
+            let prompt = build_fixup_prompt(fixup_prompt, path, &error, &ctx.config.allowlist_pattern);
+            match run_agent_step(worker_id, ctx, path, AttemptKind::Fixup, &prompt).await {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return fail_stage(worker_id, stage, ctx, path, e.to_string()).await,
+                None => return leave_stage(stage, ctx, path).await,
+            }
+        }
+    }
+}
+
+/// Run Claude for a stage, record the attempt and check it for unauthorized changes.
+/// Returns None if the run was aborted because the budget ran out.
+async fn run_agent_step(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    path: &Path,
+    kind: AttemptKind,
+    prompt: &str,
+) -> Option<anyhow::Result<ProcessOutput>> {
+    // Wait if API usage limit exceeded before calling Claude
+    if ctx.usage.is_paused() {
+        ctx.usage.wait_if_paused().await;
+    }
+    let scope_before = capture_scope(path, &ctx.config.allowlist_pattern, &ctx.working_dir);
+    let result = ctx.run_agent(prompt, &[path.to_path_buf()]).await?;
+    if let Ok(ref output) = result {
+        record_attempt(ctx, path, kind, &scope_before, prompt, output).await;
+        let allowlist = expand_pattern(&ctx.config.allowlist_pattern, path);
+        check_unauthorized_changes(ctx, worker_id, &path.display().to_string(), &[allowlist]).await;
+    }
+    Some(result)
+}
+
+/// Mark the stage and the file failed
+async fn fail_stage(
+    worker_id: usize,
+    stage: &StageConfig,
+    ctx: &WorkerContext,
+    path: &Path,
+    error: String,
+) -> StageOutcome {
+    warn!(worker = worker_id, stage = %stage.name, file = %path.display(), error = %error, "Stage FAILED");
+    append_to_failure_log(&ctx.artifacts_dir, path, &format!("FINAL STATUS: FAILED in stage '{}'", stage.name));
+
+    let mut state = ctx.state.lock().await;
+    state.update_stage(path, &stage.name, |s| s.status = StageStatus::Failed);
+    state.update_status(path, FileStatus::Failed, Some(worker_id));
+    state.set_error(path, error);
+    if let Err(e) = state.save(&ctx.state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+    StageOutcome::Stopped
+}
+
+/// Leave the file pending at this stage for --resume (the budget ran out)
+async fn leave_stage(stage: &StageConfig, ctx: &WorkerContext, path: &Path) -> StageOutcome {
+    warn!(stage = %stage.name, file = %path.display(), "Budget exhausted, leaving file at stage");
+    let mut state = ctx.state.lock().await;
+    state.update_stage(path, &stage.name, |s| s.status = StageStatus::Pending);
+    state.update_status(path, FileStatus::Pending, None);
+    if let Err(e) = state.save(&ctx.state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+    StageOutcome::Stopped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autoscale::VerifyStats;
+    use crate::budget::BudgetHandle;
+    use crate::cli::Cli;
+    use crate::config::Config;
+    use crate::control::ControlHandle;
+    use crate::memory::MemoryMonitor;
+    use crate::pipeline::PassCriteria;
+    use crate::state::State;
+    use crate::types::FileState;
+    use crate::usage::UsageMonitor;
+    use crate::verify_groups::VerifyGroups;
+    use clap::Parser;
+    use tokio::sync::Mutex;
+
+    fn context(dir: &Path, files: &[&str]) -> WorkerContext {
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let config = Config::from_cli(&cli).unwrap();
+        let mut state = State::new(config.clone());
+        for file in files {
+            let data = serde_json::json!({ "file": file });
+            state.files.insert(file.into(), FileState::new(data));
+        }
+        WorkerContext {
+            state: Arc::new(Mutex::new(state)),
+            state_path: dir.join("state.json"),
+            budget: BudgetHandle::new(config.budget.clone(), 0.0),
+            config: Arc::new(config),
+            working_dir: dir.to_path_buf(),
+            memory: MemoryMonitor::new().handle(),
+            usage: UsageMonitor::new().handle(),
+            control: ControlHandle::new(1, 1, 1),
+            verify_stats: VerifyStats::default(),
+            verify_groups: Arc::new(VerifyGroups::new(&[])),
+            snapshot: None,
+            artifacts_dir: dir.join("artifacts"),
+            agent_version: None,
+            done_tx: async_channel::unbounded().0,
+        }
+    }
+
+    fn command_stage(command: &str) -> StageConfig {
+        StageConfig {
+            name: "check".into(),
+            action: StageAction::Command(command.into()),
+            concurrency: None,
+            max_retries: 1,
+            fixup_prompt: None,
+            pass: PassCriteria::default(),
+            timeout_secs: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_stage_runs_a_command_stage() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("good.txt"), "ok\n").unwrap();
+        std::fs::write(dir.path().join("bad.txt"), "no\n").unwrap();
+        let ctx = context(dir.path(), &["good.txt", "bad.txt"]);
+        let stage = command_stage("grep -q ok {file}");
+
+        let task = |path: &str| FileTask {
+            path: path.into(),
+            original_data: serde_json::json!({ "file": path }),
+        };
+
+        let outcome = run_stage(0, &stage, &task("good.txt"), &ctx).await;
+        assert!(matches!(outcome, StageOutcome::Passed));
+
+        // Out of retries: the stage and the file fail, with every attempt in the failure log
+        let outcome = run_stage(0, &stage, &task("bad.txt"), &ctx).await;
+        assert!(matches!(outcome, StageOutcome::Stopped));
+
+        let state = ctx.state.lock().await;
+        let good = &state.files[Path::new("good.txt")];
+        assert_eq!(good.stages["check"].status, StageStatus::Passed);
+        assert_eq!(good.stages["check"].attempts, 0);
+        let bad = &state.files[Path::new("bad.txt")];
+        assert_eq!(bad.status, FileStatus::Failed);
+        assert_eq!(bad.stages["check"].status, StageStatus::Failed);
+        assert_eq!(bad.stages["check"].attempts, 2);
+
+        let log = std::fs::read_to_string(dir.path().join("artifacts/failures/bad.txt.log")).unwrap();
+        assert!(log.contains("STAGE 'check' FAILED (attempt 2/2)"));
+        assert!(log.contains("FINAL STATUS: FAILED in stage 'check'"));
+    }
+}
@@ -1,50 +1,57 @@
-use super::WorkerContext;
-use crate::claude::{build_fixup_prompt, run_claude};
+use super::{record_attempt, spawn_workers, WorkerContext};
+use crate::attempts::{
+    capture_scope, diff_scope, load_original_scope, recapture_scope, restore_original_scope,
+};
+use crate::bisect::bisect;
+use crate::claude::{build_fixup_prompt, build_prompt, build_review_prompt};
+use crate::config::VerifyBatchConfig;
+use crate::control::GatePermit;
 use crate::git::commit_file_changes;
-use crate::process::{expand_pattern_with_allowlist, parse_result, run_command};
-use crate::types::{FileStatus, FileTask};
+use crate::process::{parse_result, parse_verdict};
+use crate::types::{AttemptKind, FileStatus, FileTask, ProcessOutput};
 use async_channel::Receiver;
 use chrono::Utc;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
-use std::path::{Path, PathBuf};
-use tokio::task::JoinHandle;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::{debug, error, info, warn};
 
-/// Spawn a pool of verification workers
-pub fn spawn_verify_pool(
-    concurrency: usize,
-    rx: Receiver<FileTask>,
-    ctx: WorkerContext,
-    tasks_dir: PathBuf,
-) -> Vec<JoinHandle<()>> {
-    (0..concurrency)
-        .map(|worker_id| {
-            let rx = rx.clone();
-            let ctx = ctx.clone();
-            let tasks_dir = tasks_dir.clone();
+/// Spawn a pool of verification workers, sized by the verify gate
+pub fn spawn_verify_pool(rx: Receiver<FileTask>, ctx: WorkerContext) -> JoinHandle<()> {
+    // With batched verification, one worker at a time takes queued files into its batch
+    let collecting = Arc::new(Mutex::new(()));
 
-            tokio::spawn(async move {
-                verify_worker(worker_id, rx, ctx, tasks_dir).await;
-            })
-        })
-        .collect()
+    let gate = Arc::clone(&ctx.control.verify_gate);
+    spawn_workers(gate, None, move |worker_id| {
+        verify_worker(worker_id, rx.clone(), ctx.clone(), Arc::clone(&collecting))
+    })
+}
+
+/// Failure log for a file in a task, under the task's artifacts directory
+pub fn failure_log_path(artifacts_dir: &Path, file_path: &Path) -> PathBuf {
+    let relative: PathBuf = file_path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let mut log = artifacts_dir.join("failures").join(relative).into_os_string();
+    log.push(".log");
+    PathBuf::from(log)
 }
 
 /// Append a message to the failure log for a file
-fn append_to_failure_log(tasks_dir: &Path, file_path: &Path, message: &str) {
-    let failures_dir = tasks_dir.join("failures");
-    if let Err(e) = fs::create_dir_all(&failures_dir) {
-        error!(error = %e, "Failed to create failures directory");
-        return;
+pub(super) fn append_to_failure_log(artifacts_dir: &Path, file_path: &Path, message: &str) {
+    let log_path = failure_log_path(artifacts_dir, file_path);
+    if let Some(dir) = log_path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            error!(error = %e, "Failed to create failures directory");
+            return;
+        }
     }
 
-    let log_name = file_path
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
-    let log_path = failures_dir.join(format!("{}.log", log_name));
-
     let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
 
     match OpenOptions::new().create(true).append(true).open(&log_path) {
@@ -64,252 +71,903 @@ async fn verify_worker(
     worker_id: usize,
     rx: Receiver<FileTask>,
     ctx: WorkerContext,
-    tasks_dir: PathBuf,
+    collecting: Arc<Mutex<()>>,
 ) {
     let verification_cmd = match &ctx.config.verification_cmd {
         Some(cmd) => cmd.clone(),
         None => return,
     };
 
-    while let Ok(task) = rx.recv().await {
-        // Wait if memory pressure is high
-        if ctx.memory.is_paused() {
-            info!(worker = worker_id, "Waiting for memory pressure to ease...");
-            ctx.memory.wait_if_paused().await;
-            info!(worker = worker_id, "Resuming after memory recovery");
+    loop {
+        let Ok(task) = rx.recv().await else {
+            break;
+        };
+        let mut tasks = vec![task];
+        if ctx.config.verify_batch.enabled() {
+            collect_batch(&rx, &mut tasks, &ctx.config.verify_batch, &collecting).await;
+        }
+        // Only as many workers as the pool's current limit verify at once. The slot is taken
+        // after the files so that idle workers never hold one (see `acquire_group`).
+        let mut permit = Some(ctx.control.verify_gate.acquire().await);
+
+        ctx.wait_until_clear(worker_id, None).await;
+
+        // Under the abort policy nothing new starts once the budget is gone;
+        // the files stay awaiting verification for --resume
+        if ctx.budget.is_aborting() {
+            for task in &tasks {
+                debug!(file = %task.path.display(), "Budget exhausted, leaving file awaiting verification");
+                ctx.file_finished(&task.path).await;
+            }
+            continue;
         }
 
-        // Wait if API usage limit exceeded
-        if ctx.usage.is_paused() {
-            info!(worker = worker_id, "Waiting for API usage quota to reset...");
-            ctx.usage.wait_if_paused().await;
-            info!(worker = worker_id, "Resuming after usage quota reset");
+        let tasks = ctx.claim(tasks).await;
+        let tasks = ctx
+            .start_files(tasks, FileStatus::VerifyInProgress, worker_id)
+            .await;
+        if tasks.is_empty() {
+            continue;
         }
-        let file_display = task.path.display().to_string();
-        let mut attempts = {
-            let state = ctx.state.lock().await;
-            state.get_attempts(&task.path)
-        };
 
-        loop {
-            info!(
+        verify_batch(worker_id, &ctx, &verification_cmd, tasks, &mut permit).await;
+    }
+
+    info!(worker = worker_id, "Verify worker shutting down");
+}
+
+/// Wait up to the batch window for more files to share a verify run. Files already
+/// queued are taken under `collecting`, so one worker at a time drains a burst into
+/// its batch; the wait for more happens without it.
+async fn collect_batch(
+    rx: &Receiver<FileTask>,
+    tasks: &mut Vec<FileTask>,
+    config: &VerifyBatchConfig,
+    collecting: &Mutex<()>,
+) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(config.wait_secs);
+    while tasks.len() < config.size {
+        {
+            let _collecting = collecting.lock().await;
+            while tasks.len() < config.size {
+                let Ok(task) = rx.try_recv() else {
+                    break;
+                };
+                tasks.push(task);
+            }
+        }
+        if tasks.len() >= config.size {
+            break;
+        }
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(task)) => tasks.push(task),
+            // Window elapsed, or no more files are coming
+            _ => break,
+        }
+    }
+}
+
+/// Take a slot in the file's verify group. While waiting, the verify slot (if held) is
+/// released so files outside the group keep verifying.
+async fn acquire_group(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    group: Option<&str>,
+    permit: &mut Option<GatePermit>,
+    file_display: &str,
+) -> Option<OwnedSemaphorePermit> {
+    let name = group?;
+    if let Some(group_permit) = ctx.verify_groups.try_acquire(name) {
+        return Some(group_permit);
+    }
+
+    info!(worker = worker_id, file = %file_display, group = %name, "Waiting for verify group");
+    let held = permit.take().is_some();
+    let group_permit = ctx.verify_groups.acquire(name).await;
+    if held {
+        *permit = Some(ctx.control.verify_gate.acquire().await);
+    }
+    Some(group_permit)
+}
+
+/// Run a verification command, applying the timeout and recording its latency.
+/// A timeout is reported as a failed run.
+async fn run_verification(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    cmd: &str,
+    paths: &[PathBuf],
+    dir: &Path,
+    file_display: &str,
+) -> anyhow::Result<ProcessOutput> {
+    let timeout = ctx.config.verify_timeout_secs.map(Duration::from_secs);
+    let started = Instant::now();
+    match ctx.run_command(cmd, paths, dir, timeout).await? {
+        Some(result) => {
+            ctx.verify_stats.record(started.elapsed(), false);
+            Ok(result)
+        }
+        None => {
+            ctx.verify_stats.record(started.elapsed(), true);
+            let secs = ctx.config.verify_timeout_secs.unwrap_or_default();
+            warn!(
                 worker = worker_id,
                 file = %file_display,
-                attempt = attempts + 1,
-                "Starting verification"
+                timeout_secs = secs,
+                "Verification timed out"
             );
+            Ok(ProcessOutput {
+                stdout: String::new(),
+                stderr: format!("Verification timed out after {}s", secs),
+                exit_code: -1,
+                agent_usage: None,
+                is_error: false,
+            })
+        }
+    }
+}
 
-            // Update status
-            {
-                let mut state = ctx.state.lock().await;
-                state.update_status(&task.path, FileStatus::VerifyInProgress);
-                if let Err(e) = state.save(&ctx.state_path) {
-                    error!(error = %e, "Failed to save state");
+/// Mark a file that passed verification as completed, committing it if enabled
+pub(super) async fn complete_file(worker_id: usize, ctx: &WorkerContext, path: &Path) {
+    let file_display = path.display().to_string();
+
+    // Auto-commit if enabled
+    if ctx.config.git.auto_commit {
+        let description = ctx.config.git.commit_message_template.as_deref();
+        match commit_file_changes(&ctx.working_dir, path, description).await {
+            Ok(Some(hash)) => {
+                info!(
+                    worker = worker_id,
+                    file = %file_display,
+                    commit = %hash,
+                    "Auto-committed changes"
+                );
+            }
+            Ok(None) => {
+                debug!(
+                    worker = worker_id,
+                    file = %file_display,
+                    "No changes to commit"
+                );
+            }
+            Err(e) => {
+                warn!(
+                    worker = worker_id,
+                    file = %file_display,
+                    error = %e,
+                    "Failed to auto-commit (continuing anyway)"
+                );
+            }
+        }
+    }
+
+    let mut state = ctx.state.lock().await;
+    state.update_status(path, FileStatus::Completed, Some(worker_id));
+    if let Err(e) = state.save(&ctx.state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+}
+
+/// Verify files with one command run per verify group. When a run fails, the files are
+/// bisected to find the ones that fail on their own; only those go through fixups.
+async fn verify_batch(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    verification_cmd: &str,
+    tasks: Vec<FileTask>,
+    permit: &mut Option<GatePermit>,
+) {
+    // Files in different verify groups, or whose commands run in different directories,
+    // never share a run
+    let mut groups: Vec<(Option<String>, PathBuf, Vec<FileTask>)> = Vec::new();
+    for task in tasks {
+        let group = ctx.verify_groups.group_for(&task.path, &task.original_data);
+        let Ok(dir) = ctx.command_dir(&task.original_data) else {
+            // Verified alone, which fails it with the reason
+            verify_file(worker_id, ctx, verification_cmd, &task, permit, None).await;
+            ctx.file_finished(&task.path).await;
+            continue;
+        };
+        match groups.iter_mut().find(|(g, d, _)| *g == group && *d == dir) {
+            Some((_, _, tasks)) => tasks.push(task),
+            None => groups.push((group, dir, vec![task])),
+        }
+    }
+
+    for (group, dir, mut tasks) in groups {
+        if tasks.len() == 1 {
+            let task = tasks.remove(0);
+            verify_file(worker_id, ctx, verification_cmd, &task, permit, None).await;
+            ctx.file_finished(&task.path).await;
+            continue;
+        }
+
+        let file_display = tasks
+            .iter()
+            .map(|t| t.path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(
+            worker = worker_id,
+            files = %file_display,
+            count = tasks.len(),
+            group = ?group,
+            "Starting batch verification"
+        );
+        {
+            let mut state = ctx.state.lock().await;
+            for task in &tasks {
+                state.update_status(&task.path, FileStatus::VerifyInProgress, Some(worker_id));
+            }
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+        }
+
+        // Each run owns its inputs so bisection can call it repeatedly. Runs keep this
+        // worker's verify slot while waiting for the group; the whole batch shares one group.
+        let check = |subset: Vec<FileTask>| {
+            let ctx = ctx.clone();
+            let group = group.clone();
+            let dir = dir.clone();
+            let verification_cmd = verification_cmd.to_string();
+            async move {
+                let paths: Vec<PathBuf> = subset.iter().map(|t| t.path.clone()).collect();
+                let files_display = paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let cmd = ctx.expand_command(&verification_cmd, &paths, &dir);
+
+                let _group_permit =
+                    acquire_group(worker_id, &ctx, group.as_deref(), &mut None, &files_display)
+                        .await;
+                let result = run_verification(worker_id, &ctx, &cmd, &paths, &dir, &files_display)
+                    .await
+                    .unwrap_or_else(|e| ProcessOutput {
+                        stdout: String::new(),
+                        stderr: format!("Verification command failed to execute: {}", e),
+                        exit_code: -1,
+                        agent_usage: None,
+                        is_error: false,
+                    });
+                debug!(
+                    worker = worker_id,
+                    files = %files_display,
+                    exit_code = result.exit_code,
+                    "Batch verification run"
+                );
+                if result.exit_code == 0 {
+                    Ok(())
+                } else {
+                    Err(result)
                 }
             }
+        };
 
-            // Run verification command
-            let cmd = expand_pattern_with_allowlist(
-                &verification_cmd,
-                &task.path,
-                &ctx.config.allowlist_pattern,
+        let (failing, passing) = match check(tasks.clone()).await {
+            Ok(()) => (Vec::new(), tasks),
+            Err(output) => {
+                warn!(
+                    worker = worker_id,
+                    files = %file_display,
+                    "Batch verification failed, bisecting"
+                );
+                bisect(tasks, output, check).await
+            }
+        };
+
+        // With a review, passing files go through verify_file as already-verified
+        let mut follow_up = Vec::new();
+        for task in passing {
+            if ctx.config.review_rubric.is_some() {
+                let passed = ProcessOutput {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    exit_code: 0,
+                    agent_usage: None,
+                    is_error: false,
+                };
+                follow_up.push((task, passed));
+                continue;
+            }
+            info!(worker = worker_id, file = %task.path.display(), "Verification PASSED");
+            complete_file(worker_id, ctx, &task.path).await;
+            ctx.file_finished(&task.path).await;
+        }
+
+        if !failing.is_empty() {
+            let culprits: Vec<_> = failing
+                .iter()
+                .map(|(t, _)| t.path.display().to_string())
+                .collect();
+            warn!(
+                worker = worker_id,
+                files = ?culprits,
+                "Bisection isolated failing files, running fixups"
             );
-            let result = match run_command(&cmd).await {
-                Ok(r) => r,
-                Err(e) => {
-                    error!(
-                        worker = worker_id,
-                        file = %file_display,
-                        error = %e,
-                        "Verification command failed to execute"
-                    );
-                    let mut state = ctx.state.lock().await;
-                    state.update_status(&task.path, FileStatus::Failed);
-                    state.set_error(&task.path, e.to_string());
-                    if let Err(e) = state.save(&ctx.state_path) {
-                        error!(error = %e, "Failed to save state");
-                    }
-                    break;
-                }
-            };
+        }
+        follow_up.extend(failing);
+        if follow_up.is_empty() {
+            continue;
+        }
+
+        // Culprits (and files awaiting review) run in parallel, each in its own verify slot
+        // once not paused. This worker's slot is handed back so they can't wait on it.
+        *permit = None;
+        let mut fixups = JoinSet::new();
+        for (task, failure) in follow_up {
+            let ctx = ctx.clone();
+            let verification_cmd = verification_cmd.to_string();
+            fixups.spawn(async move {
+                ctx.control.wait_if_paused().await;
+                let mut permit = Some(ctx.control.verify_gate.acquire().await);
+                verify_file(
+                    worker_id,
+                    &ctx,
+                    &verification_cmd,
+                    &task,
+                    &mut permit,
+                    Some(failure),
+                )
+                .await;
+                ctx.file_finished(&task.path).await;
+            });
+        }
+        while fixups.join_next().await.is_some() {}
+        *permit = Some(ctx.control.verify_gate.acquire().await);
+    }
+}
+
+/// Verify a single file, running fixups until it passes or runs out of retries.
+/// `known_failure` is a verification already run for this file (from bisection), used
+/// in place of the first run. Leaves the file completed or failed, or awaiting
+/// verification when the budget ran out.
+async fn verify_file(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    verification_cmd: &str,
+    task: &FileTask,
+    permit: &mut Option<GatePermit>,
+    mut known_result: Option<ProcessOutput>,
+) {
+    let file_display = task.path.display().to_string();
+    let group = ctx.verify_groups.group_for(&task.path, &task.original_data);
+    let mut attempts = {
+        let state = ctx.state.lock().await;
+        state.get_attempts(&task.path)
+    };
+    let dir = match ctx.command_dir(&task.original_data) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!(worker = worker_id, file = %file_display, error = %e, "Cannot verify file");
+            let mut state = ctx.state.lock().await;
+            state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+            state.set_error(&task.path, e.to_string());
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+            return;
+        }
+    };
+    let cmd = ctx.expand_command(verification_cmd, std::slice::from_ref(&task.path), &dir);
+
+    loop {
+        let result = match known_result.take() {
+            Some(result) => result,
+            None => {
+                let group_permit =
+                    acquire_group(worker_id, ctx, group.as_deref(), permit, &file_display).await;
 
-            if result.exit_code == 0 {
                 info!(
                     worker = worker_id,
                     file = %file_display,
-                    "Verification PASSED"
+                    attempt = attempts + 1,
+                    "Starting verification"
                 );
 
-                // Auto-commit if enabled
-                if ctx.config.git.auto_commit {
-                    let description = ctx.config.git.commit_message_template.as_deref();
-                    match commit_file_changes(&ctx.working_dir, &task.path, description).await {
-                        Ok(Some(hash)) => {
-                            info!(
-                                worker = worker_id,
-                                file = %file_display,
-                                commit = %hash,
-                                "Auto-committed changes"
-                            );
-                        }
-                        Ok(None) => {
-                            debug!(
-                                worker = worker_id,
-                                file = %file_display,
-                                "No changes to commit"
-                            );
-                        }
-                        Err(e) => {
-                            warn!(
-                                worker = worker_id,
-                                file = %file_display,
-                                error = %e,
-                                "Failed to auto-commit (continuing anyway)"
-                            );
-                        }
+                // Update status
+                {
+                    let mut state = ctx.state.lock().await;
+                    state.update_status(&task.path, FileStatus::VerifyInProgress, Some(worker_id));
+                    if let Err(e) = state.save(&ctx.state_path) {
+                        error!(error = %e, "Failed to save state");
                     }
                 }
 
-                let mut state = ctx.state.lock().await;
-                state.update_status(&task.path, FileStatus::Completed);
-                if let Err(e) = state.save(&ctx.state_path) {
-                    error!(error = %e, "Failed to save state");
-                }
-                break;
+                // Run verification command
+                let result = match run_verification(
+                    worker_id,
+                    ctx,
+                    &cmd,
+                    std::slice::from_ref(&task.path),
+                    &dir,
+                    &file_display,
+                )
+                .await
+                {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error!(
+                            worker = worker_id,
+                            file = %file_display,
+                            error = %e,
+                            "Verification command failed to execute"
+                        );
+                        let mut state = ctx.state.lock().await;
+                        state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+                        state.set_error(&task.path, e.to_string());
+                        if let Err(e) = state.save(&ctx.state_path) {
+                            error!(error = %e, "Failed to save state");
+                        }
+                        break;
+                    }
+                };
+                drop(group_permit);
+                result
             }
+        };
 
-            // Verification failed
-            attempts += 1;
-            {
-                let mut state = ctx.state.lock().await;
-                state.increment_attempts(&task.path);
+        // A rejected review goes through the same fixup loop as a failed verification
+        let mut rejected = false;
+        let result = if result.exit_code == 0 {
+            info!(
+                worker = worker_id,
+                file = %file_display,
+                "Verification PASSED"
+            );
+            let Some(ref rubric) = ctx.config.review_rubric else {
+                complete_file(worker_id, ctx, &task.path).await;
+                break;
+            };
+            match review_file(worker_id, ctx, task, rubric).await {
+                ReviewOutcome::Approved => {
+                    complete_file(worker_id, ctx, &task.path).await;
+                    break;
+                }
+                ReviewOutcome::Rejected(output) => {
+                    rejected = true;
+                    output
+                }
+                ReviewOutcome::Stopped => break,
             }
+        } else {
+            result
+        };
 
-            let error_output = if result.stderr.is_empty() {
-                &result.stdout
-            } else {
-                &result.stderr
-            };
+        // Verification failed
+        attempts += 1;
+        {
+            let mut state = ctx.state.lock().await;
+            state.increment_attempts(&task.path);
+        }
 
-            let failure_msg = format!(
+        let error_output = if result.stderr.is_empty() {
+            &result.stdout
+        } else {
+            &result.stderr
+        };
+
+        let failure_msg = if rejected {
+            format!(
+                "REVIEW REJECTED (attempt {}/{})\n\n{}",
+                attempts, ctx.config.max_retries, error_output
+            )
+        } else {
+            format!(
                 "VERIFICATION FAILED (attempt {}/{})\nCommand: {}\nExit code: {}\n\nOutput:\n{}",
                 attempts, ctx.config.max_retries, cmd, result.exit_code, error_output
+            )
+        };
+        append_to_failure_log(&ctx.artifacts_dir, &task.path, &failure_msg);
+
+        if attempts >= ctx.config.max_retries {
+            warn!(
+                worker = worker_id,
+                file = %file_display,
+                attempts = attempts,
+                "Verification FAILED after max retries"
             );
-            append_to_failure_log(&tasks_dir, &task.path, &failure_msg);
 
-            if attempts >= ctx.config.max_retries {
-                warn!(
-                    worker = worker_id,
-                    file = %file_display,
-                    attempts = attempts,
-                    "Verification FAILED after max retries"
-                );
+            append_to_failure_log(
+                &ctx.artifacts_dir,
+                &task.path,
+                "FINAL STATUS: FAILED after max retries",
+            );
 
-                append_to_failure_log(
-                    &tasks_dir,
-                    &task.path,
-                    "FINAL STATUS: FAILED after max retries",
-                );
+            let mut state = ctx.state.lock().await;
+            state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+            state.set_error(&task.path, error_output.clone());
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+            break;
+        }
 
-                let mut state = ctx.state.lock().await;
-                state.update_status(&task.path, FileStatus::Failed);
-                state.set_error(&task.path, error_output.clone());
-                if let Err(e) = state.save(&ctx.state_path) {
-                    error!(error = %e, "Failed to save state");
-                }
-                break;
+        // No new fixups once the task budget is gone; the file is re-verified on --resume
+        if ctx.budget.is_exhausted() {
+            warn!(
+                worker = worker_id,
+                file = %file_display,
+                "Budget exhausted, leaving file awaiting verification"
+            );
+            let mut state = ctx.state.lock().await;
+            state.update_status(&task.path, FileStatus::AwaitingVerification, Some(worker_id));
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
             }
+            break;
+        }
 
-            // Run fixup
+        // A file that has used up its own budget fails instead of running another fixup
+        let file_usage = {
+            let state = ctx.state.lock().await;
+            state.files.get(&task.path).map(|f| f.usage.clone()).unwrap_or_default()
+        };
+        if let Some(reason) = ctx.budget.check_file(&file_usage) {
             warn!(
                 worker = worker_id,
                 file = %file_display,
-                attempt = attempts,
-                max = ctx.config.max_retries,
-                "Verification failed, running fixup"
+                reason = %reason,
+                "File budget exhausted, marking failed"
+            );
+            append_to_failure_log(
+                &ctx.artifacts_dir,
+                &task.path,
+                &format!("FINAL STATUS: FAILED, file budget exhausted ({})", reason),
             );
 
-            {
-                let mut state = ctx.state.lock().await;
-                state.update_status(&task.path, FileStatus::FixupInProgress);
-                if let Err(e) = state.save(&ctx.state_path) {
-                    error!(error = %e, "Failed to save state");
+            let mut state = ctx.state.lock().await;
+            state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+            state.set_error(&task.path, format!("File budget exhausted: {}", reason));
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+            break;
+        }
+
+        // After enough fixups in a row, start over from the original content instead
+        let fresh_start = match ctx.config.retry.fresh_start_after {
+            Some(after) if after > 0 && trailing_fixups(ctx, &task.path).await >= after => {
+                match restore_original_scope(
+                    &ctx.artifacts_dir,
+                    &task.path,
+                    &ctx.config.allowlist_pattern,
+                    &ctx.working_dir,
+                ) {
+                    Ok(Some(restored)) => {
+                        append_to_failure_log(
+                            &ctx.artifacts_dir,
+                            &task.path,
+                            &format!(
+                                "FRESH START: reverted {} file(s) to their original content",
+                                restored.len()
+                            ),
+                        );
+                        true
+                    }
+                    Ok(None) => {
+                        warn!(
+                            worker = worker_id,
+                            file = %file_display,
+                            "No original scope saved, running fixup instead of fresh start"
+                        );
+                        false
+                    }
+                    Err(e) => {
+                        warn!(
+                            worker = worker_id,
+                            file = %file_display,
+                            error = %e,
+                            "Failed to revert scope, running fixup instead of fresh start"
+                        );
+                        false
+                    }
                 }
             }
+            _ => false,
+        };
 
+        let (kind, status, prompt) = if fresh_start {
+            warn!(
+                worker = worker_id,
+                file = %file_display,
+                attempt = attempts,
+                max = ctx.config.max_retries,
+                "Verification failed, reverted file and rerunning original prompt"
+            );
+            let prompt = build_prompt(
+                &ctx.config.prompt,
+                &task.path,
+                &task.original_data,
+                &ctx.config.allowlist_pattern,
+            );
+            (AttemptKind::Prompt, FileStatus::PromptInProgress, prompt)
+        } else {
+            warn!(
+                worker = worker_id,
+                file = %file_display,
+                attempt = attempts,
+                max = ctx.config.max_retries,
+                rejected,
+                "Verification failed, running fixup"
+            );
             let fixup_prompt_base = ctx
                 .config
                 .fixup_prompt
                 .as_deref()
                 .unwrap_or("Fix the issues with the file");
-
-            let fixup_prompt = build_fixup_prompt(
+            let prompt = build_fixup_prompt(
                 fixup_prompt_base,
                 &task.path,
                 error_output,
                 &ctx.config.allowlist_pattern,
             );
+            (AttemptKind::Fixup, FileStatus::FixupInProgress, prompt)
+        };
 
-            append_to_failure_log(
-                &tasks_dir,
-                &task.path,
-                &format!("FIXUP PROMPT SENT:\n{}", fixup_prompt),
-            );
-
-            // Wait if API usage limit exceeded before calling Claude for fixup
-            if ctx.usage.is_paused() {
-                info!(worker = worker_id, "Waiting for API usage quota to reset before fixup...");
-                ctx.usage.wait_if_paused().await;
-                info!(worker = worker_id, "Resuming fixup after usage quota reset");
+        {
+            let mut state = ctx.state.lock().await;
+            state.update_status(&task.path, status, Some(worker_id));
+            if let Err(e) = state.save(&ctx.state_path) {
+                error!(error = %e, "Failed to save state");
             }
+        }
 
-            match run_claude(&fixup_prompt, &ctx.working_dir).await {
-                Ok(output) => {
-                    let response_log = format!(
-                        "CLAUDE FIXUP RESPONSE:\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
-                        output.stdout, output.stderr
-                    );
-                    append_to_failure_log(&tasks_dir, &task.path, &response_log);
+        append_to_failure_log(
+            &ctx.artifacts_dir,
+            &task.path,
+            &format!("{} PROMPT SENT:\n{}", kind_label(kind), prompt),
+        );
 
-                    let parsed = parse_result(&output.stdout);
-                    {
-                        let mut state = ctx.state.lock().await;
-                        state.set_result(&task.path, parsed);
-                        if let Err(e) = state.save(&ctx.state_path) {
-                            error!(error = %e, "Failed to save state");
-                        }
-                    }
-                    info!(
-                        worker = worker_id,
-                        file = %file_display,
-                        "Fixup complete, re-verifying"
-                    );
+        // Back off before retrying
+        let delay = ctx.config.retry.backoff(attempts);
+        if !delay.is_zero() {
+            info!(
+                worker = worker_id,
+                file = %file_display,
+                delay_secs = delay.as_secs(),
+                "Backing off before retry"
+            );
+            let aborted = tokio::select! {
+                _ = tokio::time::sleep(delay) => false,
+                _ = ctx.budget.aborted() => true,
+            };
+            if aborted {
+                let mut state = ctx.state.lock().await;
+                state.update_status(&task.path, FileStatus::AwaitingVerification, Some(worker_id));
+                if let Err(e) = state.save(&ctx.state_path) {
+                    error!(error = %e, "Failed to save state");
                 }
-                Err(e) => {
-                    error!(
-                        worker = worker_id,
-                        file = %file_display,
-                        error = %e,
-                        "Fixup failed"
-                    );
+                break;
+            }
+        }
 
-                    append_to_failure_log(
-                        &tasks_dir,
-                        &task.path,
-                        &format!("FIXUP COMMAND FAILED: {}", e),
-                    );
+        // Wait if API usage limit exceeded before calling Claude for fixup
+        if ctx.usage.is_paused() {
+            info!(worker = worker_id, "Waiting for API usage quota to reset before fixup...");
+            ctx.usage.wait_if_paused().await;
+            info!(worker = worker_id, "Resuming fixup after usage quota reset");
+        }
+
+        let scope_before =
+            capture_scope(&task.path, &ctx.config.allowlist_pattern, &ctx.working_dir);
 
+        match ctx.run_agent(&prompt, std::slice::from_ref(&task.path)).await {
+            None => {
+                warn!(
+                    worker = worker_id,
+                    file = %file_display,
+                    "Fixup aborted, budget exhausted"
+                );
+                let mut state = ctx.state.lock().await;
+                state.update_status(&task.path, FileStatus::AwaitingVerification, Some(worker_id));
+                if let Err(e) = state.save(&ctx.state_path) {
+                    error!(error = %e, "Failed to save state");
+                }
+                break;
+            }
+            Some(Ok(output)) => {
+                record_attempt(ctx, &task.path, kind, &scope_before, &prompt, &output).await;
+
+                let response_log = format!(
+                    "CLAUDE {} RESPONSE:\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
+                    kind_label(kind),
+                    output.stdout,
+                    output.stderr
+                );
+                append_to_failure_log(&ctx.artifacts_dir, &task.path, &response_log);
+
+                let parsed = parse_result(&output.stdout);
+                {
                     let mut state = ctx.state.lock().await;
-                    state.update_status(&task.path, FileStatus::Failed);
-                    state.set_error(&task.path, e.to_string());
+                    state.set_result(&task.path, parsed);
                     if let Err(e) = state.save(&ctx.state_path) {
                         error!(error = %e, "Failed to save state");
                     }
-                    break;
                 }
+                info!(
+                    worker = worker_id,
+                    file = %file_display,
+                    "Fixup complete, re-verifying"
+                );
+            }
+            Some(Err(e)) => {
+                error!(
+                    worker = worker_id,
+                    file = %file_display,
+                    error = %e,
+                    "Fixup failed"
+                );
+
+                append_to_failure_log(
+                    &ctx.artifacts_dir,
+                    &task.path,
+                    &format!("{} COMMAND FAILED: {}", kind_label(kind), e),
+                );
+
+                let mut state = ctx.state.lock().await;
+                state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+                state.set_error(&task.path, e.to_string());
+                if let Err(e) = state.save(&ctx.state_path) {
+                    error!(error = %e, "Failed to save state");
+                }
+                break;
             }
         }
     }
+}
 
-    info!(worker = worker_id, "Verify worker shutting down");
+/// Fixups run since the file's last prompt (original or fresh start)
+async fn trailing_fixups(ctx: &WorkerContext, path: &Path) -> u32 {
+    let state = ctx.state.lock().await;
+    state
+        .files
+        .get(path)
+        .map(|f| {
+            f.attempt_log
+                .iter()
+                .rev()
+                .filter(|a| a.kind != AttemptKind::Review)
+                .take_while(|a| a.kind == AttemptKind::Fixup)
+                .count() as u32
+        })
+        .unwrap_or(0)
+}
+
+/// Label for an agent run in the failure log
+fn kind_label(kind: AttemptKind) -> &'static str {
+    match kind {
+        AttemptKind::Prompt => "FRESH START",
+        AttemptKind::Fixup => "FIXUP",
+        AttemptKind::Review => "REVIEW",
+    }
+}
+
+/// How a file's review ended
+enum ReviewOutcome {
+    Approved,
+    /// Rejected, with the reasons as the output to fix up
+    Rejected(ProcessOutput),
+    /// The reviewer couldn't run; the file's status is already updated
+    Stopped,
+}
+
+/// Send the file's changes since its first prompt to a reviewer with the rubric
+async fn review_file(
+    worker_id: usize,
+    ctx: &WorkerContext,
+    task: &FileTask,
+    rubric: &str,
+) -> ReviewOutcome {
+    let file_display = task.path.display().to_string();
+
+    // No reviews once the task budget is gone; the file is re-verified on --resume
+    if ctx.budget.is_exhausted() {
+        warn!(
+            worker = worker_id,
+            file = %file_display,
+            "Budget exhausted, leaving file awaiting verification"
+        );
+        let mut state = ctx.state.lock().await;
+        state.update_status(&task.path, FileStatus::AwaitingVerification, Some(worker_id));
+        if let Err(e) = state.save(&ctx.state_path) {
+            error!(error = %e, "Failed to save state");
+        }
+        return ReviewOutcome::Stopped;
+    }
+
+    // Without a saved snapshot the whole scope is shown as added
+    let original = load_original_scope(&ctx.artifacts_dir, &task.path)
+        .unwrap_or_else(|e| {
+            warn!(file = %file_display, error = %e, "Failed to load original scope for review");
+            None
+        })
+        .unwrap_or_default();
+    let current = recapture_scope(
+        &original,
+        &task.path,
+        &ctx.config.allowlist_pattern,
+        &ctx.working_dir,
+    );
+    let (diff, _) = diff_scope(&original, &current);
+    let prompt = build_review_prompt(rubric, &task.path, &diff);
+
+    // A reply without a verdict is retried like an agent error, then fails the file.
+    // None if the budget ran out.
+    let mut retries = 0;
+    let verdict = loop {
+        info!(worker = worker_id, file = %file_display, "Starting review");
+        let scope_before = capture_scope(&task.path, &ctx.config.allowlist_pattern, &ctx.working_dir);
+        let error = match ctx.run_agent(&prompt, std::slice::from_ref(&task.path)).await {
+            None => break None,
+            Some(Err(e)) => format!("REVIEW COMMAND FAILED: {}", e),
+            Some(Ok(output)) => {
+                record_attempt(ctx, &task.path, AttemptKind::Review, &scope_before, &prompt, &output).await;
+                if let Some(verdict) = parse_verdict(&output.stdout) {
+                    break Some(verdict);
+                }
+                if retries < ctx.config.retry.agent_errors {
+                    retries += 1;
+                    warn!(
+                        worker = worker_id,
+                        file = %file_display,
+                        retry = retries,
+                        "Reviewer returned no VERDICT line, retrying"
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(ctx.config.retry.backoff(retries)) => continue,
+                        _ = ctx.budget.aborted() => break None,
+                    }
+                }
+                "REVIEW FAILED: the reviewer did not return a VERDICT line".to_string()
+            }
+        };
+
+        error!(worker = worker_id, file = %file_display, error = %error, "Review failed");
+        append_to_failure_log(&ctx.artifacts_dir, &task.path, &error);
+        let mut state = ctx.state.lock().await;
+        state.update_status(&task.path, FileStatus::Failed, Some(worker_id));
+        state.set_error(&task.path, error);
+        if let Err(e) = state.save(&ctx.state_path) {
+            error!(error = %e, "Failed to save state");
+        }
+        return ReviewOutcome::Stopped;
+    };
+
+    let Some(verdict) = verdict else {
+        warn!(worker = worker_id, file = %file_display, "Review aborted, budget exhausted");
+        let mut state = ctx.state.lock().await;
+        state.update_status(&task.path, FileStatus::AwaitingVerification, Some(worker_id));
+        if let Err(e) = state.save(&ctx.state_path) {
+            error!(error = %e, "Failed to save state");
+        }
+        return ReviewOutcome::Stopped;
+    };
+
+    if verdict.approved {
+        info!(worker = worker_id, file = %file_display, "Review APPROVED");
+        return ReviewOutcome::Approved;
+    }
+
+    warn!(
+        worker = worker_id,
+        file = %file_display,
+        reasons = ?verdict.reasons,
+        "Review REJECTED"
+    );
+    let reasons = verdict
+        .reasons
+        .iter()
+        .map(|r| format!("- {}", r))
+        .collect::<Vec<_>>()
+        .join("\n");
+    ReviewOutcome::Rejected(ProcessOutput {
+        stdout: String::new(),
+        stderr: format!("A reviewer rejected the changes:\n{}", reasons),
+        exit_code: 1,
+        agent_usage: None,
+        is_error: false,
+    })
 }
@@ -1,9 +1,11 @@
-use crate::types::{ParsedResult, ProcessOutput};
+use crate::types::{ParsedResult, ProcessOutput, ReviewVerdict};
 use anyhow::{Context, Result};
 use glob::glob;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::process::Stdio;
-use tokio::process::Command;
+use std::process::{Output, Stdio};
+use std::time::Duration;
+use tokio::process::{Child, Command};
 
 /// Extract the file stem, stripping both the extension and common test suffixes (.test, .spec)
 /// e.g., "parser.test.ts" -> "parser", "component.spec.tsx" -> "component"
@@ -20,15 +22,8 @@ pub fn extract_file_stem(file_path: &Path) -> String {
         .unwrap_or(stem)
 }
 
-/// Expand pattern placeholders with file path components
-/// Supports: {file}, {file_stem}, {file_dir}, {all_files}, {test_files}, {created_files}
+/// Expand the path placeholders in a pattern: {file}, {file_stem}, {file_dir}
 pub fn expand_pattern(pattern: &str, file_path: &Path) -> String {
-    expand_pattern_with_allowlist(pattern, file_path, "{file_stem}*")
-}
-
-/// Expand pattern placeholders with file path components and a custom allowlist
-/// Supports: {file}, {file_stem}, {file_dir}, {all_files}, {test_files}, {created_files}
-pub fn expand_pattern_with_allowlist(pattern: &str, file_path: &Path, allowlist: &str) -> String {
     let file_str = file_path.to_string_lossy();
 
     let file_stem = extract_file_stem(file_path);
@@ -38,39 +33,87 @@ pub fn expand_pattern_with_allowlist(pattern: &str, file_path: &Path, allowlist:
         .map(|p| p.to_string_lossy().to_string())
         .unwrap_or_default();
 
+    pattern
+        .replace("{file}", &file_str)
+        .replace("{file_stem}", &file_stem)
+        .replace("{file_dir}", &file_dir)
+}
+
+/// Expand pattern placeholders with file path components and a custom allowlist.
+/// Supports: {file}, {file_stem}, {file_dir}, {all_files}, {test_files}, {created_files}
+/// File lists are found under `working_dir` and are relative to it, like `file_path`.
+pub fn expand_pattern_with_allowlist(
+    pattern: &str,
+    file_path: &Path,
+    allowlist: &str,
+    working_dir: &Path,
+) -> String {
     // Only compute these if needed (they involve filesystem operations)
     let all_files = if pattern.contains("{all_files}") {
-        find_all_files(file_path, allowlist).join(" ")
+        find_all_files(file_path, allowlist, working_dir).join(" ")
     } else {
         String::new()
     };
 
     let test_files = if pattern.contains("{test_files}") {
-        find_test_files(file_path, allowlist).join(" ")
+        find_test_files(file_path, allowlist, working_dir).join(" ")
     } else {
         String::new()
     };
 
     let created_files = if pattern.contains("{created_files}") {
-        find_created_files(file_path, allowlist).join(" ")
+        find_created_files(file_path, allowlist, working_dir).join(" ")
     } else {
         String::new()
     };
 
-    pattern
-        .replace("{file}", &file_str)
-        .replace("{file_stem}", &file_stem)
-        .replace("{file_dir}", &file_dir)
+    expand_pattern(pattern, file_path)
         .replace("{all_files}", &all_files)
         .replace("{test_files}", &test_files)
         .replace("{created_files}", &created_files)
 }
 
+/// Expand pattern placeholders for several files at once (batched verification).
+/// Each placeholder becomes the space-separated, de-duplicated values for every file.
+pub fn expand_pattern_for_files(
+    pattern: &str,
+    file_paths: &[PathBuf],
+    allowlist: &str,
+    working_dir: &Path,
+) -> String {
+    const PLACEHOLDERS: [&str; 6] = [
+        "{file}",
+        "{file_stem}",
+        "{file_dir}",
+        "{all_files}",
+        "{test_files}",
+        "{created_files}",
+    ];
+
+    let mut expanded = pattern.to_string();
+    for placeholder in PLACEHOLDERS {
+        if !pattern.contains(placeholder) {
+            continue;
+        }
+        let mut values: Vec<String> = Vec::new();
+        for path in file_paths {
+            let value = expand_pattern_with_allowlist(placeholder, path, allowlist, working_dir);
+            for part in value.split_whitespace() {
+                if !values.iter().any(|v| v == part) {
+                    values.push(part.to_string());
+                }
+            }
+        }
+        expanded = expanded.replace(placeholder, &values.join(" "));
+    }
+    expanded
+}
+
 /// Find all files matching the allowlist pattern (includes the source file)
 /// Returns: {file} and any files that match the allowlist glob
-pub fn find_all_files(file_path: &Path, allowlist_pattern: &str) -> Vec<String> {
+pub fn find_all_files(file_path: &Path, allowlist_pattern: &str, working_dir: &Path) -> Vec<String> {
     let glob_pattern = expand_allowlist_to_glob(file_path, allowlist_pattern);
-    let mut files = collect_glob_matches(&glob_pattern);
+    let mut files = collect_glob_matches(&glob_pattern, working_dir);
 
     // Ensure the source file is included
     let file_str = file_path.to_string_lossy().to_string();
@@ -83,8 +126,8 @@ pub fn find_all_files(file_path: &Path, allowlist_pattern: &str) -> Vec<String>
 
 /// Find test files that likely correspond to the source file
 /// Looks for files with common test patterns: *.test.*, *.spec.*, *_test.*, *_spec.*
-pub fn find_test_files(file_path: &Path, allowlist_pattern: &str) -> Vec<String> {
-    let all_files = find_all_files(file_path, allowlist_pattern);
+pub fn find_test_files(file_path: &Path, allowlist_pattern: &str, working_dir: &Path) -> Vec<String> {
+    let all_files = find_all_files(file_path, allowlist_pattern, working_dir);
     let file_str = file_path.to_string_lossy().to_string();
 
     all_files
@@ -109,9 +152,9 @@ pub fn find_test_files(file_path: &Path, allowlist_pattern: &str) -> Vec<String>
 
 /// Find files that match the allowlist glob but are NOT the source file itself
 /// These are likely files created by Claude during processing
-pub fn find_created_files(file_path: &Path, allowlist_pattern: &str) -> Vec<String> {
+pub fn find_created_files(file_path: &Path, allowlist_pattern: &str, working_dir: &Path) -> Vec<String> {
     let glob_pattern = expand_allowlist_to_glob(file_path, allowlist_pattern);
-    let files = collect_glob_matches(&glob_pattern);
+    let files = collect_glob_matches(&glob_pattern, working_dir);
     let file_str = file_path.to_string_lossy().to_string();
 
     files.into_iter().filter(|f| f != &file_str).collect()
@@ -146,18 +189,57 @@ fn expand_allowlist_to_glob(file_path: &Path, allowlist_pattern: &str) -> String
     }
 }
 
-/// Collect all files matching a glob pattern
-fn collect_glob_matches(pattern: &str) -> Vec<String> {
-    match glob(pattern) {
+/// Collect all files matching a glob pattern evaluated under `working_dir`, as paths
+/// relative to it
+fn collect_glob_matches(pattern: &str, working_dir: &Path) -> Vec<String> {
+    let pattern = format!(
+        "{}/{}",
+        glob::Pattern::escape(&working_dir.to_string_lossy()),
+        pattern
+    );
+    match glob(&pattern) {
         Ok(paths) => paths
             .filter_map(|entry| entry.ok())
             .filter(|p| p.is_file())
-            .map(|p| p.to_string_lossy().to_string())
+            .filter_map(|p| {
+                p.strip_prefix(working_dir)
+                    .ok()
+                    .map(|p| p.to_string_lossy().to_string())
+            })
             .collect(),
         Err(_) => Vec::new(),
     }
 }
 
+/// Files an agent run on `file_path` is expected to touch: the file itself, anything
+/// matching the allowlist glob and discovered related test/snapshot files.
+/// The glob is evaluated under `working_dir`; returned paths are relative to it.
+pub fn find_scope_files(file_path: &Path, allowlist_pattern: &str, working_dir: &Path) -> Vec<PathBuf> {
+    let glob_pattern = expand_allowlist_to_glob(file_path, allowlist_pattern);
+    let mut files: Vec<PathBuf> = collect_glob_matches(&glob_pattern, working_dir)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+
+    for path in std::iter::once(file_path.to_path_buf())
+        .chain(find_related_files(file_path, working_dir))
+    {
+        if !files.contains(&path) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// `path` (relative to `root`) as seen from `dir`: relative to it if inside, else absolute
+pub fn path_from_dir(path: &Path, root: &Path, dir: &Path) -> PathBuf {
+    let full = root.join(path);
+    full.strip_prefix(dir)
+        .map(Path::to_path_buf)
+        .unwrap_or(full)
+}
+
 /// Discover related files for a source file using multiple conventions.
 ///
 /// Searches for test files and snapshots in:
@@ -261,22 +343,75 @@ pub fn find_related_files(source_path: &Path, working_dir: &Path) -> Vec<PathBuf
     found
 }
 
-/// Run a shell command and capture output
-pub async fn run_command(command: &str) -> Result<ProcessOutput> {
-    let output = Command::new("sh")
+/// Start a shell command in `working_dir` with its output captured, in its own process
+/// group. The shell is killed if the returned child is dropped; `wait_command` kills the
+/// whole group.
+pub fn spawn_command(command: &str, working_dir: &Path) -> Result<Child> {
+    Command::new("sh")
         .arg("-c")
         .arg(command)
+        .current_dir(working_dir)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .output()
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to execute command")
+}
+
+/// Kills a command's process group when dropped, unless the command finished, so a
+/// timed out or cancelled command doesn't leave the processes it started running
+struct ProcessGroupGuard(Option<u32>);
+
+impl ProcessGroupGuard {
+    fn finished(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            // SAFETY: kill takes no pointers; a negative PID signals the whole group
+            unsafe {
+                libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Wait for a spawned process and collect its output.
+/// Returns None if it is still running after `timeout` (it is killed).
+pub async fn wait_for_output(child: Child, timeout: Option<Duration>) -> std::io::Result<Option<Output>> {
+    let output = child.wait_with_output();
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, output).await {
+            Ok(output) => output.map(Some),
+            Err(_) => Ok(None),
+        },
+        None => output.await.map(Some),
+    }
+}
+
+/// Wait for a command started with `spawn_command` and capture its output.
+/// Returns None if the command is still running after `timeout` (its process group is killed).
+pub async fn wait_command(child: Child, timeout: Option<Duration>) -> Result<Option<ProcessOutput>> {
+    let group = ProcessGroupGuard(child.id());
+    let Some(output) = wait_for_output(child, timeout)
         .await
-        .context("Failed to execute command")?;
+        .context("Failed to execute command")?
+    else {
+        return Ok(None);
+    };
+    group.finished();
 
-    Ok(ProcessOutput {
+    Ok(Some(ProcessOutput {
         stdout: String::from_utf8_lossy(&output.stdout).to_string(),
         stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         exit_code: output.status.code().unwrap_or(-1),
-    })
+        agent_usage: None,
+        is_error: false,
+    }))
 }
 
 /// Parse result from Claude's output
@@ -316,6 +451,49 @@ pub fn parse_result(stdout: &str) -> ParsedResult {
     }
 }
 
+/// Parse a reviewer's verdict from the last `VERDICT:` line of its output
+pub fn parse_verdict(stdout: &str) -> Option<ReviewVerdict> {
+    stdout
+        .lines()
+        .rev()
+        .find_map(|line| line.trim().strip_prefix("VERDICT:"))
+        .and_then(|json_str| serde_json::from_str(json_str.trim()).ok())
+}
+
+/// Parse a batch result: a `RESULT:` JSON object keyed by file path.
+/// Files missing from the map get a null result. If the result isn't such a map,
+/// every file gets the whole result.
+pub fn parse_batch_result(stdout: &str, paths: &[PathBuf]) -> HashMap<PathBuf, ParsedResult> {
+    let parsed = parse_result(stdout);
+
+    let keyed = parsed.value.as_object().filter(|map| {
+        !parsed.is_raw
+            && paths
+                .iter()
+                .any(|p| map.contains_key(p.to_string_lossy().as_ref()))
+    });
+
+    paths
+        .iter()
+        .map(|path| {
+            let result = match keyed {
+                Some(map) => ParsedResult {
+                    value: map
+                        .get(path.to_string_lossy().as_ref())
+                        .cloned()
+                        .unwrap_or(serde_json::Value::Null),
+                    is_raw: false,
+                },
+                None => ParsedResult {
+                    value: parsed.value.clone(),
+                    is_raw: parsed.is_raw,
+                },
+            };
+            (path.clone(), result)
+        })
+        .collect()
+}
+
 /// Check if a file path matches the allowed pattern (glob-style)
 pub fn matches_allowlist(path: &Path, pattern: &str) -> bool {
     let path_str = path.to_string_lossy();
@@ -415,6 +593,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_expand_pattern_for_files() {
+        let paths = vec![
+            PathBuf::from("src/a/one.ts"),
+            PathBuf::from("src/a/two.ts"),
+            PathBuf::from("src/b/three.ts"),
+        ];
+        assert_eq!(
+            expand_pattern_for_files(
+                "jest {file} --dirs {file_dir}",
+                &paths,
+                "{file_stem}*",
+                Path::new(".")
+            ),
+            "jest src/a/one.ts src/a/two.ts src/b/three.ts --dirs src/a src/b"
+        );
+        assert_eq!(
+            expand_pattern_for_files("tsc --noEmit", &paths, "{file_stem}*", Path::new(".")),
+            "tsc --noEmit"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commands_run_in_and_expand_under_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let package = root.join("packages/api");
+        std::fs::create_dir_all(package.join("src")).unwrap();
+        std::fs::write(package.join("src/client.ts"), "").unwrap();
+        std::fs::write(package.join("src/client.test.ts"), "").unwrap();
+
+        let file = Path::new("packages/api/src/client.ts");
+        assert_eq!(
+            expand_pattern_with_allowlist("vitest {test_files}", file, "{file_stem}*", &root),
+            "vitest packages/api/src/client.test.ts"
+        );
+
+        // From the package directory, paths are relative to it
+        let in_package = path_from_dir(file, &root, &package);
+        assert_eq!(in_package, Path::new("src/client.ts"));
+        assert_eq!(
+            expand_pattern_with_allowlist(
+                "vitest {test_files}",
+                &in_package,
+                "{file_stem}*",
+                &package
+            ),
+            "vitest src/client.test.ts"
+        );
+        assert_eq!(path_from_dir(Path::new("lib/x.ts"), &root, &package), root.join("lib/x.ts"));
+
+        let child = spawn_command("pwd", &package).unwrap();
+        let output = wait_command(child, None).await.unwrap().unwrap();
+        assert_eq!(output.stdout.trim(), package.to_string_lossy());
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_command_kills_its_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let child = spawn_command("sleep 30 & echo $! > pid; wait", dir.path()).unwrap();
+        let output = wait_command(child, Some(Duration::from_millis(500))).await.unwrap();
+        assert!(output.is_none());
+
+        // Gone, or killed and waiting to be reaped
+        let pid = std::fs::read_to_string(dir.path().join("pid")).unwrap();
+        let running = || {
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+            let state = stat.rsplit(") ").next().and_then(|rest| rest.chars().next());
+            !matches!(state, None | Some('Z'))
+        };
+        for _ in 0..20 {
+            if !running() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!running(), "background sleep still running");
+    }
+
     #[test]
     fn test_expand_pattern() {
         let path = PathBuf::from("src/reducer/teamsReducer.test.ts");
@@ -478,6 +735,27 @@ More output
         assert_eq!(result.value["coverage"], 78.5);
     }
 
+    #[test]
+    fn test_parse_verdict() {
+        let stdout = r#"
+The tests only assert that mocks return what they were given.
+VERDICT: {"approved": false, "reasons": ["test_parse is tautological"]}
+"#;
+        assert_eq!(
+            parse_verdict(stdout),
+            Some(ReviewVerdict {
+                approved: false,
+                reasons: vec!["test_parse is tautological".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_verdict("VERDICT: {\"approved\": true}").map(|v| v.approved),
+            Some(true)
+        );
+        assert_eq!(parse_verdict("VERDICT: looks good"), None);
+        assert_eq!(parse_verdict("RESULT: {}"), None);
+    }
+
     #[test]
     fn test_parse_result_string() {
         let stdout = r#"
@@ -500,6 +778,18 @@ RESULT: not valid json
         assert_eq!(result.value, "not valid json");
     }
 
+    #[test]
+    fn test_parse_batch_result() {
+        let paths = vec![PathBuf::from("src/a.ts"), PathBuf::from("src/b.ts")];
+        let stdout = r#"RESULT: {"src/a.ts": {"coverage": 90}, "src/c.ts": "ignored"}"#;
+        let results = parse_batch_result(stdout, &paths);
+        assert_eq!(results[Path::new("src/a.ts")].value["coverage"], 90);
+        assert!(results[Path::new("src/b.ts")].value.is_null());
+
+        let results = parse_batch_result(r#"RESULT: "done""#, &paths);
+        assert_eq!(results[Path::new("src/b.ts")].value, "done");
+    }
+
     #[test]
     fn test_parse_result_none() {
         let stdout = "Some output without result";
@@ -0,0 +1,274 @@
+use crate::attempts::{clear_inflight_scope, restore_inflight_scope};
+use crate::config::{Config, OrphanPolicy, PartialEditPolicy};
+use crate::lease::host_name;
+use crate::state::State;
+use crate::types::ProcessId;
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use sysinfo::{Pid, ProcessStatus, System};
+use tracing::{error, info, warn};
+
+/// Identify a running process on this host, or None if it has exited
+pub fn process_id(pid: u32) -> Option<ProcessId> {
+    let mut sys = System::new();
+    let sys_pid = Pid::from_u32(pid);
+    if !sys.refresh_process(sys_pid) {
+        return None;
+    }
+    sys.process(sys_pid).map(|p| ProcessId {
+        pid,
+        started: p.start_time(),
+    })
+}
+
+/// This runner process
+pub fn current_process() -> ProcessId {
+    static CURRENT: OnceLock<ProcessId> = OnceLock::new();
+    *CURRENT.get_or_init(|| {
+        let pid = std::process::id();
+        process_id(pid).unwrap_or(ProcessId { pid, started: 0 })
+    })
+}
+
+/// A recorded process is still running (same PID and start time)
+fn is_running(sys: &System, id: &ProcessId) -> bool {
+    let pid = Pid::from_u32(id.pid);
+    is_alive(sys, pid) && sys.process(pid).is_some_and(|p| p.start_time() == id.started)
+}
+
+/// Exited processes can linger as zombies until they are reaped
+fn is_alive(sys: &System, pid: Pid) -> bool {
+    sys.process(pid).is_some_and(|p| p.status() != ProcessStatus::Zombie)
+}
+
+/// A recorded process and everything it started, if it is still running
+fn process_tree(sys: &System, root: &ProcessId) -> Vec<Pid> {
+    if !is_running(sys, root) {
+        return Vec::new();
+    }
+    let mut tree = vec![Pid::from_u32(root.pid)];
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        tree.extend(
+            sys.processes()
+                .iter()
+                .filter(|(_, p)| p.parent() == Some(parent))
+                .map(|(pid, _)| *pid),
+        );
+        i += 1;
+    }
+    tree
+}
+
+/// Requeue files a crashed or killed run left in progress. Agent and verification
+/// processes it left running are killed or left alone per the orphan policy, and partial
+/// edits are kept or reverted per the partial edit policy. Returns files that must not be
+/// requeued in this run because their orphans are still running.
+pub fn recover_stale_files(
+    state: &mut State,
+    config: &Config,
+    artifacts_dir: &Path,
+    working_dir: &Path,
+    state_path: &Path,
+) -> Vec<PathBuf> {
+    let host = host_name();
+    let mut sys = System::new();
+    sys.refresh_processes();
+
+    // A file is stale unless a live runner holds it. A runner on this host that has exited
+    // can't renew its lease, so its files are stale before the lease expires.
+    let runner = state.runner_id.clone();
+    let mut stale: Vec<PathBuf> = state
+        .files
+        .iter()
+        .filter(|(_, f)| f.status.is_in_progress())
+        .filter(|(_, f)| match f.lease {
+            Some(ref lease) if lease.blocks(runner.as_deref()) => f
+                .process
+                .as_ref()
+                .is_some_and(|r| r.host == host && !is_running(&sys, &r.runner)),
+            _ => true,
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    if stale.is_empty() {
+        return Vec::new();
+    }
+    stale.sort();
+
+    let mut orphans: Vec<(PathBuf, Vec<Pid>)> = Vec::new();
+    for path in &stale {
+        let Some(ref record) = state.files[path].process else {
+            continue;
+        };
+        if record.host != host {
+            warn!(file = %path.display(), host = %record.host, "Can't check for processes left on another host");
+            continue;
+        }
+        let pids: Vec<Pid> = record
+            .children
+            .iter()
+            .flat_map(|child| process_tree(&sys, child))
+            .collect();
+        if !pids.is_empty() {
+            orphans.push((path.clone(), pids));
+        }
+    }
+
+    let mut held = HashSet::new();
+    if !orphans.is_empty() {
+        for (path, pids) in &orphans {
+            let pids: Vec<u32> = pids.iter().map(|p| p.as_u32()).collect();
+            warn!(file = %path.display(), pids = ?pids, "Processes from an earlier run are still working on file");
+        }
+        let kill = match config.recovery.orphans {
+            OrphanPolicy::Kill => true,
+            OrphanPolicy::Leave => false,
+            OrphanPolicy::Ask => ask_to_kill(orphans.len()),
+        };
+        if kill {
+            kill_orphans(&mut sys, &orphans);
+        }
+        for (path, pids) in &orphans {
+            if pids.iter().any(|pid| is_alive(&sys, *pid)) {
+                warn!(file = %path.display(), "Leaving file to the processes still working on it");
+                held.insert(path.clone());
+            }
+        }
+    }
+
+    for path in stale.iter().filter(|p| !held.contains(*p)) {
+        let status = state.files[path].status.clone();
+        if let Some(ref lease) = state.files[path].lease {
+            let owner = lease.owner.clone();
+            state.dead_runners.insert(owner);
+        }
+        match config.recovery.partial_edits {
+            PartialEditPolicy::Revert => {
+                match restore_inflight_scope(artifacts_dir, path, &config.allowlist_pattern, working_dir) {
+                    Ok(Some(restored)) if !restored.is_empty() => {
+                        info!(file = %path.display(), restored = ?restored, "Reverted partial edits from an unfinished run")
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!(file = %path.display(), error = %e, "Failed to revert partial edits"),
+                }
+            }
+            PartialEditPolicy::Keep => {}
+        }
+        if let Err(e) = clear_inflight_scope(artifacts_dir, path) {
+            warn!(file = %path.display(), error = %e, "Failed to remove snapshot of unfinished run");
+        }
+        if let Some(file) = state.file_mut(path) {
+            file.process = None;
+            file.lease = None;
+        }
+        info!(file = %path.display(), status = status.as_str(), "Requeueing file left in progress by an earlier run");
+    }
+
+    if let Err(e) = state.save(state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+    let mut held: Vec<PathBuf> = held.into_iter().collect();
+    held.sort();
+    held
+}
+
+/// Ask on the terminal whether to kill orphaned processes (no when there's no terminal)
+fn ask_to_kill(files: usize) -> bool {
+    if !std::io::stdin().is_terminal() {
+        warn!("Not running in a terminal, leaving orphaned processes alone (see --orphans)");
+        return false;
+    }
+    eprint!(
+        "Processes from an earlier run are still working on {} file(s). Kill them? [y/N] ",
+        files
+    );
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Kill orphaned process trees and give them a few seconds to exit
+fn kill_orphans(sys: &mut System, orphans: &[(PathBuf, Vec<Pid>)]) {
+    let pids: Vec<Pid> = orphans.iter().flat_map(|(_, pids)| pids.iter().copied()).collect();
+    for pid in &pids {
+        if let Some(process) = sys.process(*pid) {
+            info!(pid = %pid, name = %process.name(), "Killing orphaned process");
+            process.kill();
+        }
+    }
+    for _ in 0..50 {
+        std::thread::sleep(Duration::from_millis(100));
+        sys.refresh_processes();
+        if pids.iter().all(|pid| !is_alive(sys, *pid)) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attempts::{capture_scope, save_inflight_scope};
+    use crate::cli::Cli;
+    use crate::types::{FileState, FileStatus, ProcessRecord};
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn test_recover_kills_orphans_and_reverts_partial_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let working_dir = dir.path().join("work");
+        let artifacts_dir = dir.path().join("artifacts");
+        let state_path = dir.path().join("state.json");
+        fs::create_dir_all(&working_dir).unwrap();
+        fs::write(working_dir.join("a.ts"), "before").unwrap();
+
+        let cli = Cli::parse_from([
+            "claude-loop-runner",
+            "-i",
+            "in.json",
+            "-p",
+            "do it",
+            "--orphans",
+            "kill",
+            "--partial-edits",
+            "revert",
+        ]);
+        let config = Config::from_cli(&cli).unwrap();
+        let path = PathBuf::from("a.ts");
+        let scope = capture_scope(&path, &config.allowlist_pattern, &working_dir);
+        save_inflight_scope(&artifacts_dir, &path, &scope).unwrap();
+        fs::write(working_dir.join("a.ts"), "half edited").unwrap();
+
+        // An agent left running by a runner that has since died
+        let mut orphan = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let mut file = FileState::new(serde_json::Value::Null);
+        file.status = FileStatus::PromptInProgress;
+        file.process = Some(ProcessRecord {
+            host: host_name(),
+            runner: ProcessId {
+                pid: u32::MAX,
+                started: 0,
+            },
+            children: vec![process_id(orphan.id()).unwrap()],
+        });
+        let mut state = State::new(config.clone());
+        state.files.insert(path.clone(), file);
+
+        let held = recover_stale_files(&mut state, &config, &artifacts_dir, &working_dir, &state_path);
+
+        assert!(held.is_empty());
+        assert!(orphan.try_wait().unwrap().is_some());
+        assert_eq!(fs::read_to_string(working_dir.join("a.ts")).unwrap(), "before");
+        assert!(state.files[&path].process.is_none());
+        assert!(!artifacts_dir.join("attempts/a.ts/inflight.json").exists());
+    }
+}
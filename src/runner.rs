@@ -1,32 +1,100 @@
-use crate::config::Config;
+use crate::attempts;
+use crate::autoscale::{self, Levels, VerifyStats};
+use crate::batch::group_batches;
+use crate::budget::BudgetHandle;
+use crate::cache::{self, ResultCache};
+use crate::config::{BatchConfig, Config};
+use crate::control::{self, ControlHandle, DispatchCommand};
+use crate::deps::{DependencyGraph, Readiness};
+use crate::lease;
 use crate::memory::MemoryMonitor;
-use crate::pools::{spawn_prompt_pool, spawn_verify_pool, WorkerContext};
-use crate::process::expand_pattern;
+use crate::ordering::order_files;
+use crate::pipeline::StageAction;
+use crate::pools::{spawn_pipeline, spawn_prompt_pool, spawn_verify_pool, Released, WorkerContext};
+use crate::recovery;
+use crate::snapshot::{self, FsSnapshot};
 use crate::state::State;
 use crate::types::{FileStatus, FileTask};
 use crate::usage::{self, UsageMonitor};
-use anyhow::Result;
-use async_channel::{bounded, Sender};
+use crate::verify_groups::VerifyGroups;
+use anyhow::{Context, Result};
+use async_channel::{bounded, unbounded, Receiver, Sender};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
 /// Main orchestration function
 pub async fn run(
     config: Config,
-    state: State,
+    mut state: State,
     state_path: PathBuf,
     tasks_dir: PathBuf,
+    working_dir: PathBuf,
     shutdown_rx: tokio::sync::broadcast::Receiver<()>,
     usage_limit: Option<f64>,
 ) -> Result<()> {
     let config = Arc::new(config);
-    let state = Arc::new(Mutex::new(state));
 
-    // Get current working directory for ACP server
-    let working_dir = std::env::current_dir()?;
+    // Other runners resuming the same task only take files this runner hasn't leased
+    state.runner_id = Some(lease::runner_id());
+    info!(runner = state.runner_id.as_deref().unwrap_or_default(), "Runner started");
+
+    // Files an earlier run left in progress when it crashed or was killed. Recovery may
+    // ask on the terminal and wait for orphans to exit, so it runs off the async workers.
+    let (mut state, held) = {
+        let config = Arc::clone(&config);
+        let working_dir = working_dir.clone();
+        let state_path = state_path.clone();
+        tokio::task::spawn_blocking(move || {
+            let held = recovery::recover_stale_files(
+                &mut state,
+                &config,
+                &attempts::artifacts_dir(&state_path),
+                &working_dir,
+                &state_path,
+            );
+            (state, held)
+        })
+        .await
+        .context("Recovery of unfinished files panicked")?
+    };
+
+    // Recorded in each completed file's fingerprint (incremental runs only)
+    let agent_version = if config.incremental {
+        Some(cache::agent_version().await)
+    } else {
+        None
+    };
+
+    // Files unchanged since a completed run in any task reuse that run's result
+    if let Some(ref agent_version) = agent_version {
+        let results = ResultCache::load(&tasks_dir);
+        let reused = cache::reuse_results(&mut state, &results, &config, &working_dir, agent_version);
+        if !reused.is_empty() {
+            info!(files = reused.len(), "Skipping files unchanged since an earlier run");
+            if let Err(e) = state.save(&state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+        }
+    }
+
+    let snapshot = if state.git_state.snapshot_tracking {
+        match FsSnapshot::load(&snapshot::snapshot_path(&state_path)) {
+            Ok(snapshot) => Some(Arc::new(snapshot)),
+            Err(e) => {
+                warn!(error = %e, "Failed to load filesystem snapshot, unauthorized changes won't be detected");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Budgets count usage from earlier runs too, so raising a limit lets --resume continue
+    let budget = BudgetHandle::new(config.budget.clone(), state.usage.wall_secs);
+    let state = Arc::new(Mutex::new(state));
 
     // Create memory monitor with hysteresis (85% high, 70% low)
     let memory_monitor = MemoryMonitor::new();
@@ -45,32 +113,146 @@ pub async fn run(
         usage::noop_handle()
     };
 
+    // Pick the files for this run in a deterministic order
+    let mut files_to_process = select_files(&state, &config, &working_dir).await;
+    files_to_process.retain(|path| !held.contains(path));
+
+    // Dependencies between files; files on a cycle can never run
+    let graph = plan_dependencies(&state, &mut files_to_process, &config, &working_dir, &state_path).await;
+    let file_count = files_to_process.len();
+
     // Build global allowlist (no channel needed, just state mutation)
-    let file_count = build_allowlist(
+    build_allowlist(
         &state,
-        config.max_files,
+        &files_to_process,
         &config.allowlist_pattern,
         &state_path,
+        &working_dir,
     )
-    .await?;
+    .await;
 
     if file_count == 0 {
         info!("No files to process");
         return Ok(());
     }
 
-    let verify_concurrency = config.verify_concurrency.unwrap_or(config.concurrency);
+    {
+        let mut state = state.lock().await;
+        if budget.check_task(&state.usage) {
+            warn!("Task budget already used up, raise the limit and --resume to continue");
+            state.set_budget_paused(budget.reason());
+            if let Err(e) = state.save(&state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+            return Ok(());
+        }
+    }
 
-    info!(
-        files = file_count,
-        prompt_concurrency = config.concurrency,
-        verify_concurrency = verify_concurrency,
-        "Starting processing"
-    );
+    let pipeline = !config.pipeline.is_empty();
+    // A pipeline's prompt stages share the prompt pool's limit and its command stages
+    // the verify pool's, starting at the sum of their workers
+    let (prompt_concurrency, verify_concurrency) = if pipeline {
+        config.pipeline.iter().fold((0, 0), |(prompt, verify), stage| {
+            let workers = stage.concurrency.unwrap_or(config.concurrency);
+            match stage.action {
+                StageAction::Prompt(_) => (prompt + workers, verify),
+                StageAction::Command(_) => (prompt, verify + workers),
+            }
+        })
+    } else {
+        (
+            config.concurrency,
+            config.verify_concurrency.unwrap_or(config.concurrency),
+        )
+    };
+
+    if pipeline {
+        info!(files = file_count, stages = config.pipeline.len(), "Starting pipeline");
+        for stage in &config.pipeline {
+            info!(
+                stage = %stage.name,
+                concurrency = stage.concurrency.unwrap_or(config.concurrency),
+                max_retries = stage.max_retries,
+                "Pipeline stage"
+            );
+        }
+        if config.verification_cmd.is_some() || config.batch.enabled() {
+            warn!("--verify and --batch-size are ignored when running a pipeline");
+        }
+    } else {
+        info!(
+            files = file_count,
+            prompt_concurrency = config.concurrency,
+            verify_concurrency = verify_concurrency,
+            "Starting processing"
+        );
+    }
+
+    if config.batch.enabled() && !pipeline {
+        info!(
+            batch_size = config.batch.size,
+            batch_by = ?config.batch.by,
+            "Batching files into shared prompts"
+        );
+    }
+
+    if config.verify_batch.enabled() && config.verification_cmd.is_some() && !pipeline {
+        info!(
+            verify_batch_size = config.verify_batch.size,
+            wait_secs = config.verify_batch.wait_secs,
+            "Batching verification runs"
+        );
+    }
+
+    for group in config.verify_groups.iter().filter(|_| !pipeline) {
+        info!(
+            group = %group.name,
+            limit = group.limit,
+            patterns = ?group.patterns,
+            "Verify group"
+        );
+    }
+
+    // Create channels sized to fit all files in the task (avoids deadlock, including
+    // when files outside this run's selection are requeued through the control file)
+    let capacity = state.lock().await.files.len().max(file_count);
+    let (prompt_tx, prompt_rx) = bounded::<Vec<FileTask>>(capacity);
+    let (verify_tx, verify_rx) = bounded::<FileTask>(capacity);
+    let (done_tx, done_rx) = unbounded::<Released>();
+
+    // Live control through the task's control file (see the `ctl` subcommand)
+    let max_concurrency = config.max_concurrency.unwrap_or(config.concurrency);
+    let control = ControlHandle::new(prompt_concurrency, verify_concurrency, max_concurrency);
+    let (commands_tx, commands_rx) = unbounded::<DispatchCommand>();
+    let control_path = control::control_path(&state_path);
+    let control_listener = match control::spawn_control_listener(
+        control_path.clone(),
+        control.clone(),
+        Arc::clone(&state),
+        state_path.clone(),
+        commands_tx,
+    ) {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            warn!(error = %e, "Failed to set up control file, live control disabled");
+            None
+        }
+    };
 
-    // Create channels sized to fit all files (avoids deadlock)
-    let (prompt_tx, prompt_rx) = bounded::<FileTask>(file_count);
-    let (verify_tx, verify_rx) = bounded::<FileTask>(file_count);
+    // Resize pools from observed signals if requested
+    let verify_stats = VerifyStats::default();
+    let autoscaler = config.auto_concurrency.then(|| {
+        let max = Levels {
+            prompt: max_concurrency.max(prompt_concurrency),
+            verify: max_concurrency.max(verify_concurrency),
+        };
+        info!(
+            max_prompt = max.prompt,
+            max_verify = max.verify,
+            "Adaptive concurrency enabled"
+        );
+        autoscale::spawn_autoscaler(control.clone(), verify_stats.clone(), max, Duration::from_secs(15))
+    });
 
     // Spawn worker pools BEFORE queuing so consumers are ready
     let ctx = WorkerContext {
@@ -80,39 +262,61 @@ pub async fn run(
         working_dir: working_dir.clone(),
         memory: memory_handle,
         usage: usage_handle,
+        control: control.clone(),
+        verify_stats: verify_stats.clone(),
+        budget: budget.clone(),
+        verify_groups: Arc::new(VerifyGroups::new(&config.verify_groups)),
+        snapshot,
+        artifacts_dir: attempts::artifacts_dir(&state_path),
+        agent_version,
+        done_tx,
     };
 
-    let prompt_handles = spawn_prompt_pool(
-        config.concurrency,
-        prompt_rx.clone(),
-        verify_tx.clone(),
-        ctx.clone(),
-    );
-
-    let verify_handles = spawn_verify_pool(
-        verify_concurrency,
-        verify_rx.clone(),
-        ctx,
-        tasks_dir,
-    );
-
-    // Now queue files — workers are already consuming
-    queue_files(
-        &state,
-        &prompt_tx,
-        &verify_tx,
-        config.max_files,
-    )
-    .await?;
+    // Pools spawn workers as the control gates' limits grow, up to their ceilings.
+    // A pipeline has a pool per stage in their place.
+    let (prompt_handles, verify_handles) = if pipeline {
+        let handles = spawn_pipeline(&config.pipeline, prompt_rx.clone(), ctx, capacity);
+        (handles, Vec::new())
+    } else {
+        let prompt_handle = spawn_prompt_pool(prompt_rx.clone(), verify_tx.clone(), ctx.clone());
+        let verify_handle = spawn_verify_pool(verify_rx.clone(), ctx);
+        (vec![prompt_handle], vec![verify_handle])
+    };
 
-    // Close senders so workers know when to stop
-    drop(prompt_tx);
-    drop(verify_tx);
+    // Now queue files as their dependencies complete — workers are already consuming.
+    // The dispatcher owns the senders and drops them once nothing is left, so workers know when to stop.
+    let dispatcher = Dispatcher {
+        state: Arc::clone(&state),
+        state_path: state_path.clone(),
+        graph,
+        verifying: config.verification_cmd.is_some() && !pipeline,
+        // Pipeline stages take one file at a time
+        batch: if pipeline {
+            BatchConfig::default()
+        } else {
+            config.batch.clone()
+        },
+        working_dir: working_dir.clone(),
+        budget: budget.clone(),
+        lease_check: lease_check_interval(config.lease_ttl_secs),
+        prompt_tx,
+        verify_tx,
+        done_rx,
+        commands_rx,
+    };
+    let dispatcher = tokio::spawn(dispatcher.run(files_to_process));
+    let heartbeat =
+        spawn_lease_heartbeat(Arc::clone(&state), state_path.clone(), config.lease_ttl_secs);
 
     // Wait for shutdown signal or completion
     let mut shutdown_rx = shutdown_rx;
     tokio::select! {
         _ = async {
+            match dispatcher.await {
+                Ok(Err(e)) => error!(error = %e, "Dispatcher failed"),
+                Err(e) => error!(error = %e, "Dispatcher panicked"),
+                Ok(Ok(())) => {}
+            }
             for handle in prompt_handles {
                 let _ = handle.await;
             }
@@ -129,62 +333,149 @@ pub async fn run(
         }
     }
 
-    // Print summary
-    let state = state.lock().await;
+    heartbeat.abort();
+    if let Some(autoscaler) = autoscaler {
+        autoscaler.abort();
+        info!(
+            prompt_concurrency = control.prompt_gate.limit(),
+            verify_concurrency = control.verify_gate.limit(),
+            "Final adaptive concurrency levels"
+        );
+    }
+    let listening = control_listener.is_some();
+    if let Some(listener) = control_listener {
+        listener.abort();
+    }
+
+    // Record this run's time and whether it stopped early for the budget
+    let mut state = state.lock().await;
+    state.release_leases();
+    state.usage.wall_secs += budget.run_secs();
     let summary = state.get_summary();
+    let unfinished = summary.total
+        - summary.completed
+        - summary.failed
+        - summary.blocked
+        - summary.skipped;
+    state.set_budget_paused(budget.reason().filter(|_| unfinished > 0));
+    // Leave a complete state file for anyone reading or editing it by hand
+    if let Err(e) = state.compact(&state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+
+    // Leave the control file to runners still working on the task
+    let others_running = state.files.values().any(|f| {
+        f.lease
+            .as_ref()
+            .is_some_and(|l| state.leased_elsewhere(l))
+    });
+    if listening && !others_running {
+        let _ = std::fs::remove_file(&control_path);
+    }
+
+    // Print summary
     info!(
         total = summary.total,
         completed = summary.completed,
         failed = summary.failed,
+        blocked = summary.blocked,
+        skipped = summary.skipped,
         pending = summary.pending,
         "Processing complete"
     );
+    if !state.usage.is_empty() {
+        info!(
+            cost_usd = format!("{:.4}", state.usage.cost_usd),
+            tokens = state.usage.tokens(),
+            agent_calls = state.usage.agent_calls,
+            wall_secs = format!("{:.0}", state.usage.wall_secs),
+            "Task usage"
+        );
+    }
+    if let Some(ref reason) = state.budget_paused {
+        warn!(
+            reason = %reason,
+            remaining = unfinished,
+            "Paused for budget: raise the limit and --resume to continue"
+        );
+    }
 
     Ok(())
 }
 
-/// Build the global allowlist and return the count of files to process.
-/// Does NOT queue files to channels — just updates state.
-async fn build_allowlist(
-    state: &Arc<Mutex<State>>,
-    max_files: Option<usize>,
-    allowlist_pattern: &str,
-    state_path: &Path,
-) -> Result<usize> {
-    let mut state = state.lock().await;
+/// Leases are renewed, and other runners' leases checked, every third of their lifetime
+fn lease_check_interval(ttl_secs: u64) -> Duration {
+    Duration::from_secs((ttl_secs / 3).max(1))
+}
+
+/// Renew this runner's leases every third of their lifetime so other runners don't take
+/// its files over while an agent or verification is still running
+fn spawn_lease_heartbeat(
+    state: Arc<Mutex<State>>,
+    state_path: PathBuf,
+    ttl_secs: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(lease_check_interval(ttl_secs));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let mut state = state.lock().await;
+            state.renew_leases(ttl_secs);
+            if let Err(e) = state.save(&state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+        }
+    })
+}
+
+/// Select the files that still need processing, ordered by the configured strategy
+/// and truncated to `max_files`
+async fn select_files(state: &Arc<Mutex<State>>, config: &Config, working_dir: &Path) -> Vec<PathBuf> {
+    let state = state.lock().await;
 
-    // Collect files that need processing
     let mut files_to_process: Vec<_> = state
         .files
         .iter()
         .filter(|(_, file_state)| {
+            // Blocked files are re-checked: their dependency may have been reset since
             !matches!(
                 file_state.status,
-                FileStatus::Completed | FileStatus::Failed
+                FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped
             )
         })
         .map(|(path, _)| path.clone())
         .collect();
 
+    order_files(&mut files_to_process, &state.files, config.order, working_dir).await;
+
     // Apply max files limit
-    if let Some(max) = max_files {
+    if let Some(max) = config.max_files {
         files_to_process.truncate(max);
     }
 
+    info!(order = ?config.order, files = files_to_process.len(), "Selected files to process");
+    files_to_process
+}
+
+/// Build the global allowlist for the selected files.
+/// Does NOT queue files to channels — just updates state.
+async fn build_allowlist(
+    state: &Arc<Mutex<State>>,
+    files_to_process: &[PathBuf],
+    allowlist_pattern: &str,
+    state_path: &Path,
+    working_dir: &Path,
+) {
+    let mut state = state.lock().await;
+
     // Build global allowlist (skip if already built)
-    if state.git_state.enabled {
+    if state.git_state.tracking_enabled() {
         if state.git_state.global_allowlist_patterns.is_empty() {
-            let working_dir = std::env::current_dir().unwrap_or_default();
-            for path in &files_to_process {
-                let pattern = expand_pattern(allowlist_pattern, path);
-                state.git_state.add_allowlist_pattern(pattern.clone());
-                debug!(pattern = %pattern, "Added to global allowlist");
-
-                // Discover related test/snapshot files and add their patterns
-                for related in crate::process::find_related_files(path, &working_dir) {
-                    let related_pattern = expand_pattern(allowlist_pattern, &related);
-                    state.git_state.add_allowlist_pattern(related_pattern);
-                }
+            for path in files_to_process {
+                // Also discovers related test/snapshot files and adds their patterns
+                state.git_state_mut().allow_file(allowlist_pattern, path, working_dir);
+                debug!(file = %path.display(), "Added to global allowlist");
             }
 
             if let Err(e) = state.save(state_path) {
@@ -202,49 +493,317 @@ async fn build_allowlist(
             );
         }
     }
-
-    Ok(files_to_process.len())
 }
 
-/// Queue files to the appropriate worker channels
-async fn queue_files(
+/// Build the dependency graph and block selected files that sit on (or behind) a cycle,
+/// removing them from this run
+async fn plan_dependencies(
     state: &Arc<Mutex<State>>,
-    prompt_tx: &Sender<FileTask>,
-    verify_tx: &Sender<FileTask>,
-    max_files: Option<usize>,
-) -> Result<usize> {
-    let state = state.lock().await;
-    let mut queued = 0;
+    files_to_process: &mut Vec<PathBuf>,
+    config: &Config,
+    working_dir: &Path,
+    state_path: &Path,
+) -> DependencyGraph {
+    let mut state = state.lock().await;
+    let graph = DependencyGraph::build(&state.files, config.infer_dependencies, working_dir);
+    if graph.is_empty() {
+        return graph;
+    }
+    info!(edges = graph.edge_count(), "Scheduling files by dependency order");
+
+    let cyclic = graph.cyclic_files();
+    if cyclic.is_empty() {
+        return graph;
+    }
+
+    files_to_process.retain(|path| {
+        if !cyclic.contains(path) {
+            return true;
+        }
+        warn!(file = %path.display(), "File is part of (or depends on) a dependency cycle, blocking");
+        state.update_status(path, FileStatus::Blocked, None);
+        state.set_error(path, "Dependency cycle".to_string());
+        false
+    });
+    if let Err(e) = state.save(state_path) {
+        error!(error = %e, "Failed to save state");
+    }
+
+    graph
+}
 
-    for (path, file_state) in &state.files {
-        if let Some(max) = max_files {
-            if queued >= max {
+/// Queues files to the worker pools as their dependencies complete
+struct Dispatcher {
+    state: Arc<Mutex<State>>,
+    state_path: PathBuf,
+    graph: DependencyGraph,
+    /// Whether a verification command is configured (verify workers exit immediately otherwise)
+    verifying: bool,
+    batch: BatchConfig,
+    working_dir: PathBuf,
+    budget: BudgetHandle,
+    /// How often files leased by other runners are checked for expired leases
+    lease_check: Duration,
+    prompt_tx: Sender<Vec<FileTask>>,
+    verify_tx: Sender<FileTask>,
+    done_rx: Receiver<Released>,
+    /// Skip/requeue requests from the control file
+    commands_rx: Receiver<DispatchCommand>,
+}
+
+impl Dispatcher {
+    /// Queue files, in order, as their dependencies complete. Files downstream of a failed
+    /// or blocked dependency are marked blocked instead. Files another runner holds are
+    /// watched until it finishes them, or queued again if its lease expires. Returns once
+    /// nothing is in flight or watched; files still waiting then depend on files outside
+    /// this run.
+    async fn run(self, mut pending: Vec<PathBuf>) -> Result<()> {
+        let mut in_flight = 0usize;
+        let mut watching: Vec<PathBuf> = Vec::new();
+        // The wall-time budget can run out between agent calls
+        let mut budget_tick = tokio::time::interval(Duration::from_secs(1));
+        let mut lease_tick = tokio::time::interval(self.lease_check);
+
+        loop {
+            if !self.budget.is_exhausted() {
+                in_flight += self.dispatch_ready(&mut pending).await?;
+            }
+
+            if in_flight == 0 && (watching.is_empty() || self.budget.is_exhausted()) {
                 break;
             }
+            tokio::select! {
+                _ = budget_tick.tick() => {
+                    let usage = self.state.lock().await.usage.clone();
+                    self.budget.check_task(&usage);
+                }
+                _ = lease_tick.tick(), if !watching.is_empty() => {
+                    self.check_leases(&mut watching, &mut pending).await;
+                }
+                done = self.done_rx.recv() => match done {
+                    Ok(Released::Finished(path)) => {
+                        debug!(file = %path.display(), "File finished");
+                        in_flight -= 1;
+                    }
+                    Ok(Released::LeasedElsewhere(path)) => {
+                        in_flight -= 1;
+                        watching.push(path);
+                    }
+                    Err(_) => break,
+                },
+                Ok(command) = self.commands_rx.recv() => {
+                    self.handle_command(command, &mut pending).await;
+                }
+            }
         }
 
-        match file_state.status {
-            FileStatus::Pending | FileStatus::PromptInProgress => {
-                let task = FileTask {
-                    path: path.clone(),
-                    original_data: file_state.original_data.clone(),
+        if self.budget.is_exhausted() {
+            if !pending.is_empty() {
+                info!(files = pending.len(), "Files not dispatched because the budget ran out");
+            }
+        } else if !pending.is_empty() {
+            warn!(
+                files = pending.len(),
+                "Files left pending: they depend on files not selected in this run"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Pick up what other runners saved and stop watching files they finished. Files
+    /// whose lease expired (or was dropped unfinished) go back to `pending` to be taken over.
+    async fn check_leases(&self, watching: &mut Vec<PathBuf>, pending: &mut Vec<PathBuf>) {
+        let mut state = self.state.lock().await;
+        if let Err(e) = state.refresh(&self.state_path) {
+            warn!(error = %e, "Failed to read other runners' changes");
+        }
+        watching.retain(|path| {
+            let Some(file) = state.files.get(path) else {
+                return false;
+            };
+            if file.status.is_terminal() {
+                debug!(file = %path.display(), "File finished by another runner");
+                return false;
+            }
+            if file.lease.as_ref().is_some_and(|l| state.leased_elsewhere(l)) {
+                return true;
+            }
+            info!(file = %path.display(), "Another runner's lease on file expired, taking it over");
+            pending.push(path.clone());
+            false
+        });
+    }
+
+    /// Apply a skip or requeue request from the control file
+    async fn handle_command(&self, command: DispatchCommand, pending: &mut Vec<PathBuf>) {
+        let mut state = self.state.lock().await;
+
+        match command {
+            DispatchCommand::Skip(path) => {
+                let Some(status) = state.files.get(&path).map(|f| f.status.clone()) else {
+                    warn!(file = %path.display(), "Cannot skip unknown file");
+                    return;
                 };
-                prompt_tx.send(task).await?;
-                queued += 1;
+                match status {
+                    // Not started yet, or queued for verification: workers drop it when they pick it up
+                    FileStatus::Pending | FileStatus::Blocked | FileStatus::AwaitingVerification => {
+                        state.update_status(&path, FileStatus::Skipped, None);
+                        pending.retain(|p| *p != path);
+                        info!(file = %path.display(), "File skipped");
+                    }
+                    FileStatus::PromptInProgress
+                    | FileStatus::VerifyInProgress
+                    | FileStatus::FixupInProgress
+                    | FileStatus::InStage => {
+                        warn!(file = %path.display(), status = status.as_str(), "Cannot skip a file while a worker is on it");
+                        return;
+                    }
+                    FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped => {
+                        warn!(file = %path.display(), status = status.as_str(), "File is already finished, nothing to skip");
+                        return;
+                    }
+                }
+            }
+            DispatchCommand::Requeue(path) => {
+                let Some(status) = state.files.get(&path).map(|f| f.status.clone()) else {
+                    warn!(file = %path.display(), "Cannot requeue unknown file");
+                    return;
+                };
+                match status {
+                    FileStatus::Completed
+                    | FileStatus::Failed
+                    | FileStatus::Blocked
+                    | FileStatus::Skipped => {
+                        state.reset_file(&path);
+                        if !pending.contains(&path) {
+                            pending.push(path.clone());
+                        }
+                        info!(file = %path.display(), previous = status.as_str(), "File requeued");
+                    }
+                    _ => {
+                        warn!(file = %path.display(), status = status.as_str(), "File is already queued or in progress");
+                        return;
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = state.save(&self.state_path) {
+            error!(error = %e, "Failed to save state");
+        }
+    }
+
+    /// Queue every pending file whose dependencies are complete and block those behind
+    /// a failed dependency. Returns how many files were queued.
+    async fn dispatch_ready(&self, pending: &mut Vec<PathBuf>) -> Result<usize> {
+        let mut state = self.state.lock().await;
+        let mut queued = 0;
+        let mut any_blocked = false;
+        let mut to_prompt = Vec::new();
+
+        // Repeat until stable so blocking cascades through chains of dependents
+        loop {
+            let mut blocked = false;
+            let mut waiting = Vec::new();
+
+            for path in std::mem::take(pending) {
+                match self.graph.readiness(&path, &state.files) {
+                    Readiness::Ready => {
+                        if self.queue_file(&mut state, &path, &mut to_prompt).await? {
+                            queued += 1;
+                        }
+                    }
+                    Readiness::Waiting => waiting.push(path),
+                    Readiness::Blocked(dep) => {
+                        warn!(
+                            file = %path.display(),
+                            dependency = %dep.display(),
+                            "Dependency did not complete, blocking file"
+                        );
+                        state.update_status(&path, FileStatus::Blocked, None);
+                        state.set_error(&path, format!("Blocked by dependency: {}", dep.display()));
+                        blocked = true;
+                    }
+                }
+            }
+
+            *pending = waiting;
+            any_blocked |= blocked;
+            if !blocked {
+                break;
+            }
+        }
+
+        if any_blocked {
+            if let Err(e) = state.save(&self.state_path) {
+                error!(error = %e, "Failed to save state");
+            }
+        }
+
+        // Files ready for a prompt go out in batches (single files unless batching is on)
+        let batches = group_batches(to_prompt, &self.batch, |path| {
+            std::fs::metadata(self.working_dir.join(path))
+                .map(|m| m.len())
+                .unwrap_or(0)
+        });
+        for batch in batches {
+            let tasks: Vec<FileTask> = batch
+                .into_iter()
+                .map(|path| FileTask {
+                    original_data: state.files[&path].original_data.clone(),
+                    path,
+                })
+                .collect();
+            if tasks.len() > 1 {
+                debug!(files = tasks.len(), "Queued batch");
+            }
+            self.prompt_tx.send(tasks).await?;
+        }
+
+        Ok(queued)
+    }
+
+    /// Queue a single file to the verify channel, or collect it into `to_prompt` for batching,
+    /// depending on its status. Returns false if nothing will pick the file up in this run.
+    async fn queue_file(
+        &self,
+        state: &mut State,
+        path: &Path,
+        to_prompt: &mut Vec<PathBuf>,
+    ) -> Result<bool> {
+        let Some(file_state) = state.file_mut(path) else {
+            return Ok(false);
+        };
+        match file_state.status {
+            // Files part way through a pipeline go back to the router, which picks their stage
+            FileStatus::Pending
+            | FileStatus::PromptInProgress
+            | FileStatus::Blocked
+            | FileStatus::InStage => {
+                if file_state.status == FileStatus::Blocked {
+                    // Its dependencies have since completed
+                    file_state.set_status(FileStatus::Pending, None);
+                    file_state.last_error = None;
+                }
+                to_prompt.push(path.to_path_buf());
+                Ok(true)
             }
             FileStatus::AwaitingVerification
             | FileStatus::VerifyInProgress
             | FileStatus::FixupInProgress => {
+                if !self.verifying {
+                    debug!(file = %path.display(), "No verification command, leaving file awaiting verification");
+                    return Ok(false);
+                }
                 let task = FileTask {
-                    path: path.clone(),
+                    path: path.to_path_buf(),
                     original_data: file_state.original_data.clone(),
                 };
-                verify_tx.send(task).await?;
-                queued += 1;
+                self.verify_tx.send(task).await?;
+                Ok(true)
             }
-            FileStatus::Completed | FileStatus::Failed => {}
+            FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped => Ok(false),
         }
     }
-
-    Ok(queued)
 }
@@ -0,0 +1,260 @@
+use crate::git::GitState;
+use crate::process::matches_allowlist;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// A single file in a filesystem snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// SHA-256 of the file content
+    pub hash: String,
+    /// Size in bytes (used with `modified` to skip rehashing untouched files)
+    pub size: u64,
+    /// Last modification time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// Content-hash manifest of a directory tree.
+///
+/// Used instead of `git status` for change tracking when the working directory
+/// is not a git repository. Respects `.gitignore`/`.ignore` files even without git.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FsSnapshot {
+    /// Paths (relative to the root) excluded from the walk, e.g. the tasks directory
+    #[serde(default)]
+    pub excluded: Vec<PathBuf>,
+    /// Every tracked file, keyed by path relative to the root
+    pub files: BTreeMap<PathBuf, SnapshotEntry>,
+}
+
+/// Path of the snapshot manifest stored next to a task's state file
+pub fn snapshot_path(state_path: &Path) -> PathBuf {
+    state_path.with_extension("snapshot.json")
+}
+
+/// Hash a byte slice with SHA-256, returned as lowercase hex
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hash a file's content with SHA-256
+pub fn hash_file(path: &Path) -> Result<String> {
+    let content =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(hash_bytes(&content))
+}
+
+impl FsSnapshot {
+    /// Walk `root` and hash every non-ignored file.
+    /// `excluded` paths (absolute or relative to the current directory) are skipped if inside `root`.
+    pub fn capture(root: &Path, excluded: &[PathBuf]) -> Result<Self> {
+        let canonical_root = root
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", root.display()))?;
+        let excluded: Vec<PathBuf> = excluded
+            .iter()
+            .filter_map(|e| e.canonicalize().ok())
+            .filter_map(|e| e.strip_prefix(&canonical_root).ok().map(Path::to_path_buf))
+            .filter(|e| !e.as_os_str().is_empty())
+            .collect();
+
+        let files = walk(root, &excluded, None)?;
+        info!(files = files.len(), "Captured filesystem snapshot");
+
+        Ok(Self { excluded, files })
+    }
+
+    /// Load a snapshot manifest from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse snapshot: {}", path.display()))
+    }
+
+    /// Save the snapshot manifest atomically (write to temp, then rename)
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create snapshot directory: {}", parent.display())
+            })?;
+        }
+
+        let temp_path = path.with_extension("json.tmp");
+        let content = serde_json::to_string(self).context("Failed to serialize snapshot")?;
+        fs::write(&temp_path, content)
+            .with_context(|| format!("Failed to write snapshot: {}", temp_path.display()))?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to rename snapshot to: {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Reuse the manifest from a previous run if one exists, otherwise capture and save a new one
+    pub fn load_or_capture(path: &Path, root: &Path, excluded: &[PathBuf]) -> Result<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let snapshot = Self::capture(root, excluded)?;
+        snapshot.save(path)?;
+        Ok(snapshot)
+    }
+
+    /// Files added, modified or deleted under `root` since the snapshot was taken
+    pub fn changed_files(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        let current = walk(root, &self.excluded, Some(&self.files))?;
+
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, entry)| self.files.get(*path).map(|e| &e.hash) != Some(&entry.hash))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            self.files
+                .keys()
+                .filter(|path| !current.contains_key(*path))
+                .cloned(),
+        );
+
+        Ok(changed)
+    }
+}
+
+/// Walk the tree and build a manifest. Hashes are reused from `previous`
+/// when a file's size and modification time are unchanged.
+fn walk(
+    root: &Path,
+    excluded: &[PathBuf],
+    previous: Option<&BTreeMap<PathBuf, SnapshotEntry>>,
+) -> Result<BTreeMap<PathBuf, SnapshotEntry>> {
+    let excluded_abs: Vec<PathBuf> = excluded.iter().map(|e| root.join(e)).collect();
+
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .parents(false)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            entry.file_name() != ".git" && !excluded_abs.iter().any(|e| entry.path() == e)
+        })
+        .build();
+
+    let mut files = BTreeMap::new();
+    for entry in walker {
+        let entry = entry.context("Failed to walk directory")?;
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+
+        let rel = match entry.path().strip_prefix(root) {
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => continue,
+        };
+        let meta = entry
+            .metadata()
+            .with_context(|| format!("Failed to stat {}", entry.path().display()))?;
+        let size = meta.len();
+        let modified = meta.modified().ok().map(DateTime::<Utc>::from);
+
+        let unchanged = previous
+            .and_then(|p| p.get(&rel))
+            .filter(|e| e.size == size && e.modified.is_some() && e.modified == modified);
+
+        let hash = match unchanged {
+            Some(e) => e.hash.clone(),
+            None => hash_file(entry.path())?,
+        };
+
+        files.insert(
+            rel,
+            SnapshotEntry {
+                hash,
+                size,
+                modified,
+            },
+        );
+    }
+
+    Ok(files)
+}
+
+/// Snapshot equivalent of `check_git_changes_filtered`: classify files changed since
+/// the snapshot into allowed and unauthorized using this worker's pattern and the
+/// global allowlist. Blocking — run it off the async executor for large trees.
+pub fn check_snapshot_changes_filtered(
+    allowlist_pattern: &str,
+    working_dir: &Path,
+    snapshot: &FsSnapshot,
+    git_state: &GitState,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut allowed = Vec::new();
+    let mut unauthorized = Vec::new();
+
+    for path in snapshot.changed_files(working_dir)? {
+        if matches_allowlist(&path, allowlist_pattern) || git_state.matches_global_allowlist(&path)
+        {
+            allowed.push(path);
+        } else {
+            debug!(file = %path.display(), "Unauthorized change detected by snapshot");
+            unauthorized.push(path);
+        }
+    }
+
+    Ok((allowed, unauthorized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_detects_changes_and_respects_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::create_dir_all(root.join("tasks")).unwrap();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        fs::write(root.join("src/parser.ts"), "a").unwrap();
+        fs::write(root.join("src/other.ts"), "b").unwrap();
+        fs::write(root.join("build/out.js"), "c").unwrap();
+        fs::write(root.join("tasks/state_0.json"), "{}").unwrap();
+
+        let snapshot = FsSnapshot::capture(root, &[root.join("tasks")]).unwrap();
+        assert!(snapshot.files.contains_key(Path::new("src/parser.ts")));
+        assert!(!snapshot.files.contains_key(Path::new("build/out.js")));
+        assert!(!snapshot.files.contains_key(Path::new("tasks/state_0.json")));
+
+        fs::write(root.join("src/parser.ts"), "changed").unwrap();
+        fs::write(root.join("src/parser.test.ts"), "new").unwrap();
+        fs::remove_file(root.join("src/other.ts")).unwrap();
+        fs::write(root.join("build/out.js"), "ignored").unwrap();
+        fs::write(root.join("tasks/state_0.json"), "{\"x\":1}").unwrap();
+
+        let mut changed = snapshot.changed_files(root).unwrap();
+        changed.sort();
+        assert_eq!(
+            changed,
+            vec![
+                PathBuf::from("src/other.ts"),
+                PathBuf::from("src/parser.test.ts"),
+                PathBuf::from("src/parser.ts"),
+            ]
+        );
+
+        let (allowed, unauthorized) =
+            check_snapshot_changes_filtered("parser*", root, &snapshot, &GitState::default())
+                .unwrap();
+        assert_eq!(allowed.len(), 2);
+        assert_eq!(unauthorized, vec![PathBuf::from("src/other.ts")]);
+    }
+}
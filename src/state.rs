@@ -1,20 +1,34 @@
-use crate::config::Config;
+use crate::config::{Config, RemovedFilePolicy};
 use crate::git::GitState;
-use crate::types::{FileState, FileStatus, ParsedResult};
+use crate::journal::{append_journal, journal_path, read_journal, reset_journal, JournalEntry};
+use crate::lease::{lock_state, Lease};
+use crate::migrate::{backup, migrate_state, STATE_VERSION};
+use crate::types::{
+    AgentUsage, AttemptRecord, FileState, FileStatus, ParsedResult, StageState, UsageTotals,
+};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::warn;
 
 /// Persistent state for the runner
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
+    /// Format of the state file on disk; older files are migrated as they are loaded and
+    /// rewritten in the current format on the next save
+    #[serde(default)]
+    schema_version: u32,
     /// Configuration for this run
     pub config: Config,
     /// State of each file being processed
     pub files: HashMap<PathBuf, FileState>,
+    /// Files dropped from the input file by a sync, kept in case they come back
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub archived: HashMap<PathBuf, FileState>,
     /// When this run started
     pub started_at: DateTime<Utc>,
     /// Last update time
@@ -22,22 +36,164 @@ pub struct State {
     /// Git state (dirty files, branch info)
     #[serde(default)]
     pub git_state: GitState,
+    /// Cost, tokens, agent calls and run time across all runs of this task
+    #[serde(default, skip_serializing_if = "UsageTotals::is_empty")]
+    pub usage: UsageTotals,
+    /// Why the last run stopped early because its budget ran out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_paused: Option<String>,
+    /// This process's runner ID when it takes leases on files
+    #[serde(skip)]
+    pub runner_id: Option<String>,
+    /// Runners known to have exited, whose leases don't need to expire before their files
+    /// are taken over
+    #[serde(skip)]
+    pub dead_runners: HashSet<String>,
+    /// Bumped each time the state file is rewritten; the journal names the generation
+    /// its entries apply to
+    #[serde(default)]
+    journal_generation: u64,
+    /// How far into the journal this state has read or written
+    #[serde(skip)]
+    journal_offset: u64,
+    /// Files and usage as last read from or written to disk, to tell our changes from
+    /// other runners' when saving
+    #[serde(skip)]
+    synced: Synced,
+    /// Files changed since the last save (see `file_mut`), so a save only compares those
+    #[serde(skip)]
+    changed: HashSet<PathBuf>,
+    /// Task settings changed since the last save (see `config_mut`)
+    #[serde(skip)]
+    settings_changed: bool,
+}
+
+/// Differences between the task and its input file found by a sync
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// In the input but not the task (including files restored from the archive)
+    pub added: Vec<PathBuf>,
+    /// In the task but no longer in the input
+    pub removed: Vec<PathBuf>,
+    /// Listed with different metadata
+    pub changed: Vec<PathBuf>,
+    /// Changed files sent back to Pending
+    pub reset: Vec<PathBuf>,
+}
+
+impl SyncReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Files of a task to carry over into a clone. Each kind of filter that is set must
+/// match (any one of several statuses or globs is enough); no filters select every file.
+#[derive(Debug, Clone, Default)]
+pub struct FileSelector {
+    pub statuses: Vec<FileStatus>,
+    pub globs: Vec<glob::Pattern>,
+    /// Text the file's last error must contain
+    pub error: Option<String>,
+}
+
+impl FileSelector {
+    pub fn matches(&self, path: &Path, file: &FileState) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&file.status))
+            && (self.globs.is_empty() || self.globs.iter().any(|g| g.matches_path(path)))
+            && self.error.as_ref().is_none_or(|text| {
+                file.last_error
+                    .as_ref()
+                    .is_some_and(|error| error.contains(text.as_str()))
+            })
+    }
+}
+
+/// Read an input file: a JSON object mapping each file path to its metadata.
+/// serde_json preserves key order, so this keeps the input file's ordering.
+pub fn read_input(input_path: &Path) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let content = fs::read_to_string(input_path)
+        .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse input file: {}", input_path.display()))
+}
+
+/// Journal entries past this size are folded into the state file on the next save
+const COMPACT_AFTER_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default)]
+struct Synced {
+    files: HashMap<PathBuf, FileState>,
+    usage: UsageTotals,
+    meta: serde_json::Value,
+    /// False until the state has been read from or written to disk
+    on_disk: bool,
+    /// Modification time of the state file when it was last read or written
+    modified: Option<SystemTime>,
+}
+
+/// Modification time of a state file, to tell whether another runner rewrote it
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
 }
 
 impl State {
     /// Create a new state with the given config
     pub fn new(config: Config) -> Self {
         Self {
+            schema_version: STATE_VERSION,
             config,
             files: HashMap::new(),
+            archived: HashMap::new(),
             started_at: Utc::now(),
             updated_at: Utc::now(),
             git_state: GitState::default(),
+            usage: UsageTotals::default(),
+            budget_paused: None,
+            runner_id: None,
+            dead_runners: HashSet::new(),
+            journal_generation: 0,
+            journal_offset: 0,
+            synced: Synced::default(),
+            changed: HashSet::new(),
+            settings_changed: false,
         }
     }
 
+    /// A file to change, saved with the next `save`. Files changed through `files`
+    /// directly are only saved when the state file is rewritten.
+    pub fn file_mut(&mut self, path: &Path) -> Option<&mut FileState> {
+        let file = self.files.get_mut(path)?;
+        self.changed.insert(path.to_path_buf());
+        Some(file)
+    }
+
+    /// Note that a file was changed through `files`, so the next save includes it
+    pub fn mark_changed(&mut self, path: &Path) {
+        self.changed.insert(path.to_path_buf());
+    }
+
+    /// The task's config to change; the state file is rewritten with the next save
+    pub fn config_mut(&mut self) -> &mut Config {
+        self.settings_changed = true;
+        &mut self.config
+    }
+
+    /// The task's git state to change; the state file is rewritten with the next save
+    pub fn git_state_mut(&mut self) -> &mut GitState {
+        self.settings_changed = true;
+        &mut self.git_state
+    }
+
+    /// Record why the run stopped for its budget (None once it no longer has)
+    pub fn set_budget_paused(&mut self, reason: Option<String>) {
+        self.settings_changed |= self.budget_paused != reason;
+        self.budget_paused = reason;
+    }
+
     /// Set the git state (called after capturing initial git status)
-    /// Preserves existing global_allowlist_patterns from a prior run
+    /// Preserves existing global_allowlist_patterns, branch info, squash base and the
+    /// pre-existing dirty files from a prior run
     pub fn set_git_state(&mut self, mut git_state: GitState) {
         if git_state.global_allowlist_patterns.is_empty()
             && !self.git_state.global_allowlist_patterns.is_empty()
@@ -45,22 +201,99 @@ impl State {
             git_state.global_allowlist_patterns =
                 std::mem::take(&mut self.git_state.global_allowlist_patterns);
         }
+        // On resume we may be sitting on the task branch, so the freshly captured
+        // branch is not the one to return to
+        if self.git_state.task_branch.is_some() {
+            git_state.task_branch = self.git_state.task_branch.take();
+            git_state.original_branch = self.git_state.original_branch.take();
+        }
+        if self.git_state.base_commit.is_some() {
+            git_state.base_commit = self.git_state.base_commit.take();
+        }
+        // Files dirtied by the task's own earlier runs aren't the user's work, so keep
+        // what was dirty (and its content) when the task first started
+        if self.git_state.enabled {
+            git_state.pre_existing_dirty_files =
+                std::mem::take(&mut self.git_state.pre_existing_dirty_files);
+            git_state.pre_existing_dirty_hashes =
+                std::mem::take(&mut self.git_state.pre_existing_dirty_hashes);
+        }
         self.git_state = git_state;
+        self.settings_changed = true;
     }
 
-    /// Load state from a file
+    /// Load state from a file, replaying the journal written since it
     pub fn load(path: &Path) -> Result<Self> {
+        let modified = modified_time(path);
+        let mut state = Self::read(path, false)?;
+        state.mark_synced(modified);
+        Ok(state)
+    }
+
+    /// Load state only to look at it: an older format is migrated in memory without
+    /// backing up the files, and the result must not be saved
+    pub fn load_read_only(path: &Path) -> Result<Self> {
+        Self::read(path, true)
+    }
+
+    fn read(path: &Path, read_only: bool) -> Result<Self> {
         let content = fs::read_to_string(path)
             .with_context(|| format!("Failed to read state file: {}", path.display()))?;
-        let state: State = serde_json::from_str(&content)
+        let mut value: serde_json::Value = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse state file: {}", path.display()))?;
+
+        let journal = journal_path(path);
+        let generation = value
+            .get("journal_generation")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let replay = read_journal::<serde_json::Value>(&journal, generation, 0)?;
+        let (mut entries, offset) = match replay {
+            Some((entries, offset)) => (entries, Some(offset)),
+            None => (Vec::new(), None),
+        };
+
+        let version = migrate_state(&mut value, &mut entries, path)?;
+        if version < STATE_VERSION && !read_only {
+            backup(path, version)?;
+            backup(&journal, version)?;
+        }
+        let mut state: State = serde_json::from_value(value)
             .with_context(|| format!("Failed to parse state file: {}", path.display()))?;
+        state.schema_version = version;
+
+        for entry in entries {
+            match serde_json::from_value(entry) {
+                Ok(JournalEntry::File { path, state: file }) => {
+                    state.files.insert(path, *file);
+                }
+                Ok(JournalEntry::Usage { added }) => state.usage.add(&added),
+                Err(e) => warn!(
+                    path = %journal.display(),
+                    error = %e,
+                    "Skipping unreadable journal entry"
+                ),
+            }
+        }
+        if let Some(offset) = offset {
+            state.journal_offset = offset;
+        }
         Ok(state)
     }
 
-    /// Save state to a file atomically (write to temp, then rename)
+    /// Save changes since the last save by appending them to the journal; the state file
+    /// itself is rewritten when task settings change or the journal grows large.
+    /// Other runners may have saved since we last did, so their changes are merged in first.
     pub fn save(&mut self, path: &Path) -> Result<()> {
-        self.updated_at = Utc::now();
+        self.persist(path, false)
+    }
+
+    /// Save by rewriting the state file with the journal folded in
+    pub fn compact(&mut self, path: &Path) -> Result<()> {
+        self.persist(path, true)
+    }
 
+    fn persist(&mut self, path: &Path, compact: bool) -> Result<()> {
         // Ensure parent directory exists
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).with_context(|| {
@@ -68,6 +301,273 @@ impl State {
             })?;
         }
 
+        let _lock = lock_state(path)?;
+        self.merge_from_disk(path);
+        self.store(path, compact)
+    }
+
+    /// Claim leases on `paths` for this runner, skipping files another runner holds and
+    /// files that are already finished. Returns the files claimed.
+    pub fn claim_files(
+        &mut self,
+        path: &Path,
+        paths: &[PathBuf],
+        ttl_secs: u64,
+    ) -> Result<Vec<PathBuf>> {
+        let Some(runner) = self.runner_id.clone() else {
+            return Ok(paths.to_vec());
+        };
+
+        let _lock = lock_state(path)?;
+        self.merge_from_disk(path);
+
+        let mut claimed = Vec::new();
+        for file_path in paths {
+            let Some(file) = self.files.get_mut(file_path) else {
+                continue;
+            };
+            let finished = matches!(
+                file.status,
+                FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped
+            );
+            let leased = file.lease.as_ref().is_some_and(|l| {
+                l.blocks(Some(&runner)) && !self.dead_runners.contains(&l.owner)
+            });
+            if finished || leased {
+                continue;
+            }
+            file.lease = Some(Lease::new(&runner, ttl_secs));
+            self.changed.insert(file_path.clone());
+            claimed.push(file_path.clone());
+        }
+
+        self.store(path, false)?;
+        Ok(claimed)
+    }
+
+    /// Merge changes other runners saved since our last sync, without saving ours
+    pub fn refresh(&mut self, path: &Path) -> Result<()> {
+        let _lock = lock_state(path)?;
+        self.merge_from_disk(path);
+        Ok(())
+    }
+
+    /// Extend every lease this runner holds
+    pub fn renew_leases(&mut self, ttl_secs: u64) {
+        for (path, file) in self.files.iter_mut() {
+            if let Some(ref mut lease) = file.lease {
+                if Some(&lease.owner) == self.runner_id.as_ref() {
+                    lease.renew(ttl_secs);
+                    self.changed.insert(path.clone());
+                }
+            }
+        }
+    }
+
+    /// Drop this runner's lease on a file
+    pub fn release_lease(&mut self, path: &Path) {
+        if let Some(file) = self.files.get_mut(path) {
+            if file.lease.as_ref().map(|l| &l.owner) == self.runner_id.as_ref() {
+                file.lease = None;
+                self.changed.insert(path.to_path_buf());
+            }
+        }
+    }
+
+    /// Drop every lease this runner holds
+    pub fn release_leases(&mut self) {
+        let paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        for path in paths {
+            self.release_lease(&path);
+        }
+    }
+
+    /// Held by another runner that may still be working
+    pub fn leased_elsewhere(&self, lease: &Lease) -> bool {
+        lease.blocks(self.runner_id.as_deref()) && !self.dead_runners.contains(&lease.owner)
+    }
+
+    /// Merge changes other runners saved since our last sync: files we changed since then
+    /// keep our version unless another runner holds a live lease on them; the rest take
+    /// the saved version. Usage adds our new usage to theirs. Only new journal entries are
+    /// read unless the state file was rewritten since, and nothing is read if neither the
+    /// state file nor the journal changed.
+    fn merge_from_disk(&mut self, path: &Path) {
+        let modified = modified_time(path);
+        if modified.is_none() {
+            return;
+        }
+        if self.synced.on_disk && modified == self.synced.modified {
+            let journal_len = fs::metadata(journal_path(path)).map(|m| m.len()).unwrap_or(0);
+            if journal_len == self.journal_offset {
+                return;
+            }
+        }
+        if self.synced.on_disk {
+            match read_journal(&journal_path(path), self.journal_generation, self.journal_offset) {
+                Ok(Some((entries, offset))) => {
+                    for entry in entries {
+                        self.merge_entry(entry);
+                    }
+                    self.journal_offset = offset;
+                    return;
+                }
+                Ok(None) => {}
+                Err(e) => warn!(error = %e, path = %path.display(), "Failed to read journal, reading the whole state"),
+            }
+        }
+        match Self::read(path, false) {
+            Ok(disk) => self.merge(disk, modified),
+            Err(e) => {
+                warn!(error = %e, path = %path.display(), "Failed to read state for merging, overwriting it");
+            }
+        }
+    }
+
+    fn merge(&mut self, disk: State, modified: Option<SystemTime>) {
+        let meta = disk.meta();
+        for (path, theirs) in &disk.files {
+            if !self.keeps_ours(path, theirs) {
+                self.files.insert(path.clone(), theirs.clone());
+            }
+        }
+
+        let mut usage = disk.usage.clone();
+        usage.add(&self.usage.since(&self.synced.usage));
+        self.usage = usage;
+
+        self.synced = Synced {
+            files: disk.files,
+            usage: disk.usage,
+            meta,
+            on_disk: true,
+            modified,
+        };
+        self.journal_generation = disk.journal_generation;
+        self.journal_offset = disk.journal_offset;
+    }
+
+    fn merge_entry(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::File { path, state: theirs } => {
+                if !self.keeps_ours(&path, &theirs) {
+                    self.files.insert(path.clone(), (*theirs).clone());
+                }
+                self.synced.files.insert(path, *theirs);
+            }
+            JournalEntry::Usage { added } => {
+                self.usage.add(&added);
+                self.synced.usage.add(&added);
+            }
+        }
+    }
+
+    /// Our version of a file wins over a saved one if we changed it since our last sync
+    /// and no other runner is working on it
+    fn keeps_ours(&self, path: &Path, theirs: &FileState) -> bool {
+        self.files.get(path).is_some_and(|ours| {
+            let leased_elsewhere = theirs.lease.as_ref().is_some_and(|l| self.leased_elsewhere(l));
+            !leased_elsewhere && self.synced.files.get(path) != Some(ours)
+        })
+    }
+
+    /// Task-level fields, rewritten with the whole state file when they change
+    fn meta(&self) -> serde_json::Value {
+        serde_json::json!({
+            "config": self.config,
+            "started_at": self.started_at,
+            "git_state": self.git_state,
+            "budget_paused": self.budget_paused,
+        })
+    }
+
+    fn mark_synced(&mut self, modified: Option<SystemTime>) {
+        self.changed.clear();
+        self.settings_changed = false;
+        self.synced = Synced {
+            files: self.files.clone(),
+            usage: self.usage.clone(),
+            meta: self.meta(),
+            on_disk: true,
+            modified,
+        };
+    }
+
+    /// Write what changed since the last sync (the caller holds the state lock and has
+    /// merged other runners' changes)
+    fn store(&mut self, path: &Path, compact: bool) -> Result<()> {
+        if compact
+            || !path.exists()
+            || self.journal_offset > COMPACT_AFTER_BYTES
+            || self.schema_version < STATE_VERSION
+            || (self.settings_changed && self.meta() != self.synced.meta)
+        {
+            return self.write(path);
+        }
+        self.settings_changed = false;
+
+        let mut entries: Vec<JournalEntry> = self
+            .changed
+            .iter()
+            .filter_map(|path| {
+                let file = self.files.get(path)?;
+                (self.synced.files.get(path) != Some(file)).then(|| JournalEntry::File {
+                    path: path.clone(),
+                    state: Box::new(file.clone()),
+                })
+            })
+            .collect();
+        let added = self.usage.since(&self.synced.usage);
+        if !added.is_empty() {
+            entries.push(JournalEntry::Usage { added });
+        }
+        self.append(path, entries)?;
+        self.changed.clear();
+        self.synced.usage = self.usage.clone();
+        Ok(())
+    }
+
+    /// Append the current state of `files` to the journal, without looking for other
+    /// changes to save (for bookkeeping updated often, like the processes working on a file)
+    pub fn journal_files(&mut self, path: &Path, files: &[PathBuf]) -> Result<()> {
+        let _lock = lock_state(path)?;
+        self.merge_from_disk(path);
+        if !self.synced.on_disk || self.schema_version < STATE_VERSION {
+            return self.store(path, false);
+        }
+        let entries = files
+            .iter()
+            .filter_map(|file_path| {
+                self.files.get(file_path).map(|file| JournalEntry::File {
+                    path: file_path.clone(),
+                    state: Box::new(file.clone()),
+                })
+            })
+            .collect();
+        self.append(path, entries)
+    }
+
+    /// Append entries to the journal (the caller holds the state lock and has merged
+    /// other runners' changes)
+    fn append(&mut self, path: &Path, entries: Vec<JournalEntry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.journal_offset = append_journal(&journal_path(path), self.journal_generation, &entries)?;
+        for entry in entries {
+            if let JournalEntry::File { path, state } = entry {
+                self.synced.files.insert(path, *state);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite the state file atomically (write to temp, then rename) and start a new journal
+    fn write(&mut self, path: &Path) -> Result<()> {
+        self.updated_at = Utc::now();
+        self.schema_version = STATE_VERSION;
+        self.journal_generation += 1;
+
         let temp_path = path.with_extension("json.tmp");
         let content = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
 
@@ -77,27 +577,139 @@ impl State {
         fs::rename(&temp_path, path)
             .with_context(|| format!("Failed to rename state file to: {}", path.display()))?;
 
+        self.journal_offset = reset_journal(&journal_path(path), self.journal_generation)?;
+        self.mark_synced(modified_time(path));
         Ok(())
     }
 
     /// Load files from input JSON and merge with existing state
     /// New files are added as pending, existing files keep their status
     pub fn merge_input_file(&mut self, input_path: &Path) -> Result<()> {
-        let content = fs::read_to_string(input_path)
-            .with_context(|| format!("Failed to read input file: {}", input_path.display()))?;
-
-        let input: HashMap<PathBuf, serde_json::Value> = serde_json::from_str(&content)
-            .with_context(|| format!("Failed to parse input file: {}", input_path.display()))?;
-
-        for (path, original_data) in input {
-            self.files
-                .entry(path)
+        let input = read_input(input_path)?;
+        for (index, (path, original_data)) in input.into_iter().enumerate() {
+            let path = PathBuf::from(path);
+            let file_state = self
+                .files
+                .entry(path.clone())
                 .or_insert_with(|| FileState::new(original_data));
+            file_state.input_index.get_or_insert(index);
+            self.changed.insert(path);
         }
 
         Ok(())
     }
 
+    /// Bring the task in line with a changed input file and rewrite the state file.
+    /// New paths are added (or restored from the archive), paths no longer listed are
+    /// archived or removed, and changed metadata replaces `original_data`, optionally
+    /// sending the file back to Pending. Added files get global allowlist patterns if
+    /// the allowlist was already built.
+    pub fn sync_input(
+        &mut self,
+        path: &Path,
+        input_path: &Path,
+        removed: RemovedFilePolicy,
+        reset_changed: bool,
+        working_dir: &Path,
+    ) -> Result<SyncReport> {
+        let input = read_input(input_path)?;
+
+        let _lock = lock_state(path)?;
+        self.merge_from_disk(path);
+        let report = self.apply_input(input, removed, reset_changed);
+
+        // An allowlist not built yet is built for every selected file when the task runs
+        let allowlist_built = !self.git_state.global_allowlist_patterns.is_empty();
+        if self.git_state.tracking_enabled() && allowlist_built {
+            for added in &report.added {
+                self.git_state
+                    .allow_file(&self.config.allowlist_pattern, added, working_dir);
+            }
+        }
+        if report.is_empty() {
+            self.store(path, false)?;
+        } else {
+            // Removals can't be journaled
+            self.write(path)?;
+        }
+        Ok(report)
+    }
+
+    /// Apply an input file's entries to the task's files (see `sync_input`)
+    pub fn apply_input(
+        &mut self,
+        input: serde_json::Map<String, serde_json::Value>,
+        removed: RemovedFilePolicy,
+        reset_changed: bool,
+    ) -> SyncReport {
+        let mut report = SyncReport::default();
+        let mut listed = HashSet::with_capacity(input.len());
+
+        for (index, (file_path, original_data)) in input.into_iter().enumerate() {
+            let file_path = PathBuf::from(file_path);
+            listed.insert(file_path.clone());
+
+            let file = match self.files.get_mut(&file_path) {
+                Some(file) => file,
+                None => {
+                    let file = self
+                        .archived
+                        .remove(&file_path)
+                        .unwrap_or_else(|| FileState::new(original_data.clone()));
+                    report.added.push(file_path.clone());
+                    self.files.entry(file_path.clone()).or_insert(file)
+                }
+            };
+            file.input_index = Some(index);
+            self.changed.insert(file_path.clone());
+
+            if file.original_data != original_data {
+                file.original_data = original_data;
+                report.changed.push(file_path.clone());
+                if reset_changed && file.status != FileStatus::Pending {
+                    file.reset();
+                    report.reset.push(file_path);
+                }
+            }
+        }
+
+        let dropped: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|file_path| !listed.contains(*file_path))
+            .cloned()
+            .collect();
+        for file_path in dropped {
+            if let Some(file) = self.files.remove(&file_path) {
+                if removed == RemovedFilePolicy::Archive {
+                    self.archived.insert(file_path.clone(), file);
+                }
+                report.removed.push(file_path);
+            }
+        }
+
+        report.added.sort();
+        report.removed.sort();
+        report.changed.sort();
+        report.reset.sort();
+        report
+    }
+
+    /// A fresh state for a new task running `config` over the selected files, starting
+    /// from their original data
+    pub fn fork(&self, config: Config, selector: &FileSelector) -> State {
+        let mut forked = State::new(config);
+        for (path, file) in &self.files {
+            if !selector.matches(path, file) {
+                continue;
+            }
+            let mut fresh = FileState::new(file.original_data.clone());
+            fresh.input_index = file.input_index;
+            forked.files.insert(path.clone(), fresh);
+        }
+        forked
+    }
+
     /// Get files that need processing (pending or in-progress states)
     #[allow(dead_code)]
     pub fn get_pending_files(&self) -> Vec<PathBuf> {
@@ -125,16 +737,16 @@ impl State {
             .collect()
     }
 
-    /// Update status for a file
-    pub fn update_status(&mut self, path: &Path, status: FileStatus) {
-        if let Some(state) = self.files.get_mut(path) {
-            state.status = status;
+    /// Update status for a file, recording the change in its timeline
+    pub fn update_status(&mut self, path: &Path, status: FileStatus, worker: Option<usize>) {
+        if let Some(state) = self.file_mut(path) {
+            state.set_status(status, worker);
         }
     }
 
     /// Set result data for a file
     pub fn set_result(&mut self, path: &Path, result: ParsedResult) {
-        if let Some(state) = self.files.get_mut(path) {
+        if let Some(state) = self.file_mut(path) {
             state.result_data = Some(result.value);
             if result.is_raw {
                 state.result_data_raw = Some(true);
@@ -144,13 +756,46 @@ impl State {
         }
     }
 
+    /// Append a prompt/fixup attempt to a file's history
+    pub fn add_attempt(&mut self, path: &Path, record: AttemptRecord) {
+        if let Some(state) = self.file_mut(path) {
+            state.attempt_log.push(record);
+        }
+    }
+
     /// Increment attempts for a file
     pub fn increment_attempts(&mut self, path: &Path) {
-        if let Some(state) = self.files.get_mut(path) {
+        if let Some(state) = self.file_mut(path) {
             state.attempts += 1;
         }
     }
 
+    /// Reset a file so it is processed again from the prompt stage
+    pub fn reset_file(&mut self, path: &Path) {
+        if let Some(state) = self.file_mut(path) {
+            state.reset();
+        }
+    }
+
+    /// Update a file's progress in a pipeline stage
+    pub fn update_stage(&mut self, path: &Path, stage: &str, update: impl FnOnce(&mut StageState)) {
+        if let Some(state) = self.file_mut(path) {
+            update(state.stages.entry(stage.to_string()).or_default());
+        }
+    }
+
+    /// Record one agent call shared by `paths`, splitting its usage between them
+    pub fn record_usage(&mut self, paths: &[PathBuf], usage: &AgentUsage) {
+        self.usage.add_call(usage);
+        let share = usage.share(paths.len());
+        for path in paths {
+            if let Some(state) = self.file_mut(path) {
+                state.usage.add_call(&share);
+                state.usage.wall_secs += share.duration_secs;
+            }
+        }
+    }
+
     /// Get attempts for a file
     pub fn get_attempts(&self, path: &Path) -> u32 {
         self.files.get(path).map(|s| s.attempts).unwrap_or(0)
@@ -158,7 +803,10 @@ impl State {
 
     /// Set error message for a file
     pub fn set_error(&mut self, path: &Path, error: String) {
-        if let Some(state) = self.files.get_mut(path) {
+        if let Some(state) = self.file_mut(path) {
+            if let Some(event) = state.timeline.last_mut() {
+                event.outcome = error.lines().next().map(str::to_string);
+            }
             state.last_error = Some(error);
         }
     }
@@ -169,6 +817,44 @@ impl State {
         self.files.get(path).map(|s| s.original_data.clone())
     }
 
+    /// Build a commit message summarising the task (used when squashing on finalize)
+    pub fn summary_commit_message(&self, task_id: &str) -> String {
+        let summary = self.get_summary();
+        let mut completed: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(_, s)| s.status == FileStatus::Completed)
+            .map(|(p, _)| p.display().to_string())
+            .collect();
+        let mut failed: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(_, s)| s.status == FileStatus::Failed)
+            .map(|(p, _)| p.display().to_string())
+            .collect();
+        completed.sort();
+        failed.sort();
+
+        let prompt = self.config.prompt.lines().next().unwrap_or_default();
+        let mut message = format!(
+            "claude-loop: {} ({} completed, {} failed)\n\n{}\n",
+            task_id, summary.completed, summary.failed, prompt
+        );
+        if !completed.is_empty() {
+            message.push_str("\nCompleted:\n");
+            for path in &completed {
+                message.push_str(&format!("- {}\n", path));
+            }
+        }
+        if !failed.is_empty() {
+            message.push_str("\nFailed:\n");
+            for path in &failed {
+                message.push_str(&format!("- {}\n", path));
+            }
+        }
+        message
+    }
+
     /// Get summary counts
     pub fn get_summary(&self) -> StateSummary {
         let mut summary = StateSummary::default();
@@ -179,8 +865,11 @@ impl State {
                 FileStatus::AwaitingVerification => summary.awaiting_verification += 1,
                 FileStatus::VerifyInProgress => summary.verify_in_progress += 1,
                 FileStatus::FixupInProgress => summary.fixup_in_progress += 1,
+                FileStatus::InStage => summary.in_stage += 1,
                 FileStatus::Completed => summary.completed += 1,
                 FileStatus::Failed => summary.failed += 1,
+                FileStatus::Blocked => summary.blocked += 1,
+                FileStatus::Skipped => summary.skipped += 1,
             }
         }
         summary.total = self.files.len();
@@ -197,6 +886,348 @@ pub struct StateSummary {
     pub awaiting_verification: usize,
     pub verify_in_progress: usize,
     pub fixup_in_progress: usize,
+    pub in_stage: usize,
     pub completed: usize,
     pub failed: usize,
+    pub blocked: usize,
+    pub skipped: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    fn runner(path: &Path, id: &str) -> State {
+        let mut state = State::load(path).unwrap();
+        state.runner_id = Some(id.to_string());
+        state
+    }
+
+    fn usage() -> AgentUsage {
+        AgentUsage {
+            cost_usd: 0.5,
+            input_tokens: 100,
+            output_tokens: 10,
+            duration_secs: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_save_appends_to_journal_until_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let a = PathBuf::from("a.ts");
+        state.files.insert(a.clone(), FileState::new(serde_json::Value::Null));
+        state.save(&path).unwrap();
+        let snapshot = fs::read_to_string(&path).unwrap();
+
+        state.update_status(&a, FileStatus::Completed, None);
+        state.record_usage(std::slice::from_ref(&a), &usage());
+        state.save(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), snapshot);
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.files[&a].status, FileStatus::Completed);
+        assert_eq!(loaded.usage.agent_calls, 1);
+
+        // Changing task settings rewrites the state file
+        state.set_budget_paused(Some("cost".to_string()));
+        state.save(&path).unwrap();
+        let on_disk: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk.files[&a].status, FileStatus::Completed);
+        assert_eq!(on_disk.usage.agent_calls, 1);
+        assert_eq!(State::load(&path).unwrap().usage.agent_calls, 1);
+
+        state.config_mut().concurrency = 9;
+        state.save(&path).unwrap();
+        let on_disk: State = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk.config.concurrency, 9);
+    }
+
+    #[test]
+    fn test_journal_files_appends_only_the_given_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let (a, b) = (PathBuf::from("a.ts"), PathBuf::from("b.ts"));
+        for file in [&a, &b] {
+            state
+                .files
+                .insert(file.clone(), FileState::new(serde_json::Value::Null));
+        }
+        state.save(&path).unwrap();
+
+        state.update_status(&a, FileStatus::PromptInProgress, Some(0));
+        state.update_status(&b, FileStatus::PromptInProgress, Some(1));
+        state.journal_files(&path, std::slice::from_ref(&a)).unwrap();
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.files[&a].status, FileStatus::PromptInProgress);
+        assert_eq!(loaded.files[&b].status, FileStatus::Pending);
+
+        // The rest goes out with the next save
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path).unwrap().files[&b].status, FileStatus::PromptInProgress);
+    }
+
+    #[test]
+    fn test_sync_input_adds_archives_and_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let input = dir.path().join("in.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        fs::write(&input, r#"{"a.ts": {"n": 1}, "b.ts": {}, "c.ts": {}}"#).unwrap();
+        state.merge_input_file(&input).unwrap();
+        state.update_status(Path::new("a.ts"), FileStatus::Completed, None);
+        state.update_status(Path::new("c.ts"), FileStatus::Completed, None);
+        state.save(&path).unwrap();
+
+        fs::write(&input, r#"{"d.ts": {}, "a.ts": {"n": 2}, "b.ts": {}}"#).unwrap();
+        let report = state
+            .sync_input(&path, &input, RemovedFilePolicy::Archive, true, dir.path())
+            .unwrap();
+        assert_eq!(report.added, vec![PathBuf::from("d.ts")]);
+        assert_eq!(report.removed, vec![PathBuf::from("c.ts")]);
+        assert_eq!(report.changed, vec![PathBuf::from("a.ts")]);
+        assert_eq!(report.reset, vec![PathBuf::from("a.ts")]);
+
+        // Removals reach the state file, and the new input order is kept
+        let loaded = State::load(&path).unwrap();
+        assert!(!loaded.files.contains_key(Path::new("c.ts")));
+        assert_eq!(loaded.archived[Path::new("c.ts")].status, FileStatus::Completed);
+        let a = &loaded.files[Path::new("a.ts")];
+        assert_eq!(a.status, FileStatus::Pending);
+        assert_eq!(a.original_data, serde_json::json!({"n": 2}));
+        assert_eq!(a.input_index, Some(1));
+        assert_eq!(loaded.files[Path::new("d.ts")].input_index, Some(0));
+
+        // An archived file coming back keeps its progress
+        fs::write(&input, r#"{"a.ts": {"n": 2}, "b.ts": {}, "c.ts": {}}"#).unwrap();
+        let report = state
+            .sync_input(&path, &input, RemovedFilePolicy::Remove, false, dir.path())
+            .unwrap();
+        assert_eq!(report.added, vec![PathBuf::from("c.ts")]);
+        assert_eq!(report.removed, vec![PathBuf::from("d.ts")]);
+        assert_eq!(state.files[Path::new("c.ts")].status, FileStatus::Completed);
+        assert!(state.archived.is_empty());
+    }
+
+    #[test]
+    fn test_fork_selects_files_and_starts_them_fresh() {
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let config = Config::from_cli(&cli).unwrap();
+        let mut state = State::new(config.clone());
+        for (index, name) in ["src/a.ts", "src/b.ts", "lib/c.ts"].iter().enumerate() {
+            let mut file = FileState::new(serde_json::json!({"n": index}));
+            file.input_index = Some(index);
+            state.files.insert(PathBuf::from(name), file);
+        }
+        state.update_status(Path::new("src/a.ts"), FileStatus::Failed, None);
+        state.set_error(Path::new("src/a.ts"), "tsc: type error".to_string());
+        state.update_status(Path::new("lib/c.ts"), FileStatus::Failed, None);
+        state.set_error(Path::new("lib/c.ts"), "timed out".to_string());
+
+        let failed = FileSelector {
+            statuses: vec![FileStatus::Failed],
+            ..Default::default()
+        };
+        let mut reworded = config.clone();
+        reworded.prompt = "try harder".to_string();
+        let forked = state.fork(reworded, &failed);
+        assert_eq!(forked.config.prompt, "try harder");
+        assert_eq!(forked.files.len(), 2);
+        let a = &forked.files[Path::new("src/a.ts")];
+        assert_eq!(a.status, FileStatus::Pending);
+        assert_eq!(a.last_error, None);
+        assert_eq!(a.original_data, serde_json::json!({"n": 0}));
+        assert_eq!(forked.files[Path::new("lib/c.ts")].input_index, Some(2));
+
+        let selector = FileSelector {
+            statuses: vec![FileStatus::Failed],
+            globs: vec![glob::Pattern::new("src/**").unwrap()],
+            error: Some("type error".to_string()),
+        };
+        let forked = state.fork(config.clone(), &selector);
+        assert_eq!(forked.files.keys().collect::<Vec<_>>(), vec![Path::new("src/a.ts")]);
+        let selector = FileSelector {
+            error: Some("type error".to_string()),
+            globs: vec![glob::Pattern::new("lib/*").unwrap()],
+            ..Default::default()
+        };
+        assert!(state.fork(config, &selector).files.is_empty());
+    }
+
+    #[test]
+    fn test_load_migrates_unversioned_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let a = PathBuf::from("a.ts");
+        state.files.insert(a.clone(), FileState::new(serde_json::Value::Null));
+        state.save(&path).unwrap();
+        state.update_status(&a, FileStatus::Completed, None);
+        state.save(&path).unwrap();
+
+        // Written by a runner from before schema versions
+        let mut old: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        old.as_object_mut().unwrap().remove("schema_version");
+        let old = serde_json::to_string_pretty(&old).unwrap();
+        fs::write(&path, &old).unwrap();
+
+        // Looking at it leaves the files alone
+        let peeked = State::load_read_only(&path).unwrap();
+        assert_eq!(peeked.files[&a].status, FileStatus::Completed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), old);
+        assert!(!dir.path().join("state.json.v0.bak").exists());
+
+        let mut loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.files[&a].status, FileStatus::Completed);
+        assert_eq!(fs::read_to_string(dir.path().join("state.json.v0.bak")).unwrap(), old);
+        assert!(dir.path().join("state.journal.v0.bak").exists());
+
+        // The next save writes the current format instead of journaling against the old one
+        loaded.save(&path).unwrap();
+        let on_disk: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk["schema_version"], STATE_VERSION);
+        assert_eq!(on_disk["files"]["a.ts"]["status"], "completed");
+    }
+
+    #[test]
+    fn test_runners_share_a_task() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        for file in ["a.ts", "b.ts", "c.ts"] {
+            state
+                .files
+                .insert(PathBuf::from(file), FileState::new(serde_json::Value::Null));
+        }
+        state.save(&path).unwrap();
+
+        let (a, b, c) = (
+            PathBuf::from("a.ts"),
+            PathBuf::from("b.ts"),
+            PathBuf::from("c.ts"),
+        );
+        let mut first = runner(&path, "first");
+        let mut second = runner(&path, "second");
+        assert_eq!(
+            first
+                .claim_files(&path, std::slice::from_ref(&a), 60)
+                .unwrap(),
+            vec![a.clone()]
+        );
+        assert_eq!(
+            second
+                .claim_files(&path, &[a.clone(), b.clone()], 60)
+                .unwrap(),
+            vec![b.clone()]
+        );
+
+        // Each runner's work survives the other's saves
+        first.update_status(&a, FileStatus::Completed, None);
+        first.record_usage(std::slice::from_ref(&a), &usage());
+        first.release_lease(&a);
+        first.save(&path).unwrap();
+        second.update_status(&b, FileStatus::Completed, None);
+        second.record_usage(std::slice::from_ref(&b), &usage());
+        second.release_lease(&b);
+        second.save(&path).unwrap();
+
+        let merged = State::load(&path).unwrap();
+        assert_eq!(merged.files[&a].status, FileStatus::Completed);
+        assert_eq!(merged.files[&b].status, FileStatus::Completed);
+        assert!(merged.files[&a].lease.is_none());
+        assert_eq!(merged.usage.agent_calls, 2);
+        assert_eq!(merged.usage.input_tokens, 200);
+
+        // A finished file can't be claimed again, an expired lease can be taken over
+        first
+            .claim_files(&path, std::slice::from_ref(&c), 60)
+            .unwrap();
+        first
+            .file_mut(&c)
+            .unwrap()
+            .lease
+            .as_mut()
+            .unwrap()
+            .expires_at = Utc::now();
+        first.save(&path).unwrap();
+        assert_eq!(
+            second
+                .claim_files(&path, &[a.clone(), c.clone()], 60)
+                .unwrap(),
+            vec![c.clone()]
+        );
+        assert_eq!(second.files[&c].lease.as_ref().unwrap().owner, "second");
+    }
+
+    #[test]
+    fn test_refresh_picks_up_journaled_and_rewritten_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let (a, b) = (PathBuf::from("a.ts"), PathBuf::from("b.ts"));
+        for file in [&a, &b] {
+            state
+                .files
+                .insert(file.clone(), FileState::new(serde_json::Value::Null));
+        }
+        state.save(&path).unwrap();
+
+        let mut first = runner(&path, "first");
+        let mut second = runner(&path, "second");
+
+        // Nothing changed on disk: nothing to merge
+        second.refresh(&path).unwrap();
+        assert_eq!(second.files[&a].status, FileStatus::Pending);
+
+        first.update_status(&a, FileStatus::Completed, None);
+        first.save(&path).unwrap();
+        second.refresh(&path).unwrap();
+        assert_eq!(second.files[&a].status, FileStatus::Completed);
+
+        // A rewrite of the state file is read even if the journal is the same length
+        first.update_status(&b, FileStatus::Failed, None);
+        first.compact(&path).unwrap();
+        second.refresh(&path).unwrap();
+        assert_eq!(second.files[&b].status, FileStatus::Failed);
+    }
+
+    #[test]
+    fn test_set_git_state_keeps_the_first_runs_dirty_files() {
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let captured = |files: &[(&str, &str)]| GitState {
+            enabled: true,
+            pre_existing_dirty_files: files.iter().map(|(f, _)| PathBuf::from(f)).collect(),
+            pre_existing_dirty_hashes: files
+                .iter()
+                .map(|(f, hash)| (PathBuf::from(f), Some(hash.to_string())))
+                .collect(),
+            ..Default::default()
+        };
+
+        state.set_git_state(captured(&[("notes.md", "aaa")]));
+        // On resume, notes.md has the task's edits and a.ts was edited by an agent
+        state.set_git_state(captured(&[("notes.md", "bbb"), ("a.ts", "ccc")]));
+        assert_eq!(
+            state.git_state.pre_existing_dirty_files,
+            HashSet::from([PathBuf::from("notes.md")])
+        );
+        assert_eq!(
+            state.git_state.pre_existing_dirty_hashes,
+            HashMap::from([(PathBuf::from("notes.md"), Some("aaa".to_string()))])
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use crate::migrate::{backup, migrate_task_list, TASK_LIST_VERSION};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -17,11 +18,29 @@ pub struct TaskEntry {
     /// Whether this task is complete
     #[serde(default)]
     pub completed: bool,
+    /// Whether the last run stopped because the task's budget ran out
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused_for_budget: bool,
+    /// Task this one was cloned from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloned_from: Option<String>,
+}
+
+/// Short task description from a prompt
+pub fn describe_prompt(prompt: &str) -> String {
+    if prompt.chars().count() > 50 {
+        format!("{}...", prompt.chars().take(47).collect::<String>())
+    } else {
+        prompt.to_string()
+    }
 }
 
 /// Task list tracking multiple independent task runs
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskList {
+    /// Format of the task list file (older files are migrated as they are loaded)
+    #[serde(default)]
+    schema_version: u32,
     /// Map of task ID to task entry
     pub tasks: HashMap<String, TaskEntry>,
     /// Counter for generating unique task IDs
@@ -29,6 +48,16 @@ pub struct TaskList {
     next_id: u32,
 }
 
+impl Default for TaskList {
+    fn default() -> Self {
+        Self {
+            schema_version: TASK_LIST_VERSION,
+            tasks: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
 impl TaskList {
     /// Load task list from file, or create empty if doesn't exist
     pub fn load_or_create(tasks_dir: &Path) -> Result<Self> {
@@ -38,7 +67,15 @@ impl TaskList {
             let content = fs::read_to_string(&task_list_path).with_context(|| {
                 format!("Failed to read task list: {}", task_list_path.display())
             })?;
-            let list: TaskList = serde_json::from_str(&content).with_context(|| {
+            let mut value: serde_json::Value =
+                serde_json::from_str(&content).with_context(|| {
+                    format!("Failed to parse task list: {}", task_list_path.display())
+                })?;
+            let version = migrate_task_list(&mut value, &task_list_path)?;
+            if version < TASK_LIST_VERSION {
+                backup(&task_list_path, version)?;
+            }
+            let list: TaskList = serde_json::from_value(value).with_context(|| {
                 format!("Failed to parse task list: {}", task_list_path.display())
             })?;
             Ok(list)
@@ -89,12 +126,24 @@ impl TaskList {
                 working_dir,
                 description,
                 completed: false,
+                paused_for_budget: false,
+                cloned_from: None,
             },
         );
 
         task_id
     }
 
+    /// Create a task in the same working directory as `source`, and return its ID
+    pub fn create_clone(&mut self, source: &str, description: Option<String>) -> Option<String> {
+        let working_dir = self.get_task(source)?.working_dir.clone();
+        let task_id = self.create_task(working_dir, description);
+        if let Some(entry) = self.tasks.get_mut(&task_id) {
+            entry.cloned_from = Some(source.to_string());
+        }
+        Some(task_id)
+    }
+
     /// Get a task by ID
     pub fn get_task(&self, task_id: &str) -> Option<&TaskEntry> {
         self.tasks.get(task_id)
@@ -107,6 +156,20 @@ impl TaskList {
         }
     }
 
+    /// Mark a task as having work left (files were added or reset)
+    pub fn mark_incomplete(&mut self, task_id: &str) {
+        if let Some(entry) = self.tasks.get_mut(task_id) {
+            entry.completed = false;
+        }
+    }
+
+    /// Record whether a task stopped because its budget ran out
+    pub fn set_paused_for_budget(&mut self, task_id: &str, paused: bool) {
+        if let Some(entry) = self.tasks.get_mut(task_id) {
+            entry.paused_for_budget = paused;
+        }
+    }
+
     /// Get all incomplete tasks
     pub fn get_incomplete_tasks(&self) -> Vec<(&String, &TaskEntry)> {
         self.tasks
@@ -0,0 +1,214 @@
+//! Phase durations and throughput derived from each file's timeline of status changes
+
+use crate::state::State;
+use crate::types::{FileStatus, TimelineEvent};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Statuses a file passes through on its way to a final status, in pipeline order
+const PHASES: [FileStatus; 6] = [
+    FileStatus::Pending,
+    FileStatus::PromptInProgress,
+    FileStatus::AwaitingVerification,
+    FileStatus::VerifyInProgress,
+    FileStatus::FixupInProgress,
+    FileStatus::InStage,
+];
+
+/// Time a file spent in one status
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phase {
+    pub status: FileStatus,
+    pub started: DateTime<Utc>,
+    pub duration: TimeDelta,
+    pub worker: Option<usize>,
+    pub attempt: u32,
+}
+
+/// Phases that have ended (the file has since moved to another status)
+pub fn phases(timeline: &[TimelineEvent]) -> Vec<Phase> {
+    timeline
+        .windows(2)
+        .map(|pair| Phase {
+            status: pair[0].status.clone(),
+            started: pair[0].at,
+            duration: pair[1].at - pair[0].at,
+            worker: pair[0].worker,
+            attempt: pair[0].attempt,
+        })
+        .collect()
+}
+
+/// Time spent on a file once it left the queue, over the phases that have ended
+pub fn busy_time(timeline: &[TimelineEvent]) -> TimeDelta {
+    phases(timeline)
+        .iter()
+        .filter(|phase| phase.status != FileStatus::Pending)
+        .map(|phase| phase.duration)
+        .sum()
+}
+
+/// Ended phases of one status across all files
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseStats {
+    pub count: usize,
+    pub total: TimeDelta,
+}
+
+impl PhaseStats {
+    pub fn average(&self) -> TimeDelta {
+        self.total / self.count.max(1) as i32
+    }
+}
+
+/// Count and duration of each phase across the task, in pipeline order (unseen phases left out)
+pub fn phase_stats(state: &State) -> Vec<(FileStatus, PhaseStats)> {
+    let mut stats = [PhaseStats::default(); PHASES.len()];
+    for file in state.files.values() {
+        for phase in phases(&file.timeline) {
+            if let Some(i) = PHASES.iter().position(|s| *s == phase.status) {
+                stats[i].count += 1;
+                stats[i].total += phase.duration;
+            }
+        }
+    }
+    PHASES
+        .into_iter()
+        .zip(stats)
+        .filter(|(_, stats)| stats.count > 0)
+        .collect()
+}
+
+/// Files that took longest once out of the queue, slowest first
+pub fn slowest_files(state: &State, limit: usize) -> Vec<(PathBuf, TimeDelta)> {
+    let mut files: Vec<_> = state
+        .files
+        .iter()
+        .map(|(path, file)| (path.clone(), busy_time(&file.timeline)))
+        .filter(|(_, time)| *time > TimeDelta::zero())
+        .collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    files.truncate(limit);
+    files
+}
+
+/// Files finished in one hour
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throughput {
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// Files completed and failed per hour, oldest hour first
+pub fn hourly_throughput(state: &State) -> BTreeMap<DateTime<Utc>, Throughput> {
+    let mut hours: BTreeMap<DateTime<Utc>, Throughput> = BTreeMap::new();
+    for event in state.files.values().flat_map(|file| &file.timeline) {
+        let Ok(hour) = event.at.duration_trunc(TimeDelta::hours(1)) else {
+            continue;
+        };
+        match event.status {
+            FileStatus::Completed => hours.entry(hour).or_default().completed += 1,
+            FileStatus::Failed => hours.entry(hour).or_default().failed += 1,
+            _ => {}
+        }
+    }
+    hours
+}
+
+/// Short human-readable duration ("850ms", "12.3s", "4m05s", "2h10m")
+pub fn format_duration(duration: TimeDelta) -> String {
+    let ms = duration.num_milliseconds().max(0);
+    let secs = ms / 1000;
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else if secs < 60 {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::config::Config;
+    use crate::types::FileState;
+    use chrono::TimeZone;
+    use clap::Parser;
+
+    fn event(status: FileStatus, secs: i64) -> TimelineEvent {
+        TimelineEvent {
+            status,
+            at: Utc.with_ymd_and_hms(2026, 1, 1, 10, 0, 0).unwrap() + TimeDelta::seconds(secs),
+            worker: Some(0),
+            attempt: 0,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn test_phase_durations_from_timeline() {
+        let cli = Cli::parse_from(["claude-loop-runner", "-i", "in.json", "-p", "do it"]);
+        let mut state = State::new(Config::from_cli(&cli).unwrap());
+        let mut slow = FileState::new(serde_json::Value::Null);
+        slow.timeline = vec![
+            event(FileStatus::PromptInProgress, 0),
+            event(FileStatus::AwaitingVerification, 60),
+            event(FileStatus::VerifyInProgress, 70),
+            event(FileStatus::FixupInProgress, 80),
+            event(FileStatus::VerifyInProgress, 140),
+            event(FileStatus::Completed, 150),
+        ];
+        let mut fast = FileState::new(serde_json::Value::Null);
+        fast.timeline = vec![
+            event(FileStatus::Pending, 0),
+            event(FileStatus::PromptInProgress, 3600),
+            event(FileStatus::AwaitingVerification, 3620),
+            event(FileStatus::VerifyInProgress, 3620),
+            event(FileStatus::Failed, 3630),
+        ];
+        state.files.insert(PathBuf::from("slow.ts"), slow);
+        state.files.insert(PathBuf::from("fast.ts"), fast);
+
+        // Time in the queue doesn't count towards a file's own time
+        let slowest = slowest_files(&state, 10);
+        assert_eq!(slowest[0], (PathBuf::from("slow.ts"), TimeDelta::seconds(150)));
+        assert_eq!(slowest[1], (PathBuf::from("fast.ts"), TimeDelta::seconds(30)));
+        assert_eq!(slowest_files(&state, 1).len(), 1);
+
+        let stats = phase_stats(&state);
+        let statuses: Vec<_> = stats.iter().map(|(s, _)| s.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                FileStatus::Pending,
+                FileStatus::PromptInProgress,
+                FileStatus::AwaitingVerification,
+                FileStatus::VerifyInProgress,
+                FileStatus::FixupInProgress,
+            ]
+        );
+        let verify = stats[3].1;
+        assert_eq!(verify.count, 3);
+        assert_eq!(verify.average(), TimeDelta::seconds(10));
+        assert_eq!(stats[1].1.average(), TimeDelta::seconds(40));
+
+        let hours: Vec<_> = hourly_throughput(&state).into_values().collect();
+        assert_eq!(
+            hours,
+            vec![
+                Throughput { completed: 1, failed: 0 },
+                Throughput { completed: 0, failed: 1 },
+            ]
+        );
+
+        assert_eq!(format_duration(TimeDelta::milliseconds(850)), "850ms");
+        assert_eq!(format_duration(TimeDelta::milliseconds(12_340)), "12.3s");
+        assert_eq!(format_duration(TimeDelta::seconds(245)), "4m05s");
+        assert_eq!(format_duration(TimeDelta::seconds(7800)), "2h10m");
+    }
+}
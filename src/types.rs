@@ -1,9 +1,13 @@
+use crate::lease::Lease;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 
 /// Status of a file in the processing pipeline
-#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
 #[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
 pub enum FileStatus {
     #[default]
     /// Not yet started
@@ -16,19 +20,203 @@ pub enum FileStatus {
     VerifyInProgress,
     /// Verification failed, fixup in progress
     FixupInProgress,
+    /// Moving through a user-defined pipeline (see `FileState::stages`)
+    InStage,
     /// Successfully completed
     Completed,
     /// Failed after max retries
     Failed,
+    /// Not attempted because a dependency failed or is part of a cycle
+    Blocked,
+    /// Skipped by the operator
+    Skipped,
+}
+
+impl FileStatus {
+    /// Name of the status as it appears in state files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Pending => "pending",
+            FileStatus::PromptInProgress => "prompt_in_progress",
+            FileStatus::AwaitingVerification => "awaiting_verification",
+            FileStatus::VerifyInProgress => "verify_in_progress",
+            FileStatus::FixupInProgress => "fixup_in_progress",
+            FileStatus::InStage => "in_stage",
+            FileStatus::Completed => "completed",
+            FileStatus::Failed => "failed",
+            FileStatus::Blocked => "blocked",
+            FileStatus::Skipped => "skipped",
+        }
+    }
+
+    /// A worker is (or was, if its runner died) working on the file
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self,
+            FileStatus::PromptInProgress
+                | FileStatus::VerifyInProgress
+                | FileStatus::FixupInProgress
+                | FileStatus::InStage
+        )
+    }
+
+    /// Nothing more will be done with the file in this run
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped | FileStatus::Blocked
+        )
+    }
+}
+
+/// Line counts for a diff
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffStat {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl DiffStat {
+    pub fn add(&mut self, other: &DiffStat) {
+        self.files_changed += other.files_changed;
+        self.insertions += other.insertions;
+        self.deletions += other.deletions;
+    }
+}
+
+/// Which kind of agent run produced an attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptKind {
+    Prompt,
+    Fixup,
+    /// Reviewer run after verification passed (makes no edits)
+    Review,
+}
+
+impl AttemptKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttemptKind::Prompt => "prompt",
+            AttemptKind::Fixup => "fixup",
+            AttemptKind::Review => "review",
+        }
+    }
+}
+
+/// Record of a single agent run against a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttemptRecord {
+    /// 1-based attempt number for this file
+    pub number: u32,
+    pub kind: AttemptKind,
+    /// When the agent run finished
+    pub finished_at: DateTime<Utc>,
+    /// Changes made to the file's scope by this attempt
+    pub diffstat: DiffStat,
+    /// Paths in the scope this attempt changed, relative to the working directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed_files: Vec<PathBuf>,
+    /// Diff file, relative to the task's artifacts directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_file: Option<PathBuf>,
+    /// Prompt and agent output, relative to the task's artifacts directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcript_file: Option<PathBuf>,
+}
+
+/// Status of a file in one pipeline stage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    #[default]
+    Pending,
+    Running,
+    Passed,
+    Failed,
+}
+
+impl StageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StageStatus::Pending => "pending",
+            StageStatus::Running => "running",
+            StageStatus::Passed => "passed",
+            StageStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Progress of a file through one pipeline stage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageState {
+    pub status: StageStatus,
+    /// Runs that missed the stage's pass criteria
+    #[serde(default)]
+    pub attempts: u32,
+    /// Why the last run didn't pass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// A process identified by PID and start time (so a reused PID isn't mistaken for it)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessId {
+    pub pid: u32,
+    /// Start time in seconds since the epoch
+    pub started: u64,
+}
+
+/// Runner and child process working on a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessRecord {
+    pub host: String,
+    pub runner: ProcessId,
+    /// The agent or verification command (their own children are found through it)
+    pub children: Vec<ProcessId>,
+}
+
+/// What a completed file's result depended on, so a later run can tell it would redo
+/// the same work
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// SHA-256 of the file's content when it completed
+    pub source: String,
+    /// SHA-256 over the related test/snapshot files' paths and content
+    pub related: String,
+    /// SHA-256 over the prompts, commands and pipeline, and the file's input metadata
+    pub prompt: String,
+    /// `claude --version` of the agent that did the work
+    pub agent: String,
+}
+
+/// A status change in a file's timeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    pub status: FileStatus,
+    pub at: DateTime<Utc>,
+    /// Worker (numbered within its pool) that made the change; None for the runner itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker: Option<usize>,
+    /// Verification attempts made so far
+    #[serde(default)]
+    pub attempt: u32,
+    /// Error that caused the change, for failures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
 }
 
 /// State of a single file being processed
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileState {
     /// Current status in the pipeline
     pub status: FileStatus,
     /// Original metadata from input JSON
     pub original_data: serde_json::Value,
+    /// Position of the file in the input JSON (used for input ordering)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_index: Option<usize>,
     /// Result data from Claude (parsed JSON or raw string)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub result_data: Option<serde_json::Value>,
@@ -41,6 +229,30 @@ pub struct FileState {
     /// Last error message if failed
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_error: Option<String>,
+    /// Every prompt/fixup run with the diff it produced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempt_log: Vec<AttemptRecord>,
+    /// Claude usage spent on this file
+    #[serde(default, skip_serializing_if = "UsageTotals::is_empty")]
+    pub usage: UsageTotals,
+    /// Progress through each pipeline stage, keyed by stage name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stages: BTreeMap<String, StageState>,
+    /// Runner currently working on the file, when several runners share the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<Lease>,
+    /// Agent or verification process working on the file, so a run after a crash can find it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<ProcessRecord>,
+    /// Every status change, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timeline: Vec<TimelineEvent>,
+    /// Recorded on completion, for incremental runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Task whose result was reused because the file hadn't changed since it ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_from: Option<String>,
 }
 
 impl FileState {
@@ -48,11 +260,60 @@ impl FileState {
         Self {
             status: FileStatus::Pending,
             original_data,
+            input_index: None,
             result_data: None,
             result_data_raw: None,
             attempts: 0,
             last_error: None,
+            attempt_log: Vec::new(),
+            usage: UsageTotals::default(),
+            stages: BTreeMap::new(),
+            lease: None,
+            process: None,
+            timeline: Vec::new(),
+            fingerprint: None,
+            cached_from: None,
+        }
+    }
+
+    /// Change status, recording the change in the timeline
+    pub fn set_status(&mut self, status: FileStatus, worker: Option<usize>) {
+        if self.status != status {
+            self.timeline.push(TimelineEvent {
+                status: status.clone(),
+                at: Utc::now(),
+                worker,
+                attempt: self.attempts,
+                outcome: None,
+            });
+        }
+        self.status = status;
+    }
+
+    /// Send the file back to Pending to be processed again from the prompt stage
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.set_status(FileStatus::Pending, None);
+        self.last_error = None;
+        self.stages.clear();
+    }
+
+    /// Combined diffstat across all recorded attempts. A path changed by several
+    /// attempts counts once; attempts recorded without their paths count as their
+    /// largest single files_changed.
+    pub fn total_diffstat(&self) -> DiffStat {
+        let mut total = DiffStat::default();
+        let mut paths = BTreeSet::new();
+        let mut unlisted = 0;
+        for attempt in &self.attempt_log {
+            total.add(&attempt.diffstat);
+            if attempt.changed_files.is_empty() {
+                unlisted = unlisted.max(attempt.diffstat.files_changed);
+            }
+            paths.extend(attempt.changed_files.iter());
         }
+        total.files_changed = paths.len().max(unlisted);
+        total
     }
 }
 
@@ -71,6 +332,96 @@ pub struct ProcessOutput {
     pub stdout: String,
     pub stderr: String,
     pub exit_code: i32,
+    /// Cost, tokens and duration of a Claude run (None for plain commands)
+    pub agent_usage: Option<AgentUsage>,
+    /// Claude reported the run as failed (`is_error` in its JSON output)
+    pub is_error: bool,
+}
+
+/// Cost and token usage reported by a single Claude run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AgentUsage {
+    pub cost_usd: f64,
+    /// Input tokens, including cache reads and writes
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub duration_secs: f64,
+}
+
+impl AgentUsage {
+    /// Split usage evenly between the files of a batch
+    pub fn share(&self, files: usize) -> AgentUsage {
+        let n = files.max(1);
+        AgentUsage {
+            cost_usd: self.cost_usd / n as f64,
+            input_tokens: self.input_tokens / n as u64,
+            output_tokens: self.output_tokens / n as u64,
+            duration_secs: self.duration_secs,
+        }
+    }
+}
+
+/// Accumulated usage, for the whole task or a single file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageTotals {
+    #[serde(default)]
+    pub cost_usd: f64,
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub agent_calls: u32,
+    /// Wall-clock seconds: run time for a task, time spent in Claude for a file
+    #[serde(default)]
+    pub wall_secs: f64,
+}
+
+impl UsageTotals {
+    pub fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == UsageTotals::default()
+    }
+
+    /// Add one agent call
+    pub fn add_call(&mut self, usage: &AgentUsage) {
+        self.cost_usd += usage.cost_usd;
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+        self.agent_calls += 1;
+    }
+
+    /// Add another set of totals
+    pub fn add(&mut self, other: &UsageTotals) {
+        self.cost_usd += other.cost_usd;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.agent_calls += other.agent_calls;
+        self.wall_secs += other.wall_secs;
+    }
+
+    /// Usage added since `base` was taken
+    pub fn since(&self, base: &UsageTotals) -> UsageTotals {
+        UsageTotals {
+            cost_usd: (self.cost_usd - base.cost_usd).max(0.0),
+            input_tokens: self.input_tokens.saturating_sub(base.input_tokens),
+            output_tokens: self.output_tokens.saturating_sub(base.output_tokens),
+            agent_calls: self.agent_calls.saturating_sub(base.agent_calls),
+            wall_secs: (self.wall_secs - base.wall_secs).max(0.0),
+        }
+    }
+}
+
+/// A reviewer's decision on a file's changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewVerdict {
+    pub approved: bool,
+    /// Why the changes were rejected (or notes on an approval)
+    #[serde(default)]
+    pub reasons: Vec<String>,
 }
 
 /// Parsed result from Claude's output
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// A named group of verifications limited to `limit` at a time (1 makes it a mutex)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyGroup {
+    pub name: String,
+    pub limit: usize,
+    /// Globs for files that belong to the group (files can also name it in `verify_group`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<String>,
+}
+
+/// Parses `NAME=LIMIT[:PATTERN,PATTERN...]`, e.g. `tsc=1:src/**/*.ts`
+impl FromStr for VerifyGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=LIMIT[:PATTERNS], got '{}'", s))?;
+        let (limit, patterns) = match rest.split_once(':') {
+            Some((limit, patterns)) => (limit, patterns),
+            None => (rest, ""),
+        };
+
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("verify group name is empty".to_string());
+        }
+        let limit: usize = limit
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid limit '{}' for verify group '{}'", limit, name))?;
+        if limit == 0 {
+            return Err(format!("verify group '{}' needs a limit of at least 1", name));
+        }
+        let patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                glob::Pattern::new(p)
+                    .map(|_| p.to_string())
+                    .map_err(|e| format!("invalid pattern '{}': {}", p, e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            name: name.to_string(),
+            limit,
+            patterns,
+        })
+    }
+}
+
+/// Semaphores for the configured verify groups
+pub struct VerifyGroups {
+    patterns: Vec<(glob::Pattern, String)>,
+    limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl VerifyGroups {
+    pub fn new(groups: &[VerifyGroup]) -> Self {
+        let patterns = groups
+            .iter()
+            .flat_map(|g| {
+                g.patterns
+                    .iter()
+                    .filter_map(|p| glob::Pattern::new(p).ok())
+                    .map(|p| (p, g.name.clone()))
+            })
+            .collect();
+        let limits = groups
+            .iter()
+            .map(|g| (g.name.clone(), Arc::new(Semaphore::new(g.limit.max(1)))))
+            .collect();
+        Self {
+            patterns,
+            limits: Mutex::new(limits),
+        }
+    }
+
+    /// The group a file's verification runs in: `verify_group` in its input data,
+    /// otherwise the first group with a matching pattern
+    pub fn group_for(&self, path: &Path, original_data: &serde_json::Value) -> Option<String> {
+        if let Some(name) = original_data.get("verify_group").and_then(|v| v.as_str()) {
+            return Some(name.to_string());
+        }
+        self.patterns
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(path))
+            .map(|(_, name)| name.clone())
+    }
+
+    fn semaphore(&self, name: &str) -> Arc<Semaphore> {
+        let mut limits = self.limits.lock().unwrap();
+        Arc::clone(limits.entry(name.to_string()).or_insert_with(|| {
+            warn!(group = %name, "Verify group not configured, running it one at a time");
+            Arc::new(Semaphore::new(1))
+        }))
+    }
+
+    /// Take a slot in the group if one is free
+    pub fn try_acquire(&self, name: &str) -> Option<OwnedSemaphorePermit> {
+        self.semaphore(name).try_acquire_owned().ok()
+    }
+
+    /// Wait for a slot in the group
+    pub async fn acquire(&self, name: &str) -> OwnedSemaphorePermit {
+        self.semaphore(name)
+            .acquire_owned()
+            .await
+            .expect("verify group semaphores are never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_verify_group() {
+        let group: VerifyGroup = "tsc=1:src/**/*.ts, lib/*.ts".parse().unwrap();
+        assert_eq!(
+            group,
+            VerifyGroup {
+                name: "tsc".to_string(),
+                limit: 1,
+                patterns: vec!["src/**/*.ts".to_string(), "lib/*.ts".to_string()],
+            }
+        );
+        assert_eq!("db=2".parse::<VerifyGroup>().unwrap().patterns, Vec::<String>::new());
+        assert!("db".parse::<VerifyGroup>().is_err());
+        assert!("db=0".parse::<VerifyGroup>().is_err());
+        assert!("=1".parse::<VerifyGroup>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_resolution_and_limits() {
+        let groups = VerifyGroups::new(&[
+            "integration=2:tests/integration/*".parse().unwrap(),
+            "tsc=1".parse().unwrap(),
+        ]);
+
+        let path = Path::new("tests/integration/users.test.ts");
+        assert_eq!(groups.group_for(path, &json!({})).as_deref(), Some("integration"));
+        assert_eq!(
+            groups.group_for(path, &json!({"verify_group": "tsc"})).as_deref(),
+            Some("tsc")
+        );
+        assert_eq!(groups.group_for(Path::new("src/a.ts"), &json!({})), None);
+
+        let first = groups.try_acquire("tsc").unwrap();
+        assert!(groups.try_acquire("tsc").is_none());
+        drop(first);
+        assert!(groups.try_acquire("tsc").is_some());
+
+        let _a = groups.acquire("integration").await;
+        let _b = groups.acquire("integration").await;
+        assert!(groups.try_acquire("integration").is_none());
+
+        // Groups named only in input data act as a mutex
+        let _held = groups.try_acquire("unknown").unwrap();
+        assert!(groups.try_acquire("unknown").is_none());
+    }
+}